@@ -0,0 +1,51 @@
+//! Frames per second of the core with no frontend, `cargo bench --bench fps`. Each ROM is run for
+//! a few seconds of emulated time and the best of a few runs is reported, so the numbers can be
+//! compared between builds on the same machine. CPU cycles per second are shown too, which is
+//! the number to watch for changes to instruction decoding
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use nes::emulator::Emulator;
+use nes::ines_parser::NESFile;
+
+const ROMS: [&str; 4] = [
+    "tests/instr_test-v5/all_instrs.nes",
+    "tests/spritecans-2011/spritecans.nes",
+    "tests/scrolltest/scroll.nes",
+    "tests/holy-mapperel/M1_P128K_C128K.nes",
+];
+const WARMUP_FRAMES: usize = 60;
+const FRAMES: usize = 600;
+const RUNS: usize = 3;
+
+// How long `FRAMES` frames take and how many CPU cycles ran in them
+fn time_frames(emulator: &mut Emulator, audio: &mut Vec<i16>) -> (Duration, u64) {
+    let start = Instant::now();
+    let start_cycles = emulator.cpu().cycle_count;
+    for _ in 0..FRAMES {
+        emulator.step_frame();
+        audio.clear();
+        emulator.drain_audio(audio);
+    }
+    (start.elapsed(), emulator.cpu().cycle_count - start_cycles)
+}
+
+fn main() {
+    let mut audio = Vec::new();
+    for path in ROMS {
+        let mut emulator = Emulator::new(NESFile::new(Path::new(path).to_path_buf()));
+        for _ in 0..WARMUP_FRAMES {
+            emulator.step_frame();
+        }
+        let (time, cycles) = (0..RUNS)
+            .map(|_| time_frames(&mut emulator, &mut audio))
+            .min()
+            .unwrap();
+        println!(
+            "{path:<40} {:>8.1} fps {:>8.2}M cycles/s",
+            FRAMES as f64 / time.as_secs_f64(),
+            cycles as f64 / time.as_secs_f64() / 1e6
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    process::ExitCode,
+};
+
+use nes::{
+    frontend::{nsf::player_from_config, wav::write_wav},
+    nsf_parser::NsfFile,
+};
+
+const SAMPLE_RATE: u32 = 48000;
+
+// Renders a song from an NSF or NSFe rip to a WAV file without opening a window. Plays for the
+// length the NSFe gives, or `nsf_track_length` from config.toml, and fades out
+fn main() -> ExitCode {
+    let args = std::env::args().collect::<Vec<_>>();
+    let (input, output, track) = match args.as_slice() {
+        [_, input, output] => (input, output, None),
+        [_, input, output, track] => match track.parse::<u8>() {
+            Ok(track) if track > 0 => (input, output, Some(track - 1)),
+            _ => {
+                eprintln!("Track numbers start from 1");
+                return ExitCode::FAILURE;
+            }
+        },
+        _ => {
+            eprintln!("Usage: nsf2wav <rip.nsf|rip.nsfe> <out.wav> [track]");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let file = match NsfFile::new(input) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to load {input}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let song = track.unwrap_or(file.starting_song);
+    if song >= file.total_songs {
+        eprintln!("{input} only has {} tracks", file.total_songs);
+        return ExitCode::FAILURE;
+    }
+
+    let mut player = player_from_config(file);
+    player.set_sample_rate(f64::from(SAMPLE_RATE));
+    let samples = player.render_song(song);
+    let channels = player.audio_channels() as u16;
+
+    let result = File::create(output).and_then(|out| {
+        let mut out = BufWriter::new(out);
+        write_wav(&mut out, &samples, channels, SAMPLE_RATE)?;
+        out.flush()
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to write {output}: {e}");
+        return ExitCode::FAILURE;
+    }
+    println!(
+        "Wrote {} ({}s) to {output}",
+        player.file().track_title(song),
+        player.track_length(song).as_secs()
+    );
+    ExitCode::SUCCESS
+}
@@ -1,12 +1,16 @@
 use config::Config as OtherConfig;
-use lazy_static::lazy_static;
+use std::sync::LazyLock;
+use toml_edit::DocumentMut;
 
-lazy_static! {
-    static ref CONF: OtherConfig = OtherConfig::builder()
+// Where `update` writes back to, the builder below picks the same file
+const CONFIG_PATH: &str = "config.toml";
+
+static CONF: LazyLock<OtherConfig> = LazyLock::new(|| {
+    OtherConfig::builder()
         .add_source(config::File::with_name("config"))
         .build()
-        .unwrap();
-}
+        .unwrap()
+});
 
 pub struct Config {}
 
@@ -22,6 +26,7 @@ impl Config {
             .unwrap_or_else(|_| default.to_string())
     }
 
+    #[must_use]
     pub fn get_string(prop: &str) -> Option<String> {
         CONF.get_string(prop).ok()
     }
@@ -29,4 +34,29 @@ impl Config {
     pub fn get_int<T: Into<i64> + From<i64>>(prop: &str, default: T) -> T {
         CONF.get_int(prop).unwrap_or_else(|_| default.into()).into()
     }
+
+    /// Parses config.toml as it is on disk now, for settings that `update` can change while
+    /// running
+    ///
+    /// # Errors
+    ///
+    /// Fails if config.toml can't be parsed
+    pub fn read() -> std::io::Result<DocumentMut> {
+        let contents = std::fs::read_to_string(CONFIG_PATH).unwrap_or_default();
+        contents
+            .parse::<DocumentMut>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Edits config.toml in place, keeping comments and layout. Values already read through the
+    /// getters are not refreshed
+    ///
+    /// # Errors
+    ///
+    /// Fails if config.toml can't be parsed or written
+    pub fn update(edit: impl FnOnce(&mut DocumentMut)) -> std::io::Result<()> {
+        let mut doc = Self::read()?;
+        edit(&mut doc);
+        std::fs::write(CONFIG_PATH, doc.to_string())
+    }
 }
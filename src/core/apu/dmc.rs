@@ -1,8 +1,13 @@
-use super::frame_counter::IRQSignal;
+use crate::core::savestate::savestate;
+
+use super::Region;
 
 const PERIOD_LOOKUP: [u16; 16] = [
     428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
 ];
+const PAL_PERIOD_LOOKUP: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
 
 pub struct DMC {
     /// Writeable values
@@ -19,6 +24,8 @@ pub struct DMC {
     pub bytes_remaining: u16,
     pub current_addr: u16,
     need_init: u8,
+    // Set when a sample ends with the IRQ enabled, until $4015 is written or the IRQ disabled
+    pub irq_flag: bool,
 
     // Cycle/timer values
     previous_cycle: u64,
@@ -30,6 +37,7 @@ pub struct DMC {
     /// Misc flags
     silence_flag: bool,
     need_to_run: bool,
+    periods: &'static [u16; 16],
 }
 
 impl Default for DMC {
@@ -45,12 +53,14 @@ impl Default for DMC {
             bytes_remaining: 0,
             current_addr: 0,
             need_init: 0,
+            irq_flag: false,
             previous_cycle: 0,
             timer: PERIOD_LOOKUP[0],
             output_level: 0,
             output_buffer: None,
             silence_flag: true,
             need_to_run: false,
+            periods: &PERIOD_LOOKUP,
         }
     }
 }
@@ -60,11 +70,21 @@ impl DMC {
         DMC::default()
     }
 
+    pub fn set_region(&mut self, region: Region) {
+        self.periods = match region {
+            Region::Ntsc => &PERIOD_LOOKUP,
+            Region::Pal => &PAL_PERIOD_LOOKUP,
+        };
+    }
+
     // RAM Writes --------------------------------------------------------------
     pub fn write_ctrl(&mut self, data: u8) {
         self.irq_enable = data >> 7 != 0;
+        if !self.irq_enable {
+            self.irq_flag = false;
+        }
         self._loop = data >> 6 != 0;
-        self.period = PERIOD_LOOKUP[(data & 0x0f) as usize];
+        self.period = self.periods[(data & 0x0f) as usize];
     }
 
     pub fn write_load(&mut self, data: u8) {
@@ -121,7 +141,7 @@ impl DMC {
         self.output_buffer.is_none() && self.bytes_remaining > 0
     }
 
-    pub fn set_dmc_read_buffer(&mut self, val: u8) -> IRQSignal {
+    pub fn set_dmc_read_buffer(&mut self, val: u8) {
         if self.bytes_remaining > 0 {
             self.output_buffer = Some(val);
 
@@ -138,11 +158,10 @@ impl DMC {
                 if self._loop {
                     self.init_sample();
                 } else if self.irq_enable {
-                    return IRQSignal::Set;
+                    self.irq_flag = true;
                 }
             }
         }
-        IRQSignal::None
     }
 
     fn init_sample(&mut self) {
@@ -193,3 +212,23 @@ impl DMC {
         self.output_level as f32
     }
 }
+
+savestate!(DMC {
+    irq_enable,
+    _loop,
+    period,
+    output_level,
+    sample_addr,
+    sample_length,
+    shift_register,
+    bits_remaining,
+    bytes_remaining,
+    current_addr,
+    need_init,
+    irq_flag,
+    previous_cycle,
+    timer,
+    output_buffer,
+    silence_flag,
+    need_to_run,
+});
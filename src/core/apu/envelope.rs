@@ -1,3 +1,5 @@
+use crate::core::savestate::savestate;
+
 #[derive(Default)]
 pub struct Envelope {
     pub enabled: bool,
@@ -36,3 +38,12 @@ impl Envelope {
         }
     }
 }
+
+savestate!(Envelope {
+    enabled,
+    loops,
+    reset,
+    volume,
+    constant_volume,
+    counter,
+});
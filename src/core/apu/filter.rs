@@ -0,0 +1,123 @@
+use std::f32::consts::PI;
+
+use crate::core::savestate::{savestate, Savestate};
+
+// The analog stage after the DACs, https://www.nesdev.org/wiki/APU_Mixer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterPreset {
+    // 37 Hz high-pass. The RF modulator is left out
+    Famicom,
+    // 90 Hz high-pass, 440 Hz high-pass, 14 kHz low-pass
+    NesFrontLoader,
+    // Straight mixer output, for analysis
+    Raw,
+}
+
+impl FilterPreset {
+    pub fn from_config_str(val: &str) -> Option<Self> {
+        match val {
+            "famicom" => Some(FilterPreset::Famicom),
+            "nes" => Some(FilterPreset::NesFrontLoader),
+            "raw" => Some(FilterPreset::Raw),
+            _ => None,
+        }
+    }
+}
+
+enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+// First order RC filter
+struct Filter {
+    kind: FilterKind,
+    alpha: f32,
+    prev_in: f32,
+    prev_out: f32,
+}
+
+impl Filter {
+    fn new(kind: FilterKind, cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1. / (2. * PI * cutoff);
+        let dt = 1. / sample_rate;
+        let alpha = match kind {
+            FilterKind::HighPass => rc / (rc + dt),
+            FilterKind::LowPass => dt / (rc + dt),
+        };
+        Filter {
+            kind,
+            alpha,
+            prev_in: 0.,
+            prev_out: 0.,
+        }
+    }
+
+    fn process(&mut self, sample: f32) -> f32 {
+        let out = match self.kind {
+            FilterKind::HighPass => self.alpha * (self.prev_out + sample - self.prev_in),
+            FilterKind::LowPass => self.prev_out + self.alpha * (sample - self.prev_out),
+        };
+        self.prev_in = sample;
+        self.prev_out = out;
+        out
+    }
+}
+
+pub struct FilterChain {
+    preset: FilterPreset,
+    filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    pub fn new(preset: FilterPreset, sample_rate: f64) -> Self {
+        let sample_rate = sample_rate as f32;
+        let filters = match preset {
+            FilterPreset::Famicom => vec![Filter::new(FilterKind::HighPass, 37., sample_rate)],
+            FilterPreset::NesFrontLoader => vec![
+                Filter::new(FilterKind::HighPass, 90., sample_rate),
+                Filter::new(FilterKind::HighPass, 440., sample_rate),
+                Filter::new(FilterKind::LowPass, 14000., sample_rate),
+            ],
+            FilterPreset::Raw => vec![],
+        };
+        FilterChain { preset, filters }
+    }
+
+    pub fn preset(&self) -> FilterPreset {
+        self.preset
+    }
+
+    pub fn process_sample(&mut self, sample: i16) -> i16 {
+        let filtered = self
+            .filters
+            .iter_mut()
+            .fold(f32::from(sample), |acc, filter| filter.process(acc));
+        filtered.clamp(f32::from(i16::MIN), f32::from(i16::MAX)) as i16
+    }
+}
+
+savestate!(Filter { prev_in, prev_out });
+
+// Only the filters' history is saved. A state from another preset still loads, the history just
+// lines up with whichever filters are there
+impl Savestate for FilterChain {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.filters.len().save(out);
+        self.filters.iter().for_each(|filter| filter.save(out));
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> std::io::Result<()> {
+        let mut len = 0usize;
+        len.load(input)?;
+        for i in 0..len {
+            let mut filter = Filter::new(FilterKind::LowPass, 1., 1.);
+            filter.load(input)?;
+            if let Some(ours) = self.filters.get_mut(i) {
+                ours.prev_in = filter.prev_in;
+                ours.prev_out = filter.prev_out;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,7 @@
+use crate::core::savestate::{savestate, Savestate};
+
+use super::Region;
+
 #[derive(PartialEq, Eq, Copy, Clone)]
 pub enum Mode {
     FourStep = 0,
@@ -21,6 +25,10 @@ const STEP_CYCLES: [[u16; 6]; 2] = [
     [7457, 14913, 22371, 29828, 29829, 29830],
     [7457, 14913, 22371, 29829, 37281, 37282],
 ];
+const PAL_STEP_CYCLES: [[u16; 6]; 2] = [
+    [8313, 16627, 24939, 33252, 33253, 33254],
+    [8313, 16627, 24939, 33253, 41565, 41566],
+];
 const FRAME_TYPES: [[FrameType; 6]; 2] = [
     [
         FrameType::QuarterFrame,
@@ -45,8 +53,12 @@ pub struct FrameCounter {
     pub step: usize,
     pub mode: Mode,
     write_buffer: Option<u8>,
+    // Last value written to $4017, written again on reset
+    last_write: u8,
     write_delay: i8,
     block_tick: u8,
+    // `STEP_CYCLES` or `PAL_STEP_CYCLES`
+    step_cycles: &'static [[u16; 6]; 2],
 }
 
 impl Default for FrameCounter {
@@ -58,6 +70,8 @@ impl Default for FrameCounter {
             write_delay: 3,
             block_tick: 0,
             write_buffer: None,
+            last_write: 0,
+            step_cycles: &STEP_CYCLES,
         }
     }
 }
@@ -72,9 +86,7 @@ impl FrameCounter {
         let cycles_ran;
         let mut signal = IRQSignal::None;
 
-        if self.previous_cycle + *cycles_to_run
-            >= i32::from(STEP_CYCLES[self.mode as usize][self.step])
-        {
+        if self.previous_cycle + *cycles_to_run >= self.step_cycle() {
             if !inhibit_irq && self.mode == Mode::FourStep && self.step >= 3 {
                 signal = IRQSignal::Set;
             }
@@ -85,13 +97,11 @@ impl FrameCounter {
                 self.block_tick = 2;
             }
 
-            cycles_ran =
-                if i32::from(STEP_CYCLES[self.mode as usize][self.step]) < self.previous_cycle {
-                    0
-                } else {
-                    (i32::from(STEP_CYCLES[self.mode as usize][self.step]) - self.previous_cycle)
-                        .unsigned_abs()
-                };
+            cycles_ran = if self.step_cycle() < self.previous_cycle {
+                0
+            } else {
+                (self.step_cycle() - self.previous_cycle).unsigned_abs()
+            };
 
             *cycles_to_run -= cycles_ran as i32;
 
@@ -136,15 +146,61 @@ impl FrameCounter {
         (signal, cycles_ran)
     }
 
+    // CPU cycle the current step lands on
+    fn step_cycle(&self) -> i32 {
+        i32::from(self.step_cycles[self.mode as usize][self.step])
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.step_cycles = match region {
+            Region::Ntsc => &STEP_CYCLES,
+            Region::Pal => &PAL_STEP_CYCLES,
+        };
+    }
+
+    /// Resetting restarts the sequence as if $4017 had been written with its last value
+    pub fn reset(&mut self, cycle: usize) {
+        self.write(self.last_write, cycle);
+    }
+
     pub fn need_to_run(&self, cycles_to_run: u32) -> bool {
         self.write_buffer.is_some()
             || self.block_tick > 0
-            || (self.previous_cycle + cycles_to_run as i32)
-                >= i32::from(STEP_CYCLES[self.mode as usize][self.step]) - 1
+            || (self.previous_cycle + cycles_to_run as i32) >= self.step_cycle() - 1
     }
 
     pub fn write(&mut self, val: u8, cycle: usize) {
+        self.last_write = val;
         self.write_buffer = Some(val);
-        self.write_delay = if cycle & 1 == 1 { 4 } else { 3 }
+        // 3 cycles when written during an APU cycle, 4 between them. The APU cycle count runs one
+        // ahead of the CPU's, so odd here lines up with the CPU's even get cycles
+        self.write_delay = if cycle & 1 == 1 { 3 } else { 4 }
     }
 }
+
+impl Savestate for Mode {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u8).save(out);
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> std::io::Result<()> {
+        let mut mode = 0u8;
+        mode.load(input)?;
+        *self = if mode == 0 {
+            Mode::FourStep
+        } else {
+            Mode::FiveStep
+        };
+        Ok(())
+    }
+}
+
+savestate!(FrameCounter {
+    previous_cycle,
+    step,
+    mode,
+    write_buffer,
+    last_write,
+    write_delay,
+    block_tick,
+});
@@ -1,3 +1,5 @@
+use crate::core::savestate::savestate;
+
 const LENGTH_LOOKUP: [u8; 0x20] = [
     0x0A, 0xFE, 0x14, 0x02, 0x28, 0x04, 0x50, 0x06, 0xA0, 0x08, 0x3C, 0x0A, 0x0E, 0x0C, 0x1A, 0x0E,
     0x0C, 0x10, 0x18, 0x12, 0x30, 0x14, 0x60, 0x16, 0xC0, 0x18, 0x48, 0x1A, 0x10, 0x1C, 0x20, 0x1E,
@@ -48,3 +50,12 @@ impl LengthCounter {
         }
     }
 }
+
+savestate!(LengthCounter {
+    enabled,
+    counter,
+    halt,
+    reload_val,
+    prev_value,
+    new_halt_val,
+});
@@ -0,0 +1,211 @@
+use lazy_static::lazy_static;
+
+use crate::config::Config;
+
+// Amplitude of a full scale mixer output (~1.0) once converted to an integer sample
+const OUTPUT_SCALE: f64 = 5000.;
+
+lazy_static! {
+    // Non-linear DAC lookup tables, https://www.nesdev.org/wiki/APU_Mixer#Lookup_Table
+    //
+    //   pulse_table[pulse1 + pulse2] = 95.52 / (8128 / n + 100)
+    //   tnd_table[3 * triangle + 2 * noise + dmc] = 163.67 / (24329 / n + 100)
+    static ref PULSE_TABLE: [i32; 31] = {
+        let mut table = [0; 31];
+        for (n, entry) in table.iter_mut().enumerate().skip(1) {
+            *entry = (95.52 / (8128. / n as f64 + 100.) * OUTPUT_SCALE) as i32;
+        }
+        table
+    };
+    static ref TND_TABLE: [i32; 203] = {
+        let mut table = [0; 203];
+        for (n, entry) in table.iter_mut().enumerate().skip(1) {
+            *entry = (163.67 / (24329. / n as f64 + 100.) * OUTPUT_SCALE) as i32;
+        }
+        table
+    };
+}
+
+// Scaled channel levels land between table entries, so interpolate. Integer indices give the
+// same result as a plain lookup
+fn lookup(table: &[i32], index: f32) -> f32 {
+    let index = index.clamp(0., (table.len() - 1) as f32);
+    let lo = index as usize;
+    let hi = (lo + 1).min(table.len() - 1);
+    let frac = index - lo as f32;
+    table[lo] as f32 + (table[hi] - table[lo]) as f32 * frac
+}
+
+fn percent(val: f32) -> i64 {
+    (val * 100.).round() as i64
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MixerChannel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    DMC,
+    // Audio generated on the cartridge, mixed in linearly
+    Expansion,
+}
+
+impl MixerChannel {
+    pub const ALL: [MixerChannel; 6] = [
+        MixerChannel::Pulse1,
+        MixerChannel::Pulse2,
+        MixerChannel::Triangle,
+        MixerChannel::Noise,
+        MixerChannel::DMC,
+        MixerChannel::Expansion,
+    ];
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            MixerChannel::Pulse1 => "Pulse 1",
+            MixerChannel::Pulse2 => "Pulse 2",
+            MixerChannel::Triangle => "Triangle",
+            MixerChannel::Noise => "Noise",
+            MixerChannel::DMC => "DMC",
+            MixerChannel::Expansion => "Expansion",
+        }
+    }
+
+    const fn config_key(self) -> &'static str {
+        match self {
+            MixerChannel::Pulse1 => "pulse1",
+            MixerChannel::Pulse2 => "pulse2",
+            MixerChannel::Triangle => "triangle",
+            MixerChannel::Noise => "noise",
+            MixerChannel::DMC => "dmc",
+            MixerChannel::Expansion => "expansion",
+        }
+    }
+
+    // Spread the channels out a little when stereo is first turned on
+    const fn default_pan(self) -> f32 {
+        match self {
+            MixerChannel::Pulse1 => -0.3,
+            MixerChannel::Pulse2 => 0.3,
+            MixerChannel::Triangle | MixerChannel::DMC | MixerChannel::Expansion => 0.,
+            MixerChannel::Noise => 0.15,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelMix {
+    // 0 to 1
+    pub volume: f32,
+    pub muted: bool,
+    // -1 (left) to 1 (right), only used in stereo
+    pub pan: f32,
+}
+
+impl ChannelMix {
+    fn gain(&self) -> f32 {
+        if self.muted {
+            0.
+        } else {
+            self.volume
+        }
+    }
+
+    // Balance style panning, the centre is full volume on both sides
+    fn gains(&self, stereo: bool) -> (f32, f32) {
+        let gain = self.gain();
+        if !stereo {
+            return (gain, gain);
+        }
+        (
+            gain * (1. - self.pan).min(1.),
+            gain * (1. + self.pan).min(1.),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MixerSettings {
+    pub channels: [ChannelMix; 6],
+    pub stereo: bool,
+}
+
+impl Default for MixerSettings {
+    fn default() -> Self {
+        Self {
+            channels: MixerChannel::ALL.map(|channel| ChannelMix {
+                volume: 1.,
+                muted: false,
+                pan: channel.default_pan(),
+            }),
+            stereo: false,
+        }
+    }
+}
+
+impl MixerSettings {
+    #[must_use]
+    pub fn channel(&self, channel: MixerChannel) -> &ChannelMix {
+        &self.channels[channel as usize]
+    }
+
+    pub fn channel_mut(&mut self, channel: MixerChannel) -> &mut ChannelMix {
+        &mut self.channels[channel as usize]
+    }
+
+    /// Reads the `[mixer]` table of config.toml. Volume and pan are stored as percentages
+    #[must_use]
+    pub fn from_config() -> Self {
+        let mut settings = Self {
+            stereo: Config::get_bool("mixer.stereo", false),
+            ..Self::default()
+        };
+        for channel in MixerChannel::ALL {
+            let key = channel.config_key();
+            let mix = settings.channel_mut(channel);
+            let volume = Config::get_int(&format!("mixer.{key}.volume"), 100i64);
+            let pan = Config::get_int(&format!("mixer.{key}.pan"), percent(mix.pan));
+            mix.volume = volume.clamp(0, 100) as f32 / 100.;
+            mix.pan = pan.clamp(-100, 100) as f32 / 100.;
+            mix.muted = Config::get_bool(&format!("mixer.{key}.muted"), false);
+        }
+        settings
+    }
+
+    pub fn save_to_config(&self) -> std::io::Result<()> {
+        Config::update(|doc| {
+            let mixer = doc["mixer"].or_insert(toml_edit::table());
+            mixer["stereo"] = toml_edit::value(self.stereo);
+            for channel in MixerChannel::ALL {
+                let mix = self.channel(channel);
+                let mut table = toml_edit::InlineTable::new();
+                table.insert("volume", percent(mix.volume).into());
+                table.insert("muted", mix.muted.into());
+                table.insert("pan", percent(mix.pan).into());
+                mixer[channel.config_key()] = toml_edit::value(table);
+            }
+        })
+    }
+
+    /// Mixes the raw channel outputs, in `MixerChannel` order, into a left and right sample. The
+    /// expansion level is a fraction of full scale. In mono both sides are the same
+    #[must_use]
+    pub fn mix(&self, levels: &[f32; 6]) -> (i32, i32) {
+        let mut left = [0.; 6];
+        let mut right = [0.; 6];
+        for (i, mix) in self.channels.iter().enumerate() {
+            let (l, r) = mix.gains(self.stereo);
+            left[i] = levels[i] * l;
+            right[i] = levels[i] * r;
+        }
+        (Self::mix_side(&left), Self::mix_side(&right))
+    }
+
+    fn mix_side(levels: &[f32; 6]) -> i32 {
+        let pulse = lookup(&*PULSE_TABLE, levels[0] + levels[1]);
+        let tnd = lookup(&*TND_TABLE, 3. * levels[2] + 2. * levels[3] + levels[4]);
+        (pulse + tnd + levels[5] * OUTPUT_SCALE as f32) as i32
+    }
+}
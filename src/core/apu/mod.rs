@@ -1,33 +1,54 @@
 pub mod base_channel;
 pub mod dmc;
 pub mod envelope;
+pub mod filter;
 pub mod frame_counter;
 pub mod length_counter;
+pub mod mixer;
 pub mod noise;
 pub mod pulse;
 pub mod sweep;
 pub mod triangle;
 
 use dmc::DMC;
+use filter::{FilterChain, FilterPreset};
 use frame_counter::FrameCounter;
+use mixer::MixerSettings;
 use noise::Noise;
 use pulse::Pulse;
 use triangle::Triangle;
 
+use crate::core::savestate::savestate;
 use crate::frontend::blip_buf::BlipBuf;
 
 use self::base_channel::AudioChannel;
 use self::frame_counter::{FrameType, IRQSignal};
 use self::length_counter::NeedToRunFlag;
 
+/// Which console's timing the APU runs with. The NES is only emulated as NTSC, PAL is for NSF rips
+/// made for PAL machines
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+}
+
 pub struct APU {
     pulse1: Pulse,
     pulse2: Pulse,
     triangle: Triangle,
-    _noise: Noise,
+    noise: Noise,
     pub dmc: DMC,
     frame_counter: FrameCounter,
     pub output_buffer: BlipBuf<65536>,
+    // One per output channel, the right one is only used in stereo
+    filters: [FilterChain; 2],
+    mixer: MixerSettings,
+    // Cartridge audio as a fraction of full scale, set by mappers with expansion sound
+    expansion_output: f32,
+    region: Region,
+    sample_rate: f64,
     irq_pending: bool,
     irq_disabled: bool,
     cycle: usize,
@@ -44,6 +65,7 @@ impl Default for APU {
 
 impl APU {
     pub const CLOCK_RATE: f64 = 1789772.7272;
+    pub const PAL_CLOCK_RATE: f64 = 1662607.;
     const DEFAULT_SAMPLE_RATE: f64 = 48000.;
 
     #[must_use]
@@ -52,10 +74,18 @@ impl APU {
             pulse1: Pulse::new(AudioChannel::Pulse1),
             pulse2: Pulse::new(AudioChannel::Pulse2),
             triangle: Triangle::default(),
-            _noise: Noise::default(),
+            noise: Noise::default(),
             dmc: DMC::default(),
             frame_counter: FrameCounter::default(),
             output_buffer: BlipBuf::new(Self::CLOCK_RATE, Self::DEFAULT_SAMPLE_RATE),
+            filters: [
+                FilterChain::new(FilterPreset::NesFrontLoader, Self::DEFAULT_SAMPLE_RATE),
+                FilterChain::new(FilterPreset::NesFrontLoader, Self::DEFAULT_SAMPLE_RATE),
+            ],
+            mixer: MixerSettings::default(),
+            expansion_output: 0.,
+            region: Region::Ntsc,
+            sample_rate: Self::DEFAULT_SAMPLE_RATE,
             irq_pending: false,
             irq_disabled: false,
             cycle: 0,
@@ -74,18 +104,61 @@ impl APU {
         if self.pulse2.length.counter > 0 {
             status |= 0x2;
         }
+        if self.noise.length.counter > 0 {
+            status |= 0x8;
+        }
         if self.irq_pending {
             status |= 0x40;
         }
+        if self.dmc.irq_flag {
+            status |= 0x80;
+        }
         status
     }
 
-    pub fn clock(&mut self) -> (bool, bool) {
+    pub fn clock(&mut self) {
         self.cycle += 1;
         // self.need_to_run();
         self.run();
         self.output();
-        (self.irq_pending, self.need_dmc_transfer)
+    }
+
+    /// The frame counter's IRQ
+    pub const fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    /// Whether the DMC ran out of sample bytes on the last clock and needs the CPU to fetch one
+    pub const fn need_dmc_transfer(&self) -> bool {
+        self.need_dmc_transfer
+    }
+
+    /// Writes one of the APU's registers at $4000-$4017. $4014 and $4016 belong to the PPU and the
+    /// controllers and are ignored, as are the CPU test registers after $4017
+    pub fn write_register(&mut self, addr: u16, data: u8, cpu_cycle: u64) {
+        match addr {
+            0x4000 => self.write_ctrl(&AudioChannel::Pulse1, data),
+            0x4001 => self.write_sweep(&AudioChannel::Pulse1, data),
+            0x4002 => self.write_timer_lo(&AudioChannel::Pulse1, data),
+            0x4003 => self.write_timer_hi(&AudioChannel::Pulse1, data),
+            0x4004 => self.write_ctrl(&AudioChannel::Pulse2, data),
+            0x4005 => self.write_sweep(&AudioChannel::Pulse2, data),
+            0x4006 => self.write_timer_lo(&AudioChannel::Pulse2, data),
+            0x4007 => self.write_timer_hi(&AudioChannel::Pulse2, data),
+            0x4008 => self.write_ctrl(&AudioChannel::Triangle, data),
+            0x400A => self.write_timer_lo(&AudioChannel::Triangle, data),
+            0x400B => self.write_timer_hi(&AudioChannel::Triangle, data),
+            0x400C => self.write_ctrl(&AudioChannel::Noise, data),
+            0x400E => self.write_timer_lo(&AudioChannel::Noise, data),
+            0x400F => self.write_timer_hi(&AudioChannel::Noise, data),
+            0x4010 => self.write_dmc_ctrl(data),
+            0x4011 => self.write_dmc_load(data),
+            0x4012 => self.write_dmc_addr(data),
+            0x4013 => self.write_dmc_lc(data),
+            0x4015 => self.write_status(data, cpu_cycle),
+            0x4017 => self.write_frame_counter(data),
+            _ => {}
+        }
     }
 
     pub fn write_ctrl(&mut self, channel: &AudioChannel, val: u8) {
@@ -94,6 +167,7 @@ impl APU {
             AudioChannel::Pulse1 => flag = self.pulse1.write_ctrl(val),
             AudioChannel::Pulse2 => flag = self.pulse2.write_ctrl(val),
             AudioChannel::Triangle => self.triangle.write_ctrl(val),
+            AudioChannel::Noise => flag = self.noise.write_ctrl(val),
             _ => {}
         }
 
@@ -115,6 +189,7 @@ impl APU {
             AudioChannel::Pulse1 => self.pulse1.write_timer_lo(val),
             AudioChannel::Pulse2 => self.pulse2.write_timer_lo(val),
             AudioChannel::Triangle => self.triangle.write_timer_lo(val),
+            AudioChannel::Noise => self.noise.write_period(val),
             _ => {}
         }
     }
@@ -125,6 +200,7 @@ impl APU {
             AudioChannel::Pulse1 => flag = self.pulse1.write_timer_hi(val),
             AudioChannel::Pulse2 => flag = self.pulse2.write_timer_hi(val),
             AudioChannel::Triangle => flag = self.triangle.write_timer_hi(val),
+            AudioChannel::Noise => flag = self.noise.write_length(val),
             _ => {}
         }
 
@@ -141,9 +217,11 @@ impl APU {
                 self.pulse1.clock_quarter_frame();
                 self.pulse2.clock_quarter_frame();
                 self.triangle.clock_quarter_frame();
+                self.noise.clock_quarter_frame();
                 if typ == FrameType::HalfFrame {
                     self.pulse1.clock_length_counter();
                     self.pulse2.clock_length_counter();
+                    self.noise.clock_length_counter();
                     self.pulse1.clock_sweep();
                     self.pulse2.clock_sweep();
                     self.triangle.clock_half_frame();
@@ -163,15 +241,17 @@ impl APU {
             self.pulse1.reload_counter();
             self.pulse2.reload_counter();
             self.triangle.reload_counter();
+            self.noise.reload_counter();
 
             self.pulse1.clock(self.prev_cycle as u64);
             self.pulse2.clock(self.prev_cycle as u64);
             self.triangle.clock(self.prev_cycle as u64);
+            self.noise.clock(self.prev_cycle as u64);
             self.need_dmc_transfer = self.dmc.clock(self.prev_cycle as u64);
         }
     }
 
-    pub fn read_status(&mut self) -> (u8, IRQSignal) {
+    pub fn read_status(&mut self) -> u8 {
         let mut status = 0x0;
         if self.pulse1.length.counter > 0 {
             status |= 0x01;
@@ -182,14 +262,20 @@ impl APU {
         if self.triangle.length.counter > 0 {
             status |= 0x04
         }
+        if self.noise.length.counter > 0 {
+            status |= 0x08
+        }
         if self.irq_pending {
             status |= 0x40;
         }
         if self.dmc.bytes_remaining > 0 {
             status |= 0x10;
         }
+        if self.dmc.irq_flag {
+            status |= 0x80;
+        }
         self.irq_pending = false;
-        (status, IRQSignal::Clear)
+        status
     }
 
     pub fn write_dmc_ctrl(&mut self, data: u8) {
@@ -216,30 +302,140 @@ impl APU {
         self.pulse1.set_enabled(val & 0x1 != 0);
         self.pulse2.set_enabled(val & 0x2 != 0);
         self.triangle.set_enabled(val & 0x4 != 0);
-        self.dmc.set_enabled(val & 0x10 != 0, cpu_cycle)
+        self.noise.set_enabled(val & 0x8 != 0);
+        self.dmc.set_enabled(val & 0x10 != 0, cpu_cycle);
+        self.dmc.irq_flag = false;
     }
 
-    pub fn write_frame_counter(&mut self, val: u8) -> IRQSignal {
+    /// Reset silences every channel the way writing 0 to $4015 does, restarts the frame counter
+    /// with the last value written to $4017 and clears the frame IRQ. Most other channel state
+    /// survives. See <https://www.nesdev.org/wiki/CPU_power_up_state>
+    pub fn reset(&mut self) {
+        self.run();
+        self.write_status(0, self.cycle as u64);
+        self.triangle.reset_phase();
+        self.dmc.output_level &= 1;
+        self.frame_counter.reset(self.cycle);
+        self.irq_pending = false;
+    }
+
+    /// Back to the power on state, keeping the output buffer, filters, mixer, region and sample
+    /// rate
+    pub fn power_cycle(&mut self) {
+        let old = std::mem::take(self);
+        self.output_buffer = old.output_buffer;
+        self.filters = old.filters;
+        self.mixer = old.mixer;
+        self.sample_rate = old.sample_rate;
+        self.set_region(old.region);
+    }
+
+    pub fn write_frame_counter(&mut self, val: u8) {
         self.frame_counter.write(val, self.cycle);
         self.irq_disabled = val & 0x40 != 0;
         if self.irq_disabled {
             self.irq_pending = false;
-            return IRQSignal::Clear;
         }
-        IRQSignal::None
     }
 
     fn output(&mut self) {
-        let pulse1 = self.pulse1.output() as f64;
-        let pulse2 = self.pulse2.output() as f64;
-        let pulse_out = pulse1 + pulse2;
-        let square_volume = (477600. / (8128.0 / pulse_out + 100.0)) as i32;
+        let levels = [
+            f32::from(self.pulse1.output()),
+            f32::from(self.pulse2.output()),
+            self.triangle.output(),
+            f32::from(self.noise.output()),
+            self.dmc.output(),
+            self.expansion_output,
+        ];
+        let (left, right) = self.mixer.mix(&levels);
+        self.output_buffer.add_stereo_sample(left, right);
+    }
 
-        let triangle = self.triangle.output() as f64;
-        let dmc = self.dmc.output() as f64;
-        let tnd_out = 3. * triangle + dmc;
-        let tnd_volume = (816850. / ((24329. / tnd_out) + 100.0)) as i32;
+    /// The CPU clock for the region, which the channels' timers count in
+    #[must_use]
+    pub const fn clock_rate(&self) -> f64 {
+        match self.region {
+            Region::Ntsc => Self::CLOCK_RATE,
+            Region::Pal => Self::PAL_CLOCK_RATE,
+        }
+    }
+
+    /// Switches the noise and DMC rates, frame counter steps and output resampling to `region`.
+    /// Periods already written keep their old value until the register is written again
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+        self.frame_counter.set_region(region);
+        self.output_buffer.set_rates(self.clock_rate(), self.sample_rate);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.output_buffer.set_rates(self.clock_rate(), sample_rate);
+        self.set_filter_preset(self.filters[0].preset());
+    }
+
+    pub fn set_filter_preset(&mut self, preset: FilterPreset) {
+        self.filters = [
+            FilterChain::new(preset, self.sample_rate),
+            FilterChain::new(preset, self.sample_rate),
+        ];
+    }
+
+    #[must_use]
+    pub const fn mixer(&self) -> &MixerSettings {
+        &self.mixer
+    }
 
-        self.output_buffer.add_sample(square_volume + tnd_volume);
+    pub fn set_mixer(&mut self, mixer: MixerSettings) {
+        self.output_buffer.set_stereo(mixer.stereo);
+        self.mixer = mixer;
+    }
+
+    pub fn set_expansion_output(&mut self, level: f32) {
+        self.expansion_output = level;
+    }
+
+    /// Number of interleaved channels `end_frame` produces
+    #[must_use]
+    pub fn output_channels(&self) -> usize {
+        if self.output_buffer.stereo() {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Flushes the samples generated this frame into `out`, run through the output filters
+    pub fn end_frame(&mut self, out: &mut Vec<i16>) {
+        let start = out.len();
+        let channels = self.output_channels();
+        self.output_buffer.end_frame(out);
+        for frame in out[start..].chunks_mut(channels) {
+            for (sample, filter) in frame.iter_mut().zip(self.filters.iter_mut()) {
+                *sample = filter.process_sample(*sample);
+            }
+        }
     }
 }
+
+// The mixer and sample rate are settings and stay as they are. The output buffer and filters only
+// save their history, so audio carries on exactly as it would have
+savestate!(APU {
+    pulse1,
+    pulse2,
+    triangle,
+    noise,
+    dmc,
+    frame_counter,
+    output_buffer,
+    filters,
+    expansion_output,
+    irq_pending,
+    irq_disabled,
+    cycle,
+    need_to_run,
+    prev_cycle,
+    need_dmc_transfer,
+});
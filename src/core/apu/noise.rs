@@ -1,2 +1,138 @@
-#[derive(Default)]
-pub struct Noise {}
+use crate::core::savestate::savestate;
+
+use super::{
+    envelope::Envelope,
+    length_counter::{LengthCounter, NeedToRunFlag},
+    Region,
+};
+
+// Timer periods in CPU cycles, NTSC
+const PERIOD_LOOKUP: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIOD_LOOKUP: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+pub struct Noise {
+    pub length: LengthCounter,
+    envelope: Envelope,
+    period: u16,
+    previous_cycle: u64,
+    timer: u16,
+    // 15-bit linear feedback shift register, https://www.nesdev.org/wiki/APU_Noise
+    shift_register: u16,
+    mode: bool,
+    periods: &'static [u16; 16],
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Noise {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            length: LengthCounter::new(),
+            envelope: Envelope::new(),
+            period: PERIOD_LOOKUP[0] - 1,
+            previous_cycle: 0,
+            timer: 0,
+            shift_register: 1,
+            mode: false,
+            periods: &PERIOD_LOOKUP,
+        }
+    }
+
+    #[must_use]
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 0x01 != 0 {
+            return 0;
+        }
+        self.get_volume()
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_length_counter(&mut self) {
+        self.length.clock();
+    }
+
+    pub fn reload_counter(&mut self) {
+        self.length.reload();
+    }
+
+    pub fn write_ctrl(&mut self, val: u8) -> NeedToRunFlag {
+        let flag = self.length.write_ctrl(val);
+        self.envelope.write_ctrl(val);
+        flag
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.periods = match region {
+            Region::Ntsc => &PERIOD_LOOKUP,
+            Region::Pal => &PAL_PERIOD_LOOKUP,
+        };
+    }
+
+    pub fn write_period(&mut self, val: u8) {
+        self.mode = val & 0x80 != 0;
+        self.period = self.periods[(val & 0x0f) as usize] - 1;
+    }
+
+    pub fn write_length(&mut self, val: u8) -> NeedToRunFlag {
+        self.envelope.reset = true;
+        if self.length.enabled {
+            self.length.load_value(val)
+        } else {
+            NeedToRunFlag(None)
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length.enabled = enabled;
+        if !enabled {
+            self.length.counter = 0;
+        }
+    }
+
+    pub fn clock(&mut self, target_cycle: u64) {
+        let mut cycles_to_run = target_cycle - self.previous_cycle;
+        while cycles_to_run > u64::from(self.timer) {
+            cycles_to_run -= u64::from(self.timer) + 1;
+            self.previous_cycle += u64::from(self.timer) + 1;
+            self.timer = self.period;
+
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 0x01;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        }
+        self.timer -= cycles_to_run as u16;
+        self.previous_cycle = target_cycle;
+    }
+
+    fn get_volume(&self) -> u8 {
+        if self.length.counter > 0 {
+            if self.envelope.enabled {
+                return self.envelope.volume;
+            }
+            return self.envelope.constant_volume;
+        }
+        0
+    }
+}
+
+savestate!(Noise {
+    length,
+    envelope,
+    period,
+    previous_cycle,
+    timer,
+    shift_register,
+    mode,
+});
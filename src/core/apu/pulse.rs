@@ -1,3 +1,5 @@
+use crate::core::savestate::savestate;
+
 use super::{
     base_channel::AudioChannel,
     envelope::Envelope,
@@ -164,3 +166,15 @@ impl Pulse {
         }
     }
 }
+
+savestate!(Pulse {
+    length,
+    envelope,
+    sweep,
+    real_period,
+    period,
+    previous_cycle,
+    timer,
+    duty_counter,
+    duty_cycle,
+});
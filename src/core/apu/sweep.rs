@@ -1,3 +1,5 @@
+use crate::core::savestate::savestate;
+
 #[derive(Default)]
 pub struct Sweep {
     pub enabled: bool,
@@ -7,4 +9,14 @@ pub struct Sweep {
     pub target_period: u32,
     pub period: u8,
     pub reload: bool,
-}
\ No newline at end of file
+}
+
+savestate!(Sweep {
+    enabled,
+    negate,
+    divider,
+    shift,
+    target_period,
+    period,
+    reload,
+});
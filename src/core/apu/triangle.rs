@@ -1,3 +1,5 @@
+use crate::core::savestate::savestate;
+
 use super::length_counter::{LengthCounter, NeedToRunFlag};
 
 const SEQUENCE: [u8; 32] = [
@@ -57,6 +59,11 @@ impl Triangle {
         }
     }
 
+    /// Reset puts the sequencer back at the start of the waveform
+    pub fn reset_phase(&mut self) {
+        self.seq_pos = 0;
+    }
+
     pub fn output(&self) -> f32 {
         if self.period < 2 {
             return 7.5;
@@ -78,8 +85,8 @@ impl Triangle {
 
     pub fn write_ctrl(&mut self, data: u8) {
         self.linear.control = (data >> 7) == 1;
-        self.linear.counter_reload = data & 0x8F;
-        self.length.write_ctrl(data >> 7);
+        self.linear.counter_reload = data & 0x7F;
+        self.length.write_ctrl(data >> 2);
     }
 
     pub fn write_timer_lo(&mut self, data: u8) {
@@ -111,5 +118,24 @@ impl Triangle {
 
     pub fn set_enabled(&mut self, enabled: bool) {
         self.length.enabled = enabled;
+        if !enabled {
+            self.length.counter = 0;
+        }
     }
 }
+
+savestate!(LinearCounter {
+    counter,
+    counter_reload,
+    reload,
+    control,
+});
+
+savestate!(Triangle {
+    length,
+    linear,
+    timer,
+    previous_cycle,
+    period,
+    seq_pos,
+});
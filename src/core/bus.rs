@@ -1,11 +1,12 @@
-use std::sync::{Arc, Mutex};
-
-use crate::core::apu::base_channel::AudioChannel;
-use crate::core::apu::frame_counter::IRQSignal;
+use crate::config::Config;
 use crate::core::apu::APU;
+use crate::core::cpu::{CpuBus, IRQSource};
+use crate::core::expansion::{BoxedExpansionDevice, ExpansionDeviceFactory};
 use crate::core::joypad::Joypad;
-use crate::core::mappers::{MapperFactory, SharedMapper};
-use crate::{core::ppu::PPU, ines_parser::NESFile};
+use crate::core::mappers::{Mapper, MapperFactory};
+use crate::core::ppu::{DMAFlag, PPU};
+use crate::core::savestate::savestate;
+use crate::ines_parser::{Flags1Enum, NESFile};
 
 const RAM_SIZE: usize = 0x0800;
 const RAM_START: u16 = 0x0000;
@@ -14,44 +15,173 @@ const PPU_REG_START: u16 = 0x2000;
 const PPU_REG_END: u16 = 0x3FFF;
 const APU_IO_START: u16 = 0x4000;
 const APU_IO_END: u16 = 0x401F;
+const EXPANSION_START: u16 = 0x4020;
+const EXPANSION_END: u16 = 0x5FFF;
+
+// Bits of $4015 and $4016/$4017 that nothing drives on reads
+const STATUS_OPEN_BUS: u8 = 0x20;
+const JOYPAD_OPEN_BUS: u8 = 0xE0;
+
+/// What the 2KB of CPU RAM holds at power on. Real consoles come up with a mostly random mix, and a
+/// few games read it before clearing it, e.g. to seed a random number generator
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PowerOnRam {
+    #[default]
+    Zeros,
+    Ones,
+    // Four bytes of $00 then four of $FF, repeated
+    Pattern,
+    Random,
+}
+
+impl PowerOnRam {
+    pub const ALL: [PowerOnRam; 4] = [
+        PowerOnRam::Zeros,
+        PowerOnRam::Ones,
+        PowerOnRam::Pattern,
+        PowerOnRam::Random,
+    ];
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            PowerOnRam::Zeros => "All $00",
+            PowerOnRam::Ones => "All $FF",
+            PowerOnRam::Pattern => "Pattern",
+            PowerOnRam::Random => "Random",
+        }
+    }
+
+    #[must_use]
+    pub const fn config_str(self) -> &'static str {
+        match self {
+            PowerOnRam::Zeros => "zeros",
+            PowerOnRam::Ones => "ones",
+            PowerOnRam::Pattern => "pattern",
+            PowerOnRam::Random => "random",
+        }
+    }
+
+    #[must_use]
+    pub fn from_config_str(val: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|ram| ram.config_str() == val.to_ascii_lowercase())
+    }
+
+    /// The `power_on_ram` key of config.toml, zeros when it's missing or unrecognised
+    #[must_use]
+    pub fn from_config() -> Self {
+        Config::get_string("power_on_ram")
+            .and_then(|val| Self::from_config_str(&val))
+            .unwrap_or_default()
+    }
+
+    pub fn fill(self, ram: &mut [u8]) {
+        match self {
+            PowerOnRam::Zeros => ram.fill(0),
+            PowerOnRam::Ones => ram.fill(0xff),
+            PowerOnRam::Pattern => ram
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, val)| *val = if i & 4 == 0 { 0 } else { 0xff }),
+            PowerOnRam::Random => rand::Rng::fill(&mut rand::thread_rng(), ram),
+        }
+    }
+}
 
 pub struct Bus {
     cpu_ram: [u8; RAM_SIZE],
     pub ppu: PPU,
     pub apu: APU,
-    pub joypad: Joypad,
-    pub mapper: SharedMapper,
+    // Controllers 1 and 2
+    pub joypads: [Joypad; 2],
+    pub expansion: Option<BoxedExpansionDevice>,
+    // The cartridge. The PPU borrows it while it runs rather than keeping its own handle
+    pub mapper: Box<dyn Mapper + Send>,
+    // Last value on the CPU data bus, which is what reads of undriven bits see
+    open_bus: u8,
+    // The CPU cycle in progress. When a $4015 write starts the DMC depends on its parity
+    cpu_cycle: u64,
 }
 
 impl Bus {
     pub fn new(file: &NESFile) -> Bus {
-        let mapper = Arc::new(Mutex::new(MapperFactory::from_file(file)));
+        Self::with_expansion(file, ExpansionDeviceFactory::from_file(file))
+    }
+
+    /// Same as `new`, with the expansion device picked by the caller instead of config.toml
+    pub fn with_expansion(file: &NESFile, expansion: Option<BoxedExpansionDevice>) -> Bus {
         Bus {
             cpu_ram: [0; RAM_SIZE],
-            mapper: mapper.clone(),
-            joypad: Joypad::default(),
-            ppu: PPU::new(mapper),
+            mapper: MapperFactory::from_file(file),
+            joypads: [Joypad::default(), Joypad::default()],
+            expansion,
+            ppu: PPU::new(),
             apu: APU::new(),
+            open_bus: 0,
+            cpu_cycle: 0,
         }
     }
 
+    /// Fills CPU RAM as it would be at power on. `new` starts it zeroed
+    pub fn init_ram(&mut self, ram: PowerOnRam) {
+        ram.fill(&mut self.cpu_ram);
+    }
+
+    /// Puts the CPU RAM, PPU, APU and cartridge back in their power on state. Battery backed PRG RAM
+    /// keeps its contents, everything else starts over as if `file` had just been inserted
+    pub fn power_cycle(&mut self, file: &NESFile, ram: PowerOnRam) {
+        self.init_ram(ram);
+
+        let mut mapper = MapperFactory::from_file(file);
+        if file.header.flags1.get(Flags1Enum::BATTERY) != 0 {
+            // It's the same cartridge, so its save always fits
+            mapper.load_save(&self.mapper.dump_save()).ok();
+        }
+        self.mapper = mapper;
+        self.ppu.power_cycle();
+        self.apu.power_cycle();
+    }
+
+    /// The parts of the console wired to the reset button. CPU RAM is left alone, and so is the
+    /// cartridge, since none of the supported boards see the reset line
+    pub fn reset(&mut self) {
+        self.ppu.reset();
+        self.ppu.nmi_generated = false;
+        self.apu.reset();
+    }
+
     pub fn read_trace(&self, addr: u16) -> u8 {
         match addr {
             RAM_START..=RAM_END => self.cpu_ram[(addr & 0x07FF) as usize],
-            PPU_REG_START..=PPU_REG_END => self.ppu.read_ppudata_trace(addr as usize),
+            PPU_REG_START..=PPU_REG_END => {
+                self.ppu.read_ppudata_trace(addr as usize, &*self.mapper)
+            }
             APU_IO_START..=APU_IO_END => self.read_apu_trace(addr),
-            _ => self.mapper.lock().unwrap().read(addr),
+            EXPANSION_START..=EXPANSION_END => self
+                .mapper
+                .peek_expansion_area(addr)
+                .unwrap_or(self.open_bus),
+            _ => self.mapper.read(addr),
         }
     }
 
     pub fn read_apu_trace(&self, addr: u16) -> u8 {
-        let mapped_addr = (addr - APU_IO_START) % 0x1F;
+        let mapped_addr = addr - APU_IO_START;
         match mapped_addr {
-            0x16 => self.joypad.read_trace(),
-            // Controller 2 data disabled -- always return 0
-            0x17 => 0,
-            0x15 => self.apu.read_status_trace(),
-            _ => self.ppu.open_bus,
+            0x16 => {
+                self.joypads[0].read_trace()
+                    | self.read_expansion_trace(addr)
+                    | (self.open_bus & JOYPAD_OPEN_BUS)
+            }
+            0x17 => {
+                self.joypads[1].read_trace()
+                    | self.read_expansion_trace(addr)
+                    | (self.open_bus & JOYPAD_OPEN_BUS)
+            }
+            0x15 => self.apu.read_status_trace() | (self.open_bus & STATUS_OPEN_BUS),
+            _ => self.open_bus,
         }
     }
 
@@ -61,77 +191,51 @@ impl Bus {
         (high as u16) << 8 | low as u16
     }
 
-    pub fn read(&mut self, addr: u16) -> (u8, IRQSignal) {
-        let mut signal = IRQSignal::None;
-        let val = match addr {
-            RAM_START..=RAM_END => self.cpu_ram[(addr & 0x07FF) as usize],
-            PPU_REG_START..=PPU_REG_END => self.execute_ppu_read(addr),
-            APU_IO_START..=APU_IO_END => {
-                let ret = self.execute_apu_io_read(addr);
-                signal = ret.1;
-                ret.0
+    fn execute_apu_io_read(&mut self, addr: u16) -> u8 {
+        let mapper_addr = addr - APU_IO_START;
+        match mapper_addr {
+            0x16 => {
+                self.joypads[0].read()
+                    | self.read_expansion(addr)
+                    | (self.open_bus & JOYPAD_OPEN_BUS)
             }
-            _ => self.mapper.lock().unwrap().read(addr),
-        };
-        (val, signal)
-    }
-
-    pub fn write(&mut self, addr: u16, data: u8, cpu_cycle: u64) -> IRQSignal {
-        let mut signal = IRQSignal::None;
-        match addr {
-            RAM_START..=RAM_END => self.cpu_ram[(addr & 0x07FF) as usize] = data,
-            PPU_REG_START..=PPU_REG_END => self.execute_ppu_write(addr, data),
-            APU_IO_START..=APU_IO_END => signal = self.execute_apu_io_write(addr, data, cpu_cycle),
-            _ => self.mapper.lock().unwrap().write(addr, data),
-        }
-        signal
-    }
-
-    fn execute_apu_io_read(&mut self, addr: u16) -> (u8, IRQSignal) {
-        let mapper_addr = (addr - APU_IO_START) % 0x1F;
-        let mut signal = IRQSignal::None;
-        let val = match mapper_addr {
-            0x16 => self.joypad.read(),
-            // Controller 2 data disabled -- always return 0
-            0x17 => 0,
-            0x15 => {
-                let ret = self.apu.read_status();
-                signal = ret.1;
-                ret.0
+            0x17 => {
+                self.joypads[1].read()
+                    | self.read_expansion(addr)
+                    | (self.open_bus & JOYPAD_OPEN_BUS)
             }
-            _ => self.ppu.open_bus,
-        };
-        (val, signal)
+            0x15 => self.apu.read_status() | (self.open_bus & STATUS_OPEN_BUS),
+            // Write-only registers, and the CPU test registers at $4018-$401F
+            _ => self.open_bus,
+        }
     }
 
-    fn execute_apu_io_write(&mut self, addr: u16, data: u8, cpu_cycle: u64) -> IRQSignal {
-        let mapped_addr = (addr - APU_IO_START) % 0x1F;
-        let mut signal = IRQSignal::None;
+    fn execute_apu_io_write(&mut self, addr: u16, data: u8) {
+        let mapped_addr = addr - APU_IO_START;
         match mapped_addr {
-            0x00 => self.apu.write_ctrl(&AudioChannel::Pulse1, data),
-            0x01 => self.apu.write_sweep(&AudioChannel::Pulse1, data),
-            0x02 => self.apu.write_timer_lo(&AudioChannel::Pulse1, data),
-            0x03 => self.apu.write_timer_hi(&AudioChannel::Pulse1, data),
-            0x04 => self.apu.write_ctrl(&AudioChannel::Pulse2, data),
-            0x05 => self.apu.write_sweep(&AudioChannel::Pulse2, data),
-            0x06 => self.apu.write_timer_lo(&AudioChannel::Pulse2, data),
-            0x07 => self.apu.write_timer_hi(&AudioChannel::Pulse2, data),
-            0x08 => self.apu.write_ctrl(&AudioChannel::Triangle, data),
-            0x09 => {}
-            0x0A => self.apu.write_timer_lo(&AudioChannel::Triangle, data),
-            0x0B => self.apu.write_timer_hi(&AudioChannel::Triangle, data),
-            0x0C..=0x0F => {}
-            0x10 => self.apu.write_dmc_ctrl(data),
-            0x11 => self.apu.write_dmc_load(data),
-            0x12 => self.apu.write_dmc_addr(data),
-            0x13 => self.apu.write_dmc_lc(data),
             0x14 => self.ppu.write_oamdma(data),
-            0x15 => self.apu.write_status(data, cpu_cycle),
-            0x16 => self.joypad.write(data),
-            0x17 => signal = self.apu.write_frame_counter(data),
-            _ => unreachable!(),
+            0x16 => {
+                self.joypads
+                    .iter_mut()
+                    .for_each(|joypad| joypad.write(data));
+                if let Some(expansion) = &mut self.expansion {
+                    expansion.write(data);
+                }
+            }
+            _ => self.apu.write_register(addr, data, self.cpu_cycle),
         }
-        signal
+    }
+
+    fn read_expansion(&mut self, addr: u16) -> u8 {
+        self.expansion
+            .as_mut()
+            .map_or(0, |expansion| expansion.read(addr))
+    }
+
+    fn read_expansion_trace(&self, addr: u16) -> u8 {
+        self.expansion
+            .as_ref()
+            .map_or(0, |expansion| expansion.read_trace(addr))
     }
 
     fn execute_ppu_read(&mut self, addr: u16) -> u8 {
@@ -150,7 +254,7 @@ impl Bus {
                 open_bus_mask = 0x0;
                 self.ppu.read_oamdata()
             }
-            7 => self.ppu.read_ppudata(&mut open_bus_mask),
+            7 => self.ppu.read_ppudata(&mut open_bus_mask, &mut *self.mapper),
             _ => unreachable!(),
         };
         self.ppu.apply_open_bus(open_bus_mask, ret)
@@ -162,6 +266,7 @@ impl Bus {
         }
         let mapped_addr = (addr - PPU_REG_START) % 8;
         match mapped_addr {
+            0 | 1 | 5 | 6 if self.ppu.warming_up() => {}
             0 => self.ppu.write_ppuctrl(data),
             1 => self.ppu.write_ppumask(data),
             2 => println!("Attempted to write to read-only PPU register 0x2002"),
@@ -169,16 +274,98 @@ impl Bus {
             4 => self.ppu.write_oamdata(data),
             5 => self.ppu.write_ppuscroll(data),
             6 => self.ppu.write_ppuaddr(data),
-            7 => self.ppu.write_ppudata(data),
+            7 => self.ppu.write_ppudata(data, &mut *self.mapper),
             _ => unreachable!(),
         }
     }
+}
+
+impl CpuBus for Bus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = match addr {
+            RAM_START..=RAM_END => self.cpu_ram[(addr & 0x07FF) as usize],
+            PPU_REG_START..=PPU_REG_END => self.execute_ppu_read(addr),
+            APU_IO_START..=APU_IO_END => self.execute_apu_io_read(addr),
+            EXPANSION_START..=EXPANSION_END => self
+                .mapper
+                .read_expansion_area(addr)
+                .unwrap_or(self.open_bus),
+            _ => self.mapper.read(addr),
+        };
+        self.open_bus = val;
+        val
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        match addr {
+            RAM_START..=RAM_END => self.cpu_ram[(addr & 0x07FF) as usize] = data,
+            PPU_REG_START..=PPU_REG_END => self.execute_ppu_write(addr, data),
+            APU_IO_START..=APU_IO_END => self.execute_apu_io_write(addr, data),
+            _ => self.mapper.write(addr, data),
+        }
+    }
 
-    pub fn set_nmi_generated(&mut self, nmi: bool) {
-        self.ppu.nmi_generated = nmi;
+    fn peek(&self, addr: u16) -> u8 {
+        self.read_trace(addr)
     }
 
-    pub(crate) fn run_to(&mut self, cyc: u64) {
-        self.ppu.run_to(cyc);
+    fn tick(&mut self, cycle: u64) {
+        self.cpu_cycle = cycle;
+        self.mapper.tick();
+        self.apu.set_expansion_output(self.mapper.expansion_audio());
+        self.apu.clock();
+    }
+
+    fn run_to(&mut self, master_clock: u64) {
+        self.ppu.run_to(master_clock, &mut *self.mapper);
+    }
+
+    fn nmi(&self) -> bool {
+        self.ppu.nmi_generated
+    }
+
+    fn irq(&self) -> IRQSource {
+        let mut source = IRQSource::empty();
+        source.set(IRQSource::FRAME_COUNTER, self.apu.irq_pending());
+        source.set(IRQSource::DMC, self.apu.dmc.irq_flag);
+        source.set(IRQSource::EXT, self.mapper.irq());
+        source
+    }
+
+    fn take_oam_dma(&mut self) -> Option<u8> {
+        match std::mem::replace(&mut self.ppu.sprite_dma_transfer, DMAFlag::Disabled) {
+            DMAFlag::Enabled(page) => Some(page),
+            DMAFlag::Disabled => None,
+        }
+    }
+
+    fn dmc_dma_requested(&self) -> bool {
+        self.apu.need_dmc_transfer()
+    }
+
+    fn dmc_dma_addr(&self) -> u16 {
+        self.apu.dmc.current_addr
+    }
+
+    fn finish_dmc_dma(&mut self, data: u8) {
+        self.apu.dmc.set_dmc_read_buffer(data);
+    }
+
+    fn reset(&mut self) {
+        Bus::reset(self);
+    }
+
+    fn ppu_position(&self) -> (i16, u64) {
+        (self.ppu.scanline, self.ppu.cycle)
     }
 }
+
+// The expansion device only holds input, which the host sets
+savestate!(Bus {
+    cpu_ram,
+    ppu,
+    apu,
+    joypads,
+    mapper,
+});
@@ -2,23 +2,36 @@ use std::{
     io::Write,
     path::PathBuf,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
-use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-    SampleRate, StreamConfig, StreamError,
+use crossbeam::channel::{Receiver, TryRecvError};
+use crate::{
+    config::Config,
+    emulator::Emulator,
+    frontend::{audio::AudioOutput, egui::ConsoleMsg, speed::EmulationSpeed},
+    ines_parser::NESFile,
+};
+use super::{
+    apu::{self, filter::FilterPreset, mixer::MixerSettings},
+    bus::PowerOnRam,
+    expansion::ExpansionDeviceFactory,
+    ppu::palettes::PaletteSource,
 };
-use crossbeam::channel::Receiver;
-use crate::{config::Config, frontend::egui::ConsoleMsg, ines_parser::NESFile};
-use super::{apu, bus::Bus, cpu::CPU};
 
-pub struct Console<'a> {
-    pub cpu: CPU<'a>,
+/// The emulator as the desktop app runs it: set up from config.toml and driven from its own
+/// thread with the sound card attached
+pub struct Console {
+    pub emulator: Emulator,
     pub rom_hash: u64,
 }
 
-impl Console<'_> {
+impl Console {
     pub fn new(rom: NESFile) -> Self {
-        let mut cpu = CPU::new(Bus::new(&rom));
+        let rom_hash = rom.hash;
+        let expansion = ExpansionDeviceFactory::from_file(&rom);
+        let mut emulator = Emulator::new(rom);
+        let cpu = emulator.cpu_mut();
+        cpu.bus.expansion = expansion;
 
         if Config::get_bool("enable_logging", false) {
             cpu.set_sink(Box::new(
@@ -31,116 +44,196 @@ impl Console<'_> {
             ));
             cpu.enable_logging();
         }
-        cpu.reset();
-
-        Console {
-            cpu,
-            rom_hash: rom.hash,
+        if let Some(preset) = Config::get_string("audio_filter")
+            .and_then(|val| FilterPreset::from_config_str(&val))
+        {
+            cpu.bus.apu.set_filter_preset(preset);
+        }
+        cpu.bus.apu.set_mixer(MixerSettings::from_config());
+        match PaletteSource::from_config().load() {
+            Ok(palette) => cpu.bus.ppu.set_palette(palette),
+            Err(e) => println!("Failed to load palette, using the default: {e}"),
         }
+        cpu.bus
+            .ppu
+            .set_remove_sprite_limit(Config::get_bool("remove_sprite_limit", false));
+        cpu.bus.init_ram(PowerOnRam::from_config());
+        cpu.set_xaa_magic(Config::get_int("xaa_magic", 0xeei64) as u8);
+
+        Console { emulator, rom_hash }
+    }
+
+    fn save_path(&self, extension: &str) -> Option<PathBuf> {
+        let mut path = PathBuf::from(Config::get_string("save_directory")?);
+        path.push(format!("{}.{extension}", self.rom_hash));
+        Some(path)
     }
 
     pub fn dump_save_to_path(&self, file: PathBuf) -> std::io::Result<()> {
-        let mapper = self.cpu.bus.mapper.lock().unwrap();
-        let save = mapper.dump_save();
         std::fs::File::options()
             .create(true)
             .write(true)
             .truncate(true)
             .open(file)?
-            .write_all(save)?;
+            .write_all(&self.emulator.battery_save())?;
         Ok(())
     }
 
     pub fn dump_save(&self) -> std::io::Result<()> {
-        if let Some(save_dir_str) = Config::get_string("save_directory") {
-            let mut save_path = PathBuf::from(save_dir_str);
-            save_path.push(format!("{}.sav", self.rom_hash));
+        if let Some(save_path) = self.save_path("sav") {
             println!("{}", save_path.to_str().unwrap());
             self.dump_save_to_path(save_path)?;
         }
         Ok(())
     }
 
-    pub fn load_save(&self, file: PathBuf) -> std::io::Result<()> {
+    pub fn load_save(&mut self, file: PathBuf) -> std::io::Result<()> {
         let save = std::fs::read(file)?;
-        let mut mapper = self.cpu.bus.mapper.lock().unwrap();
-        mapper.load_save(save.as_slice());
+        self.emulator.load_battery_save(save.as_slice())
+    }
+
+    /// Writes a save state next to the battery save
+    pub fn save_state(&self) -> std::io::Result<()> {
+        if let Some(state_path) = self.save_path("state") {
+            std::fs::write(state_path, self.emulator.save_state())?;
+        }
+        Ok(())
+    }
+
+    pub fn load_state(&mut self) -> std::io::Result<()> {
+        if let Some(state_path) = self.save_path("state") {
+            self.emulator.load_state(&std::fs::read(state_path)?)?;
+        }
         Ok(())
     }
 
-    pub fn run_thread(console: Arc<Mutex<Console<'static>>>, recv: Receiver<ConsoleMsg>) {
-        let (audio_send, audio_recv) = crossbeam::channel::bounded::<i16>(4096);
-        let (stream, sample_rate) = Console::setup_audio(audio_recv);
-        let mut samples = Vec::with_capacity(16);
+    pub fn run_thread(console: Arc<Mutex<Console>>, recv: Receiver<ConsoleMsg>) {
+        let audio = AudioOutput::new();
+        let mut samples = Vec::with_capacity(1024);
 
         {
             let mut console = console.lock().unwrap();
-            console
-                .cpu
-                .bus
-                .apu
-                .output_buffer
-                .set_rates(apu::APU::CLOCK_RATE, sample_rate.0 as f64);
+            console.emulator.set_sample_rate(audio.sample_rate());
         }
 
+        let mut speed = EmulationSpeed::default();
+        let mut frame_advance = false;
+        let mut next_frame = Instant::now();
+
         loop {
-            for msg in recv.try_iter() {
+            loop {
+                let msg = match recv.try_recv() {
+                    Ok(msg) => msg,
+                    Err(TryRecvError::Empty) => break,
+                    // The app dropped its end to load something else
+                    Err(TryRecvError::Disconnected) => return,
+                };
                 let mut console = console.lock().unwrap();
                 match msg {
                     ConsoleMsg::JoypadDown(button) => {
-                        console.cpu.bus.joypad.buttons.set(button, true)
+                        let buttons = console.emulator.input(0) | button;
+                        console.emulator.set_input(0, buttons);
                     }
                     ConsoleMsg::JoypadUp(button) => {
-                        console.cpu.bus.joypad.buttons.set(button, false)
+                        let buttons = console.emulator.input(0) - button;
+                        console.emulator.set_input(0, buttons);
+                    }
+                    ConsoleMsg::KeyboardDown(key) => {
+                        if let Some(expansion) = &mut console.emulator.cpu_mut().bus.expansion {
+                            expansion.set_key(key, true);
+                        }
+                    }
+                    ConsoleMsg::KeyboardUp(key) => {
+                        if let Some(expansion) = &mut console.emulator.cpu_mut().bus.expansion {
+                            expansion.set_key(key, false);
+                        }
                     }
-                    ConsoleMsg::RunFrame => {
-                        // Exectue
-                        console.cpu.run_until_frame();
-
-                        // Audio
-                        console.cpu.bus.apu.output_buffer.end_frame(&mut samples);
-                        for sample in samples.iter() {
-                            audio_send.try_send(*sample).ok();
+                    ConsoleMsg::SetSpeed(new_speed) => {
+                        if speed.paused && !new_speed.paused {
+                            next_frame = Instant::now();
+                        }
+                        if new_speed.paused {
+                            audio.pause();
+                        } else {
+                            audio.play();
                         }
-                        stream.play().unwrap();
-                        samples.clear();
+                        speed = new_speed;
                     }
+                    ConsoleMsg::FrameAdvance => frame_advance = true,
+                    ConsoleMsg::SetMixer(mixer) => {
+                        console.emulator.cpu_mut().bus.apu.set_mixer(mixer)
+                    }
+                    ConsoleMsg::SetPalette(palette) => {
+                        // Recolour the current frame so the change shows while paused
+                        let ppu = &mut console.emulator.cpu_mut().bus.ppu;
+                        ppu.curr_frame.repalettize(&palette);
+                        ppu.set_palette(*palette);
+                    }
+                    ConsoleMsg::RemoveSpriteLimit(remove) => console
+                        .emulator
+                        .cpu_mut()
+                        .bus
+                        .ppu
+                        .set_remove_sprite_limit(remove),
+                    ConsoleMsg::SetRenderLayers(layers) => {
+                        console.emulator.cpu_mut().bus.ppu.set_render_layers(layers)
+                    }
+                    ConsoleMsg::Reset => console.emulator.reset(),
+                    ConsoleMsg::PowerCycle(ram) => console.emulator.power_cycle(ram),
+                    ConsoleMsg::InsertDisk(side) => console.emulator.insert_disk(side),
+                    // Only means something to the NSF player
+                    ConsoleMsg::PlaySong(_) => {}
                 }
             }
-        }
-    }
 
-    pub fn setup_audio(audio_recv: Receiver<i16>) -> (cpal::Stream, SampleRate) {
-        let host = cpal::default_host();
-        let device = host.default_output_device().unwrap();
-
-        let default_config: StreamConfig = device.default_output_config().unwrap().into();
-        let sample_rate = default_config.sample_rate;
-        let channels = default_config.channels;
-
-        let stream = device
-            .build_output_stream(
-                &default_config,
-                move |buf: &mut [i16], _: &cpal::OutputCallbackInfo| {
-                    Console::stream_callback(buf, audio_recv.clone(), channels);
-                },
-                |e: StreamError| {
-                    dbg!(e);
-                },
-                None,
-            )
-            .unwrap();
-        (stream, sample_rate)
-    }
+            let now = Instant::now();
+            let run_frame = if speed.paused {
+                std::mem::take(&mut frame_advance)
+            } else {
+                speed.turbo || now >= next_frame
+            };
+
+            if !run_frame {
+                // Sleep most of the way to the next frame and yield for the rest, thread::sleep
+                // alone is too coarse to hold 60.0988 fps
+                let wait = if speed.paused {
+                    Duration::from_millis(1)
+                } else {
+                    next_frame - now
+                };
+                if wait > Duration::from_millis(2) {
+                    std::thread::sleep(wait - Duration::from_millis(1));
+                } else {
+                    std::thread::yield_now();
+                }
+                continue;
+            }
 
-    fn stream_callback(buf: &mut [i16], audio_recv: Receiver<i16>, channels: u16) {
-        let requested = buf.len();
-        let sample_iter = audio_recv.try_iter().take(requested / channels as usize);
+            let mut console = console.lock().unwrap();
+
+            // Execute
+            console.emulator.step_frame();
+
+            // Audio
+            console.emulator.drain_audio(&mut samples);
+            if !speed.paused && !speed.should_mute() {
+                audio.push_samples(&samples, console.emulator.audio_channels());
+                console
+                    .emulator
+                    .cpu_mut()
+                    .bus
+                    .apu
+                    .output_buffer
+                    .set_rates(apu::APU::CLOCK_RATE, audio.adjusted_sample_rate());
+            }
+            samples.clear();
 
-        let mut i = 0;
-        for sample in sample_iter {
-            buf[i..(i + channels as usize)].fill(sample);
-            i += channels as usize;
+            // Frames are scheduled on a fixed grid so timing errors don't accumulate. After a
+            // stall, restart the grid from now instead of running frames back to back to catch up
+            next_frame += speed.frame_duration();
+            if next_frame < now {
+                next_frame = now + speed.frame_duration();
+            }
         }
     }
 }
@@ -0,0 +1,64 @@
+use super::IRQSource;
+
+/// Whatever the CPU is wired to. On the NES that's `Bus`, with the PPU, APU and cartridge behind
+/// it, and `RamBus` is plain memory, but anything with a 16-bit address space will do. Only
+/// `read`, `write` and `peek` have to be written, the rest default to a bus with nothing on it
+/// that can interrupt or steal cycles
+pub trait CpuBus {
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, data: u8);
+
+    /// Reads without side effects, for the tracer
+    fn peek(&self, addr: u16) -> u8;
+
+    fn peek_16(&self, addr: u16) -> u16 {
+        let low = self.peek(addr);
+        let high = self.peek(addr.wrapping_add(1));
+        (high as u16) << 8 | low as u16
+    }
+
+    /// Called at the start of every CPU cycle, before its read or write, with the cycle's number.
+    /// Devices clocked by the CPU, like the APU, step here
+    fn tick(&mut self, _cycle: u64) {}
+
+    /// Runs devices on the master clock, like the PPU, up to `master_clock`. Called twice a CPU
+    /// cycle, once around the access and once at the end
+    fn run_to(&mut self, _master_clock: u64) {}
+
+    /// The NMI line, the CPU acts on it going high
+    fn nmi(&self) -> bool {
+        false
+    }
+
+    /// Everything holding the IRQ line low
+    fn irq(&self) -> IRQSource {
+        IRQSource::empty()
+    }
+
+    /// A page to copy to $2004 that the CPU hasn't started on yet. Taking it clears the request
+    fn take_oam_dma(&mut self) -> Option<u8> {
+        None
+    }
+
+    /// Whether the DMC wants a sample byte, checked after every `tick`
+    fn dmc_dma_requested(&self) -> bool {
+        false
+    }
+
+    /// Where the DMC's next sample byte comes from
+    fn dmc_dma_addr(&self) -> u16 {
+        0
+    }
+
+    /// Hands the DMC the byte it asked for
+    fn finish_dmc_dma(&mut self, _data: u8) {}
+
+    /// Pressing the reset button, for whatever besides the CPU sees it
+    fn reset(&mut self) {}
+
+    /// Scanline and dot for the trace log
+    fn ppu_position(&self) -> (i16, u64) {
+        (0, 0)
+    }
+}
@@ -1,4 +1,4 @@
-use crate::core::cpu::{Register, Status, CPU};
+use crate::core::cpu::{CpuBus, Register, Status, CPU};
 
 pub enum ArithOp {
     ADC,
@@ -37,7 +37,7 @@ pub trait Arithmetic {
     }
 }
 
-impl Arithmetic for CPU<'_> {
+impl<B: CpuBus> Arithmetic for CPU<B> {
     fn add(&mut self, val: u8) {
         let res = self.acc as u16 + val as u16 + u16::from(self.status.contains(Status::CARRY));
         self.set_zero_neg_flags(val);
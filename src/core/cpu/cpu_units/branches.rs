@@ -1,4 +1,4 @@
-use crate::core::cpu::{Status, CPU};
+use crate::core::cpu::{CpuBus, Status, CPU};
 
 pub trait Branches {
     fn branch_relative(&mut self, flag: Status, set: bool);
@@ -36,7 +36,7 @@ pub trait Branches {
     }
 }
 
-impl Branches for CPU<'_> {
+impl<B: CpuBus> Branches for CPU<B> {
     fn branch_relative(&mut self, flag: Status, set: bool) {
         let branch = if set {
             self.status.contains(flag)
@@ -49,13 +49,15 @@ impl Branches for CPU<'_> {
                 self.run_irq = false;
             }
             self.dummy_read();
-            if Self::check_page_crossed_i8(self.pc, offset) {
-                self.dummy_read();
-            }
-            self.pc = match offset < 0 {
+            let target = match offset < 0 {
                 true => self.pc.wrapping_sub(offset.unsigned_abs() as u16),
                 false => self.pc.wrapping_add(offset as u16),
             };
+            if Self::check_page_crossed_i8(self.pc, offset) {
+                // The low byte is added first, the high byte is fixed up a cycle later
+                self.memory_read((self.pc & 0xff00) | (target & 0xff));
+            }
+            self.pc = target;
         }
     }
 }
@@ -1,4 +1,4 @@
-use crate::core::cpu::{Status, CPU};
+use crate::core::cpu::{CpuBus, Status, CPU};
 
 pub trait FlagChanges {
     fn flag(&mut self, flag: Status, set: bool);
@@ -32,7 +32,7 @@ pub trait FlagChanges {
     }
 }
 
-impl FlagChanges for CPU<'_> {
+impl<B: CpuBus> FlagChanges for CPU<B> {
     fn flag(&mut self, flag: Status, set: bool) {
         self.status.set(flag, set);
     }
@@ -1,4 +1,4 @@
-use crate::core::cpu::{Register, CPU};
+use crate::core::cpu::{CpuBus, Register, CPU};
 
 use super::arithmetic::Arithmetic;
 
@@ -31,7 +31,7 @@ pub(crate) trait IncDecOps: Arithmetic {
     fn dcp(&mut self);
 }
 
-impl IncDecOps for CPU<'_> {
+impl<B: CpuBus> IncDecOps for CPU<B> {
     fn inc_dec(&mut self, op: IncDec) {
         let addr = self.operand;
         let val = self.memory_read(addr);
@@ -1,4 +1,4 @@
-use crate::core::cpu::{AddressingMode, CPU};
+use crate::core::cpu::{AddressingMode, CpuBus, CPU};
 
 pub trait Jumps {
     fn jmp_to_addr(&mut self, addr: u16);
@@ -10,7 +10,7 @@ pub trait Jumps {
     fn rts(&mut self);
 }
 
-impl Jumps for CPU<'_> {
+impl<B: CpuBus> Jumps for CPU<B> {
     fn jmp_to_addr(&mut self, addr: u16) {
         self.pc = addr
     }
@@ -28,17 +28,21 @@ impl Jumps for CPU<'_> {
         }
     }
 
+    // Only the low byte of the address has been fetched, the high byte is read last, after the
+    // address of it has been pushed
     fn jsr(&mut self) {
-        let addr = self.operand;
-        self.dummy_read();
-        self.push_word(self.pc - 1);
-        self.jmp_to_addr(addr);
+        let lo = self.read_byte();
+        self.dummy_stack_read();
+        self.push_word(self.pc);
+        let hi = self.memory_read(self.pc);
+        self.jmp_to_addr((hi as u16) << 8 | lo as u16);
     }
 
     fn rts(&mut self) {
+        self.dummy_stack_read();
         let addr = self.pop_word();
-        self.dummy_read();
-        self.dummy_read();
-        self.pc = addr + 1;
+        // Reads the last byte of the JSR before stepping past it
+        self.memory_read(addr);
+        self.pc = addr.wrapping_add(1);
     }
 }
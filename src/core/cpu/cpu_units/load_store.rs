@@ -1,4 +1,4 @@
-use crate::core::cpu::{Register, CPU};
+use crate::core::cpu::{CpuBus, Register, CPU};
 
 pub trait LoadStore {
     fn ld(&mut self, reg: Register);
@@ -7,6 +7,8 @@ pub trait LoadStore {
 
     fn lax(&mut self);
 
+    fn las(&mut self);
+
     fn lda(&mut self) {
         self.ld(Register::A);
     }
@@ -34,7 +36,7 @@ pub trait LoadStore {
     }
 }
 
-impl LoadStore for CPU<'_> {
+impl<B: CpuBus> LoadStore for CPU<B> {
     fn ld(&mut self, reg: Register) {
         let val = self.get_operand_val();
         self.set_register(reg, val);
@@ -58,6 +60,13 @@ impl LoadStore for CPU<'_> {
         self.set_register(Register::A, val);
     }
 
+    fn las(&mut self) {
+        let val = self.get_operand_val() & self.sp;
+        self.sp = val;
+        self.set_register(Register::X, val);
+        self.set_register(Register::A, val);
+    }
+
     fn sax(&mut self) {
         self.memory_write(self.operand, self.acc & self.x);
     }
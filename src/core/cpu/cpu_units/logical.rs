@@ -1,4 +1,4 @@
-use crate::core::cpu::{Register, Status, CPU};
+use crate::core::cpu::{CpuBus, Register, Status, CPU};
 
 pub enum LogicalOp {
     EOR,
@@ -30,7 +30,7 @@ pub trait Logical {
     }
 }
 
-impl Logical for CPU<'_> {
+impl<B: CpuBus> Logical for CPU<B> {
     fn bit_op(&mut self, op: LogicalOp) {
         let val = self.get_operand_val();
         self.set_register(
@@ -1,11 +1,13 @@
 use crate::core::cpu::cpu_units::load_store::LoadStore;
-use crate::core::cpu::{Register, CPU};
+use crate::core::cpu::{CpuBus, Register, CPU};
 
 pub trait RegisterTransfer {
     fn transfer(&mut self, from: Register, to: Register);
 
     fn lxa(&mut self);
 
+    fn xaa(&mut self);
+
     fn tax(&mut self) {
         self.transfer(Register::A, Register::X);
     }
@@ -31,7 +33,7 @@ pub trait RegisterTransfer {
     }
 }
 
-impl RegisterTransfer for CPU<'_> {
+impl<B: CpuBus> RegisterTransfer for CPU<B> {
     fn transfer(&mut self, from: Register, to: Register) {
         let val_from = match from {
             Register::A => self.acc,
@@ -49,4 +51,11 @@ impl RegisterTransfer for CPU<'_> {
         self.lax();
         self.set_register(Register::A, self.acc);
     }
+
+    // Unstable on real chips, the bits of A that survive depend on the chip and its temperature.
+    // `xaa_magic` stands in for them
+    fn xaa(&mut self) {
+        let val = self.get_operand_val();
+        self.set_register(Register::A, (self.acc | self.xaa_magic) & self.x & val);
+    }
 }
@@ -1,4 +1,4 @@
-use crate::core::cpu::{AddressingMode, Register, Status, CPU};
+use crate::core::cpu::{AddressingMode, CpuBus, Register, Status, CPU};
 
 use super::{arithmetic::Arithmetic, logical::Logical};
 
@@ -12,7 +12,7 @@ pub enum ShiftOp {
 pub(crate) trait Shift: Logical + Arithmetic {
     fn shift(&mut self, val: u8, op: ShiftOp) -> u8;
 
-    fn sh(&mut self, reg: Register);
+    fn sh_unstable(&mut self, val: u8, index: u8);
 
     fn get_shift_val(&mut self, op: ShiftOp);
 
@@ -40,16 +40,16 @@ pub(crate) trait Shift: Logical + Arithmetic {
 
     fn rra(&mut self);
 
-    fn shy(&mut self) {
-        self.sh(Register::Y);
-    }
+    fn shy(&mut self);
 
-    fn shx(&mut self) {
-        self.sh(Register::X);
-    }
+    fn shx(&mut self);
+
+    fn sha(&mut self);
+
+    fn tas(&mut self);
 }
 
-impl Shift for CPU<'_> {
+impl<B: CpuBus> Shift for CPU<B> {
     fn shift(&mut self, val: u8, op: ShiftOp) -> u8 {
         let res = match op {
             ShiftOp::ASL => {
@@ -122,15 +122,33 @@ impl Shift for CPU<'_> {
         self.memory_write(self.operand, shifted);
     }
 
-    fn sh(&mut self, reg: Register) {
-        let reg = match reg {
-            Register::X => self.x,
-            Register::Y => self.y,
-            _ => panic!("Invalid register"),
+    // Stores `val` ANDed with the high byte of the base address plus one. When indexing crosses a
+    // page, the stored value replaces the high byte of the address too
+    fn sh_unstable(&mut self, val: u8, index: u8) {
+        let base = self.operand.wrapping_sub(index as u16);
+        let val = val & ((base >> 8) as u8).wrapping_add(1);
+        let addr = if base & 0xff00 == self.operand & 0xff00 {
+            self.operand
+        } else {
+            ((val as u16) << 8) | (self.operand & 0xff)
         };
-        let addr_hi = (self.operand >> 8) as u8;
-        let addr_lo = (self.operand & 0xFF) as u8;
-        let val = reg & (addr_hi + 1);
-        self.memory_write(((val as u16) << 8) | addr_lo as u16, val);
+        self.memory_write(addr, val);
+    }
+
+    fn shy(&mut self) {
+        self.sh_unstable(self.y, self.x);
+    }
+
+    fn shx(&mut self) {
+        self.sh_unstable(self.x, self.y);
+    }
+
+    fn sha(&mut self) {
+        self.sh_unstable(self.acc & self.x, self.y);
+    }
+
+    fn tas(&mut self) {
+        self.sp = self.acc & self.x;
+        self.sh_unstable(self.sp, self.y);
     }
 }
@@ -1,4 +1,4 @@
-use crate::core::cpu::{Register, Status, CPU};
+use crate::core::cpu::{CpuBus, Register, Status, CPU};
 
 pub trait StackOps {
     fn pha(&mut self);
@@ -10,13 +10,13 @@ pub trait StackOps {
     fn plp(&mut self);
 }
 
-impl StackOps for CPU<'_> {
+impl<B: CpuBus> StackOps for CPU<B> {
     fn pha(&mut self) {
         self.push(self.acc);
     }
 
     fn pla(&mut self) {
-        self.dummy_read();
+        self.dummy_stack_read();
         let popped = self.pop();
         self.set_register(Register::A, popped);
     }
@@ -27,7 +27,7 @@ impl StackOps for CPU<'_> {
     }
 
     fn plp(&mut self) {
-        self.dummy_read();
+        self.dummy_stack_read();
         self.status = Status::from_bits_truncate(self.pop());
     }
 }
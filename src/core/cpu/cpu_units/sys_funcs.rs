@@ -1,4 +1,4 @@
-use crate::core::cpu::{Status, CPU};
+use crate::core::cpu::{CpuBus, Status, CPU};
 
 pub trait SysFuncs {
     fn nop(&mut self);
@@ -8,15 +8,17 @@ pub trait SysFuncs {
     fn rti(&mut self);
 
     fn irq(&mut self);
+
+    fn jam(&mut self);
 }
 
-impl SysFuncs for CPU<'_> {
+impl<B: CpuBus> SysFuncs for CPU<B> {
     fn nop(&mut self) {
         self.get_operand_val();
     }
 
     fn brk(&mut self) {
-        self.push_word(self.pc + 1);
+        self.push_word(self.pc.wrapping_add(1));
         let flags = self.status.bits() | Status::BREAK.bits() | Status::BREAK2.bits();
         if self.need_nmi {
             self.need_nmi = false;
@@ -32,7 +34,7 @@ impl SysFuncs for CPU<'_> {
     }
 
     fn rti(&mut self) {
-        self.dummy_read();
+        self.dummy_stack_read();
         self.status = Status::from_bits(self.pop()).unwrap();
         self.pc = self.pop_word();
     }
@@ -41,15 +43,22 @@ impl SysFuncs for CPU<'_> {
         self.dummy_read();
         self.dummy_read();
         self.push_word(self.pc);
+        // B only exists in the pushed copy of the flags, interrupts push it clear
+        let flags = (self.status.bits() & !Status::BREAK.bits()) | Status::BREAK2.bits();
         if self.need_nmi {
             self.need_nmi = false;
-            self.push(self.status.bits() | Status::BREAK2.bits());
+            self.push(flags);
             self.status.set(Status::INTERRUPT_DISABLE, true);
             self.pc = self.memory_read_word(0xfffa);
         } else {
-            self.push(self.status.bits() | Status::BREAK2.bits());
+            self.push(flags);
             self.status.set(Status::INTERRUPT_DISABLE, true);
             self.pc = self.memory_read_word(0xfffe);
         }
     }
+
+    // Locks up the CPU until reset. `run` keeps the rest of the console going
+    fn jam(&mut self) {
+        self.jammed = Some(self.pc.wrapping_sub(1));
+    }
 }
@@ -4,22 +4,19 @@ use std::io::{self, Write};
 use bitflags::bitflags;
 
 use crate::config::Config;
-use crate::core::ppu::DMAFlag;
-use crate::core::{apu::frame_counter::IRQSignal, bus::Bus};
-
-use self::{
-    cpu_units::{
-        arithmetic::Arithmetic, branches::Branches, flag_changes::FlagChanges,
-        inc_dec_ops::IncDecOps, jumps::Jumps, load_store::LoadStore, logical::Logical,
-        register_transfer::RegisterTransfer, shift::Shift, stack_ops::StackOps,
-        sys_funcs::SysFuncs,
-    },
-    op::OPS,
-    tracer::Loggable,
-};
+use crate::core::bus::Bus;
+use crate::core::savestate::{savestate, savestate_bits};
 
+use self::{cpu_units::sys_funcs::SysFuncs, tracer::Loggable};
+
+pub use self::cpu_bus::CpuBus;
+pub use self::op::{Op, OPS};
+pub use self::ram_bus::{BusAccess, RamBus};
+
+mod cpu_bus;
 mod cpu_units;
 mod op;
+mod ram_bus;
 mod tracer;
 
 bitflags! {
@@ -77,7 +74,7 @@ pub enum AddressingMode {
     Indirect,
 }
 
-pub struct CPU<'a> {
+pub struct CPU<B: CpuBus = Bus> {
     // Registers
     pub x: u8,
     pub y: u8,
@@ -88,14 +85,13 @@ pub struct CPU<'a> {
     // Status flags
     pub status: Status,
 
-    pub bus: Bus,
+    pub bus: B,
 
     // Logger
     pub sink: Box<dyn Write + Send>,
     logging_enabled: bool,
 
     // Flags
-    irq_flag: IRQSource,
     irq_mask: u8,
     prev_run_irq: bool,
     run_irq: bool,
@@ -119,11 +115,14 @@ pub struct CPU<'a> {
     sprite_dma_offset: u8,
     dmc_dma_running: bool,
 
-    phantom: std::marker::PhantomData<&'a ()>,
+    // Address of the JAM opcode that stopped the CPU, until the next reset
+    jammed: Option<u16>,
+    // Bits of A that XAA keeps, see `set_xaa_magic`
+    xaa_magic: u8,
 }
 
-impl CPU<'_> {
-    pub fn new(bus: Bus) -> Self {
+impl<B: CpuBus> CPU<B> {
+    pub fn new(bus: B) -> Self {
         CPU {
             x: 0,
             y: 0,
@@ -134,7 +133,6 @@ impl CPU<'_> {
             status: Status::from_bits(0x04).unwrap(),
             sink: Box::new(io::sink()),
             logging_enabled: false,
-            irq_flag: IRQSource::empty(),
             need_halt: false,
             run_irq: false,
             start_clock_count: 6,
@@ -154,47 +152,33 @@ impl CPU<'_> {
             sprite_dma_offset: 0,
             irq_mask: 0,
             dmc_dma_running: false,
-            phantom: std::marker::PhantomData,
+            jammed: None,
+            xaa_magic: 0xee,
         }
     }
 
     fn poll_sprite_dma_flag(&mut self) {
-        if let DMAFlag::Enabled(x) = self.bus.ppu.sprite_dma_transfer {
+        if let Some(x) = self.bus.take_oam_dma() {
             self.sprite_dma_transfer = true;
             self.sprite_dma_offset = x;
             self.need_halt = true
         }
     }
 
-    fn set_sprite_dma_transfer(&mut self, val: bool) {
-        self.bus.ppu.sprite_dma_transfer = DMAFlag::Disabled;
-        self.sprite_dma_transfer = val;
-    }
-
     fn read_trace(&self, addr: u16) -> u8 {
-        self.bus.read_trace(addr)
+        self.bus.peek(addr)
     }
 
     fn read_16_trace(&self, addr: u16) -> u16 {
-        self.bus.read_16_trace(addr)
+        self.bus.peek_16(addr)
     }
 
     fn read(&mut self, addr: u16) -> u8 {
-        let ret = self.bus.read(addr);
-        match ret.1 {
-            IRQSignal::Set => self.irq_flag.set(IRQSource::FRAME_COUNTER, true),
-            IRQSignal::Clear => self.irq_flag.set(IRQSource::FRAME_COUNTER, false),
-            IRQSignal::None => {}
-        }
-        ret.0
+        self.bus.read(addr)
     }
 
     fn write(&mut self, addr: u16, val: u8) {
-        match self.bus.write(addr, val, self.cycle_count) {
-            IRQSignal::Set => self.irq_flag.set(IRQSource::FRAME_COUNTER, true),
-            IRQSignal::Clear => self.irq_flag.set(IRQSource::FRAME_COUNTER, false),
-            IRQSignal::None => {}
-        }
+        self.bus.write(addr, val)
     }
 
     fn run_to(&mut self, cyc: u64) {
@@ -209,6 +193,18 @@ impl CPU<'_> {
         self.sink = stream;
     }
 
+    /// The constant ORed into A by the unstable XAA opcode. $EE by default, chips have been
+    /// measured with $00, $EE and $FF among others
+    pub fn set_xaa_magic(&mut self, magic: u8) {
+        self.xaa_magic = magic;
+    }
+
+    /// Where the CPU hit a JAM opcode, if it has since the last reset. A jammed CPU does nothing
+    /// but the rest of the console keeps running, so frames still come out
+    pub const fn jammed(&self) -> Option<u16> {
+        self.jammed
+    }
+
     fn process_pending_dma(&mut self, addr: u16) {
         self.poll_sprite_dma_flag();
         if self.need_halt {
@@ -226,11 +222,9 @@ impl CPU<'_> {
                 if self.cycle_count & 0x01 == 0 {
                     if self.dmc_dma_running && !self.need_halt && !self.need_dummy_read {
                         self.process_cycle();
-                        read_val = self.read(self.bus.apu.dmc.current_addr);
+                        read_val = self.read(self.bus.dmc_dma_addr());
                         self.end_cpu_cycle(true);
-                        if let IRQSignal::Set = self.bus.apu.dmc.set_dmc_read_buffer(read_val) {
-                            self.irq_flag = IRQSource::DMC
-                        }
+                        self.bus.finish_dmc_dma(read_val);
                         self.dmc_dma_running = false;
                     } else if self.sprite_dma_transfer {
                         self.process_cycle();
@@ -252,7 +246,7 @@ impl CPU<'_> {
                     self.end_cpu_cycle(true);
                     sprite_dma_counter += 1;
                     if sprite_dma_counter == 0x200 {
-                        self.set_sprite_dma_transfer(false);
+                        self.sprite_dma_transfer = false;
                     }
                 } else {
                     self.process_cycle();
@@ -284,7 +278,7 @@ impl CPU<'_> {
 
     pub fn memory_read_word(&mut self, addr: u16) -> u16 {
         let lo = self.memory_read(addr);
-        let hi = self.memory_read(addr + 1);
+        let hi = self.memory_read(addr.wrapping_add(1));
         (hi as u16) << 8 | lo as u16
     }
 
@@ -374,13 +368,13 @@ impl CPU<'_> {
 
     fn read_byte(&mut self) -> u8 {
         let val = self.memory_read(self.pc);
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         val
     }
 
     fn read_word(&mut self) -> u16 {
         let val = self.memory_read_word(self.pc);
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         val
     }
 
@@ -419,6 +413,11 @@ impl CPU<'_> {
         self.memory_read(self.pc);
     }
 
+    // Pulls spend a cycle reading the top of the stack before S is incremented
+    fn dummy_stack_read(&mut self) {
+        self.memory_read(0x100 + self.sp as u16);
+    }
+
     fn get_ind_addr(&mut self) -> u16 {
         self.read_word()
     }
@@ -508,10 +507,9 @@ impl CPU<'_> {
     }
 
     pub fn reset(&mut self) {
-        self.bus.set_nmi_generated(false);
-        self.irq_flag = IRQSource::empty();
         self.need_halt = false;
         self.irq_mask = 0xff;
+        self.jammed = None;
 
         self.pc = self.read(0xFFFC) as u16 | ((self.read(0xFFFD) as u16) << 8);
 
@@ -535,8 +533,27 @@ impl CPU<'_> {
         });
     }
 
+    /// Pressing the reset button. Unlike `reset` this keeps the registers and the clocks running:
+    /// the CPU goes through the interrupt sequence with its stack writes turned into reads, so S
+    /// still drops by 3, then sets I and jumps through the reset vector. RAM is untouched
+    pub fn soft_reset(&mut self) {
+        self.bus.reset();
+        self.need_halt = false;
+        self.run_irq = false;
+        self.jammed = None;
+
+        self.sp = self.sp.wrapping_sub(3);
+        self.status.insert(Status::INTERRUPT_DISABLE);
+        self.pc = self.read(0xFFFC) as u16 | ((self.read(0xFFFD) as u16) << 8);
+
+        (0..7).for_each(|_| {
+            self.start_cpu_cycle(true);
+            self.end_cpu_cycle(true);
+        });
+    }
+
     fn get_nmi_flag(&self) -> bool {
-        self.bus.ppu.nmi_generated
+        self.bus.nmi()
     }
 
     fn start_cpu_cycle(&mut self, is_read: bool) {
@@ -548,11 +565,8 @@ impl CPU<'_> {
         self.cycle_count = self.cycle_count.wrapping_add(1);
         self.run_to(self.master_clock - self.ppu_offset as u64);
 
-        let (irq_pending, needs_dmc_transfer) = self.bus.apu.clock();
-        if irq_pending {
-            self.irq_flag.set(IRQSource::FRAME_COUNTER, true);
-        }
-        if needs_dmc_transfer {
+        self.bus.tick(self.cycle_count);
+        if self.bus.dmc_dma_requested() {
             self.start_dmc_transfer();
         }
     }
@@ -579,13 +593,13 @@ impl CPU<'_> {
         self.prev_nmi_flag = self.get_nmi_flag();
 
         self.prev_run_irq = self.run_irq;
-        self.run_irq = (self.irq_flag.bits() & self.irq_mask) > 0
+        self.run_irq = (self.bus.irq().bits() & self.irq_mask) > 0
             && !self.status.contains(Status::INTERRUPT_DISABLE)
     }
 
     fn get_op_code(&mut self) -> u8 {
         let op_code = self.memory_read(self.pc);
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         op_code
     }
 
@@ -599,116 +613,87 @@ impl CPU<'_> {
         }
     }
 
-    pub fn get_frame_hash(&self) -> u64 {
-        self.bus.ppu.curr_frame.get_hash()
-    }
-
+    /// Runs whole instructions until `cycle_count` reaches `cycles`, so it can end a little past
     pub fn run_for_cycles(&mut self, cycles: u64) {
-        while cycles != self.cycle_count {
-            self.run();
+        while self.cycle_count < cycles {
+            self.step();
         }
     }
 
-    pub fn run_until_frame(&mut self) {
-        let frame_num = self.bus.ppu.frame_count;
-        while self.bus.ppu.frame_count == frame_num
-            && self.cycle_count < Config::get_int("max_cycles", i64::MAX) as u64
-        {
-            self.run();
+    /// Runs one instruction, and the interrupt after it if one came in. A jammed CPU only spends
+    /// a cycle
+    pub fn step(&mut self) {
+        if self.jammed.is_some() {
+            // Stuck reading $FFFF until reset
+            self.memory_read(0xffff);
+            return;
         }
-    }
 
-    fn run(&mut self) {
         self.log();
         let opcode = self.get_op_code();
 
-        let searched_op = OPS.binary_search_by_key(&opcode, |op| op.hex);
-        let op = if searched_op.is_err() {
-            println!("Invalid opcode: {:02X}", opcode);
-            &OPS[OPS.binary_search_by_key(&"NOP", |op| op.name).unwrap()]
+        self.instr_addr_mode = OPS[opcode as usize].addressing_mode;
+        // JSR pushes the return address between the two halves of its operand, so it reads them
+        // itself
+        self.operand = if opcode == 0x20 {
+            0
         } else {
-            &OPS[OPS.binary_search_by_key(&opcode, |op| op.hex).unwrap()]
+            self.fetch_operand()
         };
-        self.instr_addr_mode = op.addressing_mode;
-        self.operand = self.fetch_operand();
-
-        match op.name {
-            "AND" => self.and(),
-            "ADC" => self.adc(),
-            "*ANC" => self.anc(),
-            "*ARR" => self.arr(),
-            "ASL" => self.asl(),
-            "*ASR" => self.asr(),
-            "*AXS" => self.axs(),
-            "BCC" => self.bcc(),
-            "BCS" => self.bcs(),
-            "BEQ" => self.beq(),
-            "BIT" => self.bit(),
-            "BPL" => self.bpl(),
-            "BMI" => self.bmi(),
-            "BNE" => self.bne(),
-            "BRK" => self.brk(),
-            "BVC" => self.bvc(),
-            "BVS" => self.bvs(),
-            "CLC" => self.clc(),
-            "CLD" => self.cld(),
-            "CLI" => self.cli(),
-            "CLV" => self.clv(),
-            "CMP" => self.cmp(),
-            "CPX" => self.cpx(),
-            "CPY" => self.cpy(),
-            "*DCP" => self.dcp(),
-            "DEC" => self.dec(),
-            "DEX" => self.dex(),
-            "DEY" => self.dey(),
-            "EOR" => self.eor(),
-            "INC" => self.inc(),
-            "INX" => self.inx(),
-            "INY" => self.iny(),
-            "*ISB" => self.isb(),
-            "JMP" => self.jmp(),
-            "JSR" => self.jsr(),
-            "*LAX" => self.lax(),
-            "LDA" => self.lda(),
-            "LDX" => self.ldx(),
-            "LDY" => self.ldy(),
-            "LSR" => self.lsr(),
-            "*LXA" => self.lxa(),
-            "NOP" | "*NOP" => self.nop(),
-            "ORA" => self.ora(),
-            "PHA" => self.pha(),
-            "PHP" => self.php(),
-            "PLA" => self.pla(),
-            "PLP" => self.plp(),
-            "ROL" => self.rol(),
-            "ROR" => self.ror(),
-            "*RLA" => self.rla(),
-            "*RRA" => self.rra(),
-            "RTI" => self.rti(),
-            "RTS" => self.rts(),
-            "*SAX" => self.sax(),
-            "SBC" | "*SBC" => self.sbc(),
-            "SEC" => self.sec(),
-            "SED" => self.sed(),
-            "SEI" => self.sei(),
-            "*SHX" => self.shx(),
-            "*SHY" => self.shy(),
-            "*SLO" => self.slo(),
-            "*SRE" => self.sre(),
-            "STA" => self.sta(),
-            "STY" => self.sty(),
-            "STX" => self.stx(),
-            "TAX" => self.tax(),
-            "TAY" => self.tay(),
-            "TSX" => self.tsx(),
-            "TXA" => self.txa(),
-            "TXS" => self.txs(),
-            "TYA" => self.tya(),
-            _ => panic!("Unknown opcode: {:02x}", opcode),
-        }
+        self.execute(opcode);
 
-        if self.prev_run_irq || self.prev_need_nmi {
+        if (self.prev_run_irq || self.prev_need_nmi) && self.jammed.is_none() {
             self.irq();
         }
     }
 }
+
+impl CPU {
+    pub fn get_frame_hash(&self) -> u64 {
+        self.bus.ppu.curr_frame.get_hash()
+    }
+
+    pub fn run_until_frame(&mut self) {
+        self.run_frame(Config::get_int("max_cycles", i64::MAX) as u64);
+    }
+
+    /// Runs to the end of the frame, stopping early once `max_cycles` have run in total
+    pub fn run_frame(&mut self, max_cycles: u64) {
+        let frame_num = self.bus.ppu.frame_count;
+        while self.bus.ppu.frame_count == frame_num && self.cycle_count < max_cycles {
+            self.step();
+        }
+    }
+}
+
+savestate_bits!(Status);
+
+// States are taken between instructions, so the addressing mode and operand of the last one
+// don't matter
+savestate!(CPU {
+    x,
+    y,
+    acc,
+    sp,
+    pc,
+    status,
+    bus,
+    irq_mask,
+    prev_run_irq,
+    run_irq,
+    start_clock_count,
+    end_clock_count,
+    master_clock,
+    cycle_count,
+    need_nmi,
+    prev_need_nmi,
+    prev_nmi_flag,
+    need_halt,
+    ppu_offset,
+    cpu_write,
+    sprite_dma_transfer,
+    need_dummy_read,
+    sprite_dma_offset,
+    dmc_dma_running,
+    jammed,
+});
@@ -1,18 +1,28 @@
-use lazy_static::lazy_static;
-
-use crate::core::cpu::AddressingMode;
+use crate::core::cpu::cpu_units::{
+    arithmetic::Arithmetic, branches::Branches, flag_changes::FlagChanges, inc_dec_ops::IncDecOps,
+    jumps::Jumps, load_store::LoadStore, logical::Logical, register_transfer::RegisterTransfer,
+    shift::Shift, stack_ops::StackOps, sys_funcs::SysFuncs,
+};
+use crate::core::cpu::{AddressingMode, CpuBus, CPU};
 
 #[derive(Clone, Copy, Debug)]
-pub struct Op<'a> {
+pub struct Op {
     pub hex: u8,
-    pub name: &'a str,
+    pub name: &'static str,
     pub addressing_mode: AddressingMode,
+    // Without the extra cycles for crossing a page or taking a branch
     pub cycles: u8,
     pub size: u16,
 }
 
-impl Op<'_> {
-    fn new(hex: u8, name: &str, addressing_mode: AddressingMode, cycles: u8, size: u16) -> Op {
+impl Op {
+    const fn new(
+        hex: u8,
+        name: &'static str,
+        addressing_mode: AddressingMode,
+        cycles: u8,
+        size: u16,
+    ) -> Op {
         Op {
             hex,
             name,
@@ -23,261 +33,307 @@ impl Op<'_> {
     }
 }
 
-lazy_static! {
-    pub static ref OPS: Vec<Op<'static>> = vec![
-        Op::new(0x00, "BRK", AddressingMode::Implicit, 7, 1),
-        Op::new(0x01, "ORA", AddressingMode::IndexedIndirect, 6, 2),
-        Op::new(0x03, "*SLO", AddressingMode::IndexedIndirect, 8, 2),
-        Op::new(0x04, "*NOP", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0x05, "ORA", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0x06, "ASL", AddressingMode::ZeroPage, 5, 2),
-        Op::new(0x07, "*SLO", AddressingMode::ZeroPage, 5, 2),
-        Op::new(0x08, "PHP", AddressingMode::Implicit, 3, 1),
-        Op::new(0x09, "ORA", AddressingMode::Immediate, 2, 2),
-        Op::new(0x0a, "ASL", AddressingMode::Accumulator, 2, 1),
-        Op::new(0x0b, "*ANC", AddressingMode::Immediate, 2, 2),
-        Op::new(0x0c, "*NOP", AddressingMode::Absolute, 4, 3),
-        Op::new(0x0d, "ORA", AddressingMode::Absolute, 4, 3),
-        Op::new(0x0e, "ASL", AddressingMode::Absolute, 6, 3),
-        Op::new(0x0f, "*SLO", AddressingMode::Absolute, 6, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0x10, "BPL", AddressingMode::Relative, 2, 2),
-        Op::new(0x11, "ORA", AddressingMode::IndirectIndexed, 5, 2),
-        Op::new(0x13, "*SLO", AddressingMode::IndirectIndexedW, 8, 2),
-        Op::new(0x14, "*NOP", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0x15, "ORA", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0x16, "ASL", AddressingMode::ZeroPageX, 6, 2),
-        Op::new(0x17, "*SLO", AddressingMode::ZeroPageX, 6, 2),
-        Op::new(0x18, "CLC", AddressingMode::Implicit, 2, 1),
-        Op::new(0x19, "ORA", AddressingMode::AbsoluteY, 4, 3),
-        Op::new(0x1a, "*NOP", AddressingMode::Implicit, 2, 1),
-        Op::new(0x1b, "*SLO", AddressingMode::AbsoluteYW, 7, 3),
-        Op::new(0x1c, "*NOP", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0x1d, "ORA", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0x1e, "ASL", AddressingMode::AbsoluteXW, 7, 3),
-        Op::new(0x1f, "*SLO", AddressingMode::AbsoluteXW, 7, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0x20, "JSR", AddressingMode::Absolute, 6, 3),
-        Op::new(0x21, "AND", AddressingMode::IndexedIndirect, 6, 2),
-        Op::new(0x23, "*RLA", AddressingMode::IndexedIndirect, 8, 2),
-        Op::new(0x24, "BIT", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0x25, "AND", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0x26, "ROL", AddressingMode::ZeroPage, 5, 2),
-        Op::new(0x27, "*RLA", AddressingMode::ZeroPage, 5, 2),
-        Op::new(0x28, "PLP", AddressingMode::Implicit, 4, 1),
-        Op::new(0x29, "AND", AddressingMode::Immediate, 2, 2),
-        Op::new(0x2a, "ROL", AddressingMode::Accumulator, 2, 1),
-        Op::new(0x2b, "*ANC", AddressingMode::Immediate, 2, 2),
-        Op::new(0x2c, "BIT", AddressingMode::Absolute, 4, 3),
-        Op::new(0x2d, "AND", AddressingMode::Absolute, 4, 3),
-        Op::new(0x2e, "ROL", AddressingMode::Absolute, 6, 3),
-        Op::new(0x2f, "*RLA", AddressingMode::Absolute, 6, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0x30, "BMI", AddressingMode::Relative, 2, 2),
-        Op::new(0x31, "AND", AddressingMode::IndirectIndexed, 5, 2),
-        Op::new(0x33, "*RLA", AddressingMode::IndirectIndexedW, 8, 2),
-        Op::new(0x34, "*NOP", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0x35, "AND", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0x36, "ROL", AddressingMode::ZeroPageX, 6, 2),
-        Op::new(0x37, "*RLA", AddressingMode::ZeroPageX, 6, 2),
-        Op::new(0x38, "SEC", AddressingMode::Implicit, 2, 1),
-        Op::new(0x39, "AND", AddressingMode::AbsoluteY, 4, 3),
-        Op::new(0x3a, "*NOP", AddressingMode::Implicit, 2, 1),
-        Op::new(0x3b, "*RLA", AddressingMode::AbsoluteYW, 7, 3),
-        Op::new(0x3c, "*NOP", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0x3d, "AND", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0x3e, "ROL", AddressingMode::AbsoluteXW, 7, 3),
-        Op::new(0x3f, "*RLA", AddressingMode::AbsoluteXW, 7, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0x40, "RTI", AddressingMode::Implicit, 6, 1),
-        Op::new(0x41, "EOR", AddressingMode::IndexedIndirect, 6, 2),
-        Op::new(0x43, "*SRE", AddressingMode::IndexedIndirect, 8, 2),
-        Op::new(0x44, "*NOP", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0x45, "EOR", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0x46, "LSR", AddressingMode::ZeroPage, 5, 2),
-        Op::new(0x47, "*SRE", AddressingMode::ZeroPage, 5, 2),
-        Op::new(0x48, "PHA", AddressingMode::Implicit, 3, 1),
-        Op::new(0x49, "EOR", AddressingMode::Immediate, 2, 2),
-        Op::new(0x4a, "LSR", AddressingMode::Accumulator, 2, 1),
-        Op::new(0x4b, "*ASR", AddressingMode::Immediate, 2, 2),
-        Op::new(0x4c, "JMP", AddressingMode::Absolute, 3, 3),
-        Op::new(0x4d, "EOR", AddressingMode::Absolute, 4, 3),
-        Op::new(0x4e, "LSR", AddressingMode::Absolute, 6, 3),
-        Op::new(0x4f, "*SRE", AddressingMode::Absolute, 6, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0x50, "BVC", AddressingMode::Relative, 2, 2),
-        Op::new(0x51, "EOR", AddressingMode::IndirectIndexed, 5, 2),
-        Op::new(0x53, "*SRE", AddressingMode::IndirectIndexedW, 8, 2),
-        Op::new(0x54, "*NOP", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0x55, "EOR", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0x56, "LSR", AddressingMode::ZeroPageX, 6, 2),
-        Op::new(0x57, "*SRE", AddressingMode::ZeroPageX, 6, 2),
-        Op::new(0x58, "CLI", AddressingMode::Implicit, 2, 1),
-        Op::new(0x59, "EOR", AddressingMode::AbsoluteY, 4, 3),
-        Op::new(0x5a, "*NOP", AddressingMode::Implicit, 2, 1),
-        Op::new(0x5b, "*SRE", AddressingMode::AbsoluteYW, 7, 3),
-        Op::new(0x5c, "*NOP", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0x5d, "EOR", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0x5e, "LSR", AddressingMode::AbsoluteXW, 7, 3),
-        Op::new(0x5f, "*SRE", AddressingMode::AbsoluteXW, 7, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0x60, "RTS", AddressingMode::Implicit, 6, 1),
-        Op::new(0x61, "ADC", AddressingMode::IndexedIndirect, 6, 2),
-        Op::new(0x63, "*RRA", AddressingMode::IndexedIndirect, 8, 2),
-        Op::new(0x64, "*NOP", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0x65, "ADC", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0x66, "ROR", AddressingMode::ZeroPage, 5, 2),
-        Op::new(0x67, "*RRA", AddressingMode::ZeroPage, 5, 2),
-        Op::new(0x68, "PLA", AddressingMode::Implicit, 4, 1),
-        Op::new(0x69, "ADC", AddressingMode::Immediate, 2, 2),
-        Op::new(0x6a, "ROR", AddressingMode::Accumulator, 2, 1),
-        Op::new(0x6b, "*ARR", AddressingMode::Immediate, 2, 2),
-        Op::new(0x6c, "JMP", AddressingMode::Indirect, 5, 3),
-        Op::new(0x6d, "ADC", AddressingMode::Absolute, 4, 3),
-        Op::new(0x6e, "ROR", AddressingMode::Absolute, 6, 3),
-        Op::new(0x6f, "*RRA", AddressingMode::Absolute, 6, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0x70, "BVS", AddressingMode::Relative, 2, 2),
-        Op::new(0x71, "ADC", AddressingMode::IndirectIndexed, 5, 2),
-        Op::new(0x73, "*RRA", AddressingMode::IndirectIndexedW, 8, 2),
-        Op::new(0x74, "*NOP", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0x75, "ADC", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0x76, "ROR", AddressingMode::ZeroPageX, 6, 2),
-        Op::new(0x77, "*RRA", AddressingMode::ZeroPageX, 6, 2),
-        Op::new(0x78, "SEI", AddressingMode::Implicit, 2, 1),
-        Op::new(0x79, "ADC", AddressingMode::AbsoluteY, 4, 3),
-        Op::new(0x7a, "*NOP", AddressingMode::Implicit, 2, 1),
-        Op::new(0x7b, "*RRA", AddressingMode::AbsoluteYW, 7, 3),
-        Op::new(0x7c, "*NOP", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0x7d, "ADC", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0x7e, "ROR", AddressingMode::AbsoluteXW, 7, 3),
-        Op::new(0x7f, "*RRA", AddressingMode::AbsoluteXW, 7, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0x80, "*NOP", AddressingMode::Immediate, 2, 2),
-        Op::new(0x81, "STA", AddressingMode::IndexedIndirect, 6, 2),
-        Op::new(0x82, "*NOP", AddressingMode::Immediate, 2, 2),
-        Op::new(0x83, "*SAX", AddressingMode::IndexedIndirect, 6, 2),
-        Op::new(0x84, "STY", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0x85, "STA", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0x86, "STX", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0x87, "*SAX", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0x88, "DEY", AddressingMode::Implicit, 2, 1),
-        Op::new(0x89, "*NOP", AddressingMode::Immediate, 2, 2),
-        Op::new(0x8a, "TXA", AddressingMode::Implicit, 2, 1),
-        Op::new(0x8c, "STY", AddressingMode::Absolute, 4, 3),
-        Op::new(0x8d, "STA", AddressingMode::Absolute, 4, 3),
-        Op::new(0x8e, "STX", AddressingMode::Absolute, 4, 3),
-        Op::new(0x8f, "*SAX", AddressingMode::Absolute, 4, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0x90, "BCC", AddressingMode::Relative, 2, 2),
-        Op::new(0x91, "STA", AddressingMode::IndirectIndexedW, 6, 2),
-        Op::new(0x94, "STY", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0x95, "STA", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0x96, "STX", AddressingMode::ZeroPageY, 4, 2),
-        Op::new(0x97, "*SAX", AddressingMode::ZeroPageY, 4, 2),
-        Op::new(0x98, "TYA", AddressingMode::Implicit, 2, 1),
-        Op::new(0x99, "STA", AddressingMode::AbsoluteYW, 5, 3),
-        Op::new(0x9a, "TXS", AddressingMode::Implicit, 2, 1),
-        Op::new(0x9c, "*SHY", AddressingMode::AbsoluteXW, 5, 3),
-        Op::new(0x9d, "STA", AddressingMode::AbsoluteXW, 5, 3),
-        Op::new(0x9e, "*SHX", AddressingMode::AbsoluteYW, 5, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0xa0, "LDY", AddressingMode::Immediate, 2, 2),
-        Op::new(0xa1, "LDA", AddressingMode::IndexedIndirect, 6, 2),
-        Op::new(0xa2, "LDX", AddressingMode::Immediate, 2, 2),
-        Op::new(0xa3, "*LAX", AddressingMode::IndexedIndirect, 6, 2),
-        Op::new(0xa4, "LDY", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0xa5, "LDA", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0xa6, "LDX", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0xa7, "*LAX", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0xa8, "TAY", AddressingMode::Implicit, 2, 1),
-        Op::new(0xa9, "LDA", AddressingMode::Immediate, 2, 2),
-        Op::new(0xaa, "TAX", AddressingMode::Implicit, 2, 1),
-        Op::new(0xab, "*LXA", AddressingMode::Immediate, 2, 2),
-        Op::new(0xac, "LDY", AddressingMode::Absolute, 4, 3),
-        Op::new(0xad, "LDA", AddressingMode::Absolute, 4, 3),
-        Op::new(0xae, "LDX", AddressingMode::Absolute, 4, 3),
-        Op::new(0xaf, "*LAX", AddressingMode::Absolute, 4, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0xb0, "BCS", AddressingMode::Relative, 2, 2),
-        Op::new(0xb1, "LDA", AddressingMode::IndirectIndexed, 5, 2),
-        Op::new(0xb3, "*LAX", AddressingMode::IndirectIndexed, 5, 2),
-        Op::new(0xb4, "LDY", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0xb5, "LDA", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0xb6, "LDX", AddressingMode::ZeroPageY, 4, 2),
-        Op::new(0xb7, "*LAX", AddressingMode::ZeroPageY, 4, 2),
-        Op::new(0xb8, "CLV", AddressingMode::Implicit, 2, 1),
-        Op::new(0xb9, "LDA", AddressingMode::AbsoluteY, 4, 3),
-        Op::new(0xba, "TSX", AddressingMode::Implicit, 2, 1),
-        Op::new(0xbc, "LDY", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0xbd, "LDA", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0xbe, "LDX", AddressingMode::AbsoluteY, 4, 3),
-        Op::new(0xbf, "*LAX", AddressingMode::AbsoluteY, 4, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0xc0, "CPY", AddressingMode::Immediate, 2, 2),
-        Op::new(0xc1, "CMP", AddressingMode::IndexedIndirect, 6, 2),
-        Op::new(0xc2, "*NOP", AddressingMode::Immediate, 2, 2),
-        Op::new(0xc3, "*DCP", AddressingMode::IndexedIndirect, 8, 2),
-        Op::new(0xc4, "CPY", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0xc5, "CMP", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0xc6, "DEC", AddressingMode::ZeroPage, 5, 2),
-        Op::new(0xc7, "*DCP", AddressingMode::ZeroPage, 5, 2),
-        Op::new(0xc8, "INY", AddressingMode::Implicit, 2, 1),
-        Op::new(0xc9, "CMP", AddressingMode::Immediate, 2, 2),
-        Op::new(0xca, "DEX", AddressingMode::Implicit, 2, 1),
-        Op::new(0xcb, "*AXS", AddressingMode::Immediate, 2, 2),
-        Op::new(0xcc, "CPY", AddressingMode::Absolute, 4, 3),
-        Op::new(0xcd, "CMP", AddressingMode::Absolute, 4, 3),
-        Op::new(0xce, "DEC", AddressingMode::Absolute, 6, 3),
-        Op::new(0xcf, "*DCP", AddressingMode::Absolute, 6, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0xd0, "BNE", AddressingMode::Relative, 2, 2),
-        Op::new(0xd1, "CMP", AddressingMode::IndirectIndexed, 5, 2),
-        Op::new(0xd3, "*DCP", AddressingMode::IndirectIndexedW, 8, 2),
-        Op::new(0xd4, "*NOP", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0xd5, "CMP", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0xd6, "DEC", AddressingMode::ZeroPageX, 6, 2),
-        Op::new(0xd7, "*DCP", AddressingMode::ZeroPageX, 6, 2),
-        Op::new(0xd8, "CLD", AddressingMode::Implicit, 2, 1),
-        Op::new(0xd9, "CMP", AddressingMode::AbsoluteY, 4, 3),
-        Op::new(0xda, "*NOP", AddressingMode::Implicit, 2, 1),
-        Op::new(0xdb, "*DCP", AddressingMode::AbsoluteYW, 7, 3),
-        Op::new(0xdc, "*NOP", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0xdd, "CMP", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0xde, "DEC", AddressingMode::AbsoluteXW, 7, 3),
-        Op::new(0xdf, "*DCP", AddressingMode::AbsoluteXW, 7, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0xe0, "CPX", AddressingMode::Immediate, 2, 2),
-        Op::new(0xe1, "SBC", AddressingMode::IndexedIndirect, 6, 2),
-        Op::new(0xe2, "*NOP", AddressingMode::Immediate, 2, 2),
-        Op::new(0xe3, "*ISB", AddressingMode::IndexedIndirect, 8, 2),
-        Op::new(0xe4, "CPX", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0xe5, "SBC", AddressingMode::ZeroPage, 3, 2),
-        Op::new(0xe6, "INC", AddressingMode::ZeroPage, 5, 2),
-        Op::new(0xe7, "*ISB", AddressingMode::ZeroPage, 5, 2),
-        Op::new(0xe8, "INX", AddressingMode::Implicit, 2, 1),
-        Op::new(0xe9, "SBC", AddressingMode::Immediate, 2, 2),
-        Op::new(0xea, "NOP", AddressingMode::Implicit, 2, 1),
-        Op::new(0xeb, "*SBC", AddressingMode::Immediate, 2, 2),
-        Op::new(0xec, "CPX", AddressingMode::Absolute, 4, 3),
-        Op::new(0xed, "SBC", AddressingMode::Absolute, 4, 3),
-        Op::new(0xee, "INC", AddressingMode::Absolute, 6, 3),
-        Op::new(0xef, "*ISB", AddressingMode::Absolute, 6, 3),
-        // ---------------------------------------------------------------------------------------------
-        Op::new(0xf0, "BEQ", AddressingMode::Relative, 2, 2),
-        Op::new(0xf1, "SBC", AddressingMode::IndirectIndexed, 5, 2),
-        Op::new(0xf3, "*ISB", AddressingMode::IndirectIndexedW, 8, 2),
-        Op::new(0xf4, "*NOP", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0xf5, "SBC", AddressingMode::ZeroPageX, 4, 2),
-        Op::new(0xf6, "INC", AddressingMode::ZeroPageX, 6, 2),
-        Op::new(0xf7, "*ISB", AddressingMode::ZeroPageX, 6, 2),
-        Op::new(0xf8, "SED", AddressingMode::Implicit, 2, 1),
-        Op::new(0xf9, "SBC", AddressingMode::AbsoluteY, 4, 3),
-        Op::new(0xfa, "*NOP", AddressingMode::Implicit, 2, 1),
-        Op::new(0xfb, "*ISB", AddressingMode::AbsoluteYW, 7, 3),
-        Op::new(0xfc, "*NOP", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0xfd, "SBC", AddressingMode::AbsoluteX, 4, 3),
-        Op::new(0xfe, "INC", AddressingMode::AbsoluteXW, 7, 3),
-        Op::new(0xff, "*ISB", AddressingMode::AbsoluteXW, 7, 3),
-    ];
+// Builds the table from the rows below, and a match on the opcode that runs the instruction. A
+// match rather than function pointers in the table so it works for every `CpuBus`
+macro_rules! ops {
+    ($(op!($hex:literal, $name:literal, $mode:ident, $cycles:literal, $size:literal, $execute:ident)),* $(,)?) => {
+        const TABLE: [Op; 256] = [
+            $(Op::new($hex, $name, AddressingMode::$mode, $cycles, $size)),*
+        ];
+
+        impl<B: CpuBus> CPU<B> {
+            // Runs the instruction once the operand has been fetched
+            pub(super) fn execute(&mut self, opcode: u8) {
+                match opcode {
+                    $($hex => self.$execute(),)*
+                }
+            }
+        }
+    };
 }
+
+/// Every opcode, indexed by its value. Used by both the CPU and the tracer
+pub static OPS: [Op; 256] = TABLE;
+
+ops![
+    op!(0x00, "BRK", Implicit, 7, 1, brk),
+    op!(0x01, "ORA", IndexedIndirect, 6, 2, ora),
+    op!(0x02, "*JAM", Implicit, 2, 1, jam),
+    op!(0x03, "*SLO", IndexedIndirect, 8, 2, slo),
+    op!(0x04, "*NOP", ZeroPage, 3, 2, nop),
+    op!(0x05, "ORA", ZeroPage, 3, 2, ora),
+    op!(0x06, "ASL", ZeroPage, 5, 2, asl),
+    op!(0x07, "*SLO", ZeroPage, 5, 2, slo),
+    op!(0x08, "PHP", Implicit, 3, 1, php),
+    op!(0x09, "ORA", Immediate, 2, 2, ora),
+    op!(0x0a, "ASL", Accumulator, 2, 1, asl),
+    op!(0x0b, "*ANC", Immediate, 2, 2, anc),
+    op!(0x0c, "*NOP", Absolute, 4, 3, nop),
+    op!(0x0d, "ORA", Absolute, 4, 3, ora),
+    op!(0x0e, "ASL", Absolute, 6, 3, asl),
+    op!(0x0f, "*SLO", Absolute, 6, 3, slo),
+    // ---------------------------------------------------------------------------------------------
+    op!(0x10, "BPL", Relative, 2, 2, bpl),
+    op!(0x11, "ORA", IndirectIndexed, 5, 2, ora),
+    op!(0x12, "*JAM", Implicit, 2, 1, jam),
+    op!(0x13, "*SLO", IndirectIndexedW, 8, 2, slo),
+    op!(0x14, "*NOP", ZeroPageX, 4, 2, nop),
+    op!(0x15, "ORA", ZeroPageX, 4, 2, ora),
+    op!(0x16, "ASL", ZeroPageX, 6, 2, asl),
+    op!(0x17, "*SLO", ZeroPageX, 6, 2, slo),
+    op!(0x18, "CLC", Implicit, 2, 1, clc),
+    op!(0x19, "ORA", AbsoluteY, 4, 3, ora),
+    op!(0x1a, "*NOP", Implicit, 2, 1, nop),
+    op!(0x1b, "*SLO", AbsoluteYW, 7, 3, slo),
+    op!(0x1c, "*NOP", AbsoluteX, 4, 3, nop),
+    op!(0x1d, "ORA", AbsoluteX, 4, 3, ora),
+    op!(0x1e, "ASL", AbsoluteXW, 7, 3, asl),
+    op!(0x1f, "*SLO", AbsoluteXW, 7, 3, slo),
+    // ---------------------------------------------------------------------------------------------
+    op!(0x20, "JSR", Absolute, 6, 3, jsr),
+    op!(0x21, "AND", IndexedIndirect, 6, 2, and),
+    op!(0x22, "*JAM", Implicit, 2, 1, jam),
+    op!(0x23, "*RLA", IndexedIndirect, 8, 2, rla),
+    op!(0x24, "BIT", ZeroPage, 3, 2, bit),
+    op!(0x25, "AND", ZeroPage, 3, 2, and),
+    op!(0x26, "ROL", ZeroPage, 5, 2, rol),
+    op!(0x27, "*RLA", ZeroPage, 5, 2, rla),
+    op!(0x28, "PLP", Implicit, 4, 1, plp),
+    op!(0x29, "AND", Immediate, 2, 2, and),
+    op!(0x2a, "ROL", Accumulator, 2, 1, rol),
+    op!(0x2b, "*ANC", Immediate, 2, 2, anc),
+    op!(0x2c, "BIT", Absolute, 4, 3, bit),
+    op!(0x2d, "AND", Absolute, 4, 3, and),
+    op!(0x2e, "ROL", Absolute, 6, 3, rol),
+    op!(0x2f, "*RLA", Absolute, 6, 3, rla),
+    // ---------------------------------------------------------------------------------------------
+    op!(0x30, "BMI", Relative, 2, 2, bmi),
+    op!(0x31, "AND", IndirectIndexed, 5, 2, and),
+    op!(0x32, "*JAM", Implicit, 2, 1, jam),
+    op!(0x33, "*RLA", IndirectIndexedW, 8, 2, rla),
+    op!(0x34, "*NOP", ZeroPageX, 4, 2, nop),
+    op!(0x35, "AND", ZeroPageX, 4, 2, and),
+    op!(0x36, "ROL", ZeroPageX, 6, 2, rol),
+    op!(0x37, "*RLA", ZeroPageX, 6, 2, rla),
+    op!(0x38, "SEC", Implicit, 2, 1, sec),
+    op!(0x39, "AND", AbsoluteY, 4, 3, and),
+    op!(0x3a, "*NOP", Implicit, 2, 1, nop),
+    op!(0x3b, "*RLA", AbsoluteYW, 7, 3, rla),
+    op!(0x3c, "*NOP", AbsoluteX, 4, 3, nop),
+    op!(0x3d, "AND", AbsoluteX, 4, 3, and),
+    op!(0x3e, "ROL", AbsoluteXW, 7, 3, rol),
+    op!(0x3f, "*RLA", AbsoluteXW, 7, 3, rla),
+    // ---------------------------------------------------------------------------------------------
+    op!(0x40, "RTI", Implicit, 6, 1, rti),
+    op!(0x41, "EOR", IndexedIndirect, 6, 2, eor),
+    op!(0x42, "*JAM", Implicit, 2, 1, jam),
+    op!(0x43, "*SRE", IndexedIndirect, 8, 2, sre),
+    op!(0x44, "*NOP", ZeroPage, 3, 2, nop),
+    op!(0x45, "EOR", ZeroPage, 3, 2, eor),
+    op!(0x46, "LSR", ZeroPage, 5, 2, lsr),
+    op!(0x47, "*SRE", ZeroPage, 5, 2, sre),
+    op!(0x48, "PHA", Implicit, 3, 1, pha),
+    op!(0x49, "EOR", Immediate, 2, 2, eor),
+    op!(0x4a, "LSR", Accumulator, 2, 1, lsr),
+    op!(0x4b, "*ASR", Immediate, 2, 2, asr),
+    op!(0x4c, "JMP", Absolute, 3, 3, jmp),
+    op!(0x4d, "EOR", Absolute, 4, 3, eor),
+    op!(0x4e, "LSR", Absolute, 6, 3, lsr),
+    op!(0x4f, "*SRE", Absolute, 6, 3, sre),
+    // ---------------------------------------------------------------------------------------------
+    op!(0x50, "BVC", Relative, 2, 2, bvc),
+    op!(0x51, "EOR", IndirectIndexed, 5, 2, eor),
+    op!(0x52, "*JAM", Implicit, 2, 1, jam),
+    op!(0x53, "*SRE", IndirectIndexedW, 8, 2, sre),
+    op!(0x54, "*NOP", ZeroPageX, 4, 2, nop),
+    op!(0x55, "EOR", ZeroPageX, 4, 2, eor),
+    op!(0x56, "LSR", ZeroPageX, 6, 2, lsr),
+    op!(0x57, "*SRE", ZeroPageX, 6, 2, sre),
+    op!(0x58, "CLI", Implicit, 2, 1, cli),
+    op!(0x59, "EOR", AbsoluteY, 4, 3, eor),
+    op!(0x5a, "*NOP", Implicit, 2, 1, nop),
+    op!(0x5b, "*SRE", AbsoluteYW, 7, 3, sre),
+    op!(0x5c, "*NOP", AbsoluteX, 4, 3, nop),
+    op!(0x5d, "EOR", AbsoluteX, 4, 3, eor),
+    op!(0x5e, "LSR", AbsoluteXW, 7, 3, lsr),
+    op!(0x5f, "*SRE", AbsoluteXW, 7, 3, sre),
+    // ---------------------------------------------------------------------------------------------
+    op!(0x60, "RTS", Implicit, 6, 1, rts),
+    op!(0x61, "ADC", IndexedIndirect, 6, 2, adc),
+    op!(0x62, "*JAM", Implicit, 2, 1, jam),
+    op!(0x63, "*RRA", IndexedIndirect, 8, 2, rra),
+    op!(0x64, "*NOP", ZeroPage, 3, 2, nop),
+    op!(0x65, "ADC", ZeroPage, 3, 2, adc),
+    op!(0x66, "ROR", ZeroPage, 5, 2, ror),
+    op!(0x67, "*RRA", ZeroPage, 5, 2, rra),
+    op!(0x68, "PLA", Implicit, 4, 1, pla),
+    op!(0x69, "ADC", Immediate, 2, 2, adc),
+    op!(0x6a, "ROR", Accumulator, 2, 1, ror),
+    op!(0x6b, "*ARR", Immediate, 2, 2, arr),
+    op!(0x6c, "JMP", Indirect, 5, 3, jmp),
+    op!(0x6d, "ADC", Absolute, 4, 3, adc),
+    op!(0x6e, "ROR", Absolute, 6, 3, ror),
+    op!(0x6f, "*RRA", Absolute, 6, 3, rra),
+    // ---------------------------------------------------------------------------------------------
+    op!(0x70, "BVS", Relative, 2, 2, bvs),
+    op!(0x71, "ADC", IndirectIndexed, 5, 2, adc),
+    op!(0x72, "*JAM", Implicit, 2, 1, jam),
+    op!(0x73, "*RRA", IndirectIndexedW, 8, 2, rra),
+    op!(0x74, "*NOP", ZeroPageX, 4, 2, nop),
+    op!(0x75, "ADC", ZeroPageX, 4, 2, adc),
+    op!(0x76, "ROR", ZeroPageX, 6, 2, ror),
+    op!(0x77, "*RRA", ZeroPageX, 6, 2, rra),
+    op!(0x78, "SEI", Implicit, 2, 1, sei),
+    op!(0x79, "ADC", AbsoluteY, 4, 3, adc),
+    op!(0x7a, "*NOP", Implicit, 2, 1, nop),
+    op!(0x7b, "*RRA", AbsoluteYW, 7, 3, rra),
+    op!(0x7c, "*NOP", AbsoluteX, 4, 3, nop),
+    op!(0x7d, "ADC", AbsoluteX, 4, 3, adc),
+    op!(0x7e, "ROR", AbsoluteXW, 7, 3, ror),
+    op!(0x7f, "*RRA", AbsoluteXW, 7, 3, rra),
+    // ---------------------------------------------------------------------------------------------
+    op!(0x80, "*NOP", Immediate, 2, 2, nop),
+    op!(0x81, "STA", IndexedIndirect, 6, 2, sta),
+    op!(0x82, "*NOP", Immediate, 2, 2, nop),
+    op!(0x83, "*SAX", IndexedIndirect, 6, 2, sax),
+    op!(0x84, "STY", ZeroPage, 3, 2, sty),
+    op!(0x85, "STA", ZeroPage, 3, 2, sta),
+    op!(0x86, "STX", ZeroPage, 3, 2, stx),
+    op!(0x87, "*SAX", ZeroPage, 3, 2, sax),
+    op!(0x88, "DEY", Implicit, 2, 1, dey),
+    op!(0x89, "*NOP", Immediate, 2, 2, nop),
+    op!(0x8a, "TXA", Implicit, 2, 1, txa),
+    op!(0x8b, "*XAA", Immediate, 2, 2, xaa),
+    op!(0x8c, "STY", Absolute, 4, 3, sty),
+    op!(0x8d, "STA", Absolute, 4, 3, sta),
+    op!(0x8e, "STX", Absolute, 4, 3, stx),
+    op!(0x8f, "*SAX", Absolute, 4, 3, sax),
+    // ---------------------------------------------------------------------------------------------
+    op!(0x90, "BCC", Relative, 2, 2, bcc),
+    op!(0x91, "STA", IndirectIndexedW, 6, 2, sta),
+    op!(0x92, "*JAM", Implicit, 2, 1, jam),
+    op!(0x93, "*SHA", IndirectIndexedW, 6, 2, sha),
+    op!(0x94, "STY", ZeroPageX, 4, 2, sty),
+    op!(0x95, "STA", ZeroPageX, 4, 2, sta),
+    op!(0x96, "STX", ZeroPageY, 4, 2, stx),
+    op!(0x97, "*SAX", ZeroPageY, 4, 2, sax),
+    op!(0x98, "TYA", Implicit, 2, 1, tya),
+    op!(0x99, "STA", AbsoluteYW, 5, 3, sta),
+    op!(0x9a, "TXS", Implicit, 2, 1, txs),
+    op!(0x9b, "*TAS", AbsoluteYW, 5, 3, tas),
+    op!(0x9c, "*SHY", AbsoluteXW, 5, 3, shy),
+    op!(0x9d, "STA", AbsoluteXW, 5, 3, sta),
+    op!(0x9e, "*SHX", AbsoluteYW, 5, 3, shx),
+    // ---------------------------------------------------------------------------------------------
+    op!(0x9f, "*SHA", AbsoluteYW, 5, 3, sha),
+    op!(0xa0, "LDY", Immediate, 2, 2, ldy),
+    op!(0xa1, "LDA", IndexedIndirect, 6, 2, lda),
+    op!(0xa2, "LDX", Immediate, 2, 2, ldx),
+    op!(0xa3, "*LAX", IndexedIndirect, 6, 2, lax),
+    op!(0xa4, "LDY", ZeroPage, 3, 2, ldy),
+    op!(0xa5, "LDA", ZeroPage, 3, 2, lda),
+    op!(0xa6, "LDX", ZeroPage, 3, 2, ldx),
+    op!(0xa7, "*LAX", ZeroPage, 3, 2, lax),
+    op!(0xa8, "TAY", Implicit, 2, 1, tay),
+    op!(0xa9, "LDA", Immediate, 2, 2, lda),
+    op!(0xaa, "TAX", Implicit, 2, 1, tax),
+    op!(0xab, "*LXA", Immediate, 2, 2, lxa),
+    op!(0xac, "LDY", Absolute, 4, 3, ldy),
+    op!(0xad, "LDA", Absolute, 4, 3, lda),
+    op!(0xae, "LDX", Absolute, 4, 3, ldx),
+    op!(0xaf, "*LAX", Absolute, 4, 3, lax),
+    // ---------------------------------------------------------------------------------------------
+    op!(0xb0, "BCS", Relative, 2, 2, bcs),
+    op!(0xb1, "LDA", IndirectIndexed, 5, 2, lda),
+    op!(0xb2, "*JAM", Implicit, 2, 1, jam),
+    op!(0xb3, "*LAX", IndirectIndexed, 5, 2, lax),
+    op!(0xb4, "LDY", ZeroPageX, 4, 2, ldy),
+    op!(0xb5, "LDA", ZeroPageX, 4, 2, lda),
+    op!(0xb6, "LDX", ZeroPageY, 4, 2, ldx),
+    op!(0xb7, "*LAX", ZeroPageY, 4, 2, lax),
+    op!(0xb8, "CLV", Implicit, 2, 1, clv),
+    op!(0xb9, "LDA", AbsoluteY, 4, 3, lda),
+    op!(0xba, "TSX", Implicit, 2, 1, tsx),
+    op!(0xbb, "*LAS", AbsoluteY, 4, 3, las),
+    op!(0xbc, "LDY", AbsoluteX, 4, 3, ldy),
+    op!(0xbd, "LDA", AbsoluteX, 4, 3, lda),
+    op!(0xbe, "LDX", AbsoluteY, 4, 3, ldx),
+    op!(0xbf, "*LAX", AbsoluteY, 4, 3, lax),
+    // ---------------------------------------------------------------------------------------------
+    op!(0xc0, "CPY", Immediate, 2, 2, cpy),
+    op!(0xc1, "CMP", IndexedIndirect, 6, 2, cmp),
+    op!(0xc2, "*NOP", Immediate, 2, 2, nop),
+    op!(0xc3, "*DCP", IndexedIndirect, 8, 2, dcp),
+    op!(0xc4, "CPY", ZeroPage, 3, 2, cpy),
+    op!(0xc5, "CMP", ZeroPage, 3, 2, cmp),
+    op!(0xc6, "DEC", ZeroPage, 5, 2, dec),
+    op!(0xc7, "*DCP", ZeroPage, 5, 2, dcp),
+    op!(0xc8, "INY", Implicit, 2, 1, iny),
+    op!(0xc9, "CMP", Immediate, 2, 2, cmp),
+    op!(0xca, "DEX", Implicit, 2, 1, dex),
+    op!(0xcb, "*AXS", Immediate, 2, 2, axs),
+    op!(0xcc, "CPY", Absolute, 4, 3, cpy),
+    op!(0xcd, "CMP", Absolute, 4, 3, cmp),
+    op!(0xce, "DEC", Absolute, 6, 3, dec),
+    op!(0xcf, "*DCP", Absolute, 6, 3, dcp),
+    // ---------------------------------------------------------------------------------------------
+    op!(0xd0, "BNE", Relative, 2, 2, bne),
+    op!(0xd1, "CMP", IndirectIndexed, 5, 2, cmp),
+    op!(0xd2, "*JAM", Implicit, 2, 1, jam),
+    op!(0xd3, "*DCP", IndirectIndexedW, 8, 2, dcp),
+    op!(0xd4, "*NOP", ZeroPageX, 4, 2, nop),
+    op!(0xd5, "CMP", ZeroPageX, 4, 2, cmp),
+    op!(0xd6, "DEC", ZeroPageX, 6, 2, dec),
+    op!(0xd7, "*DCP", ZeroPageX, 6, 2, dcp),
+    op!(0xd8, "CLD", Implicit, 2, 1, cld),
+    op!(0xd9, "CMP", AbsoluteY, 4, 3, cmp),
+    op!(0xda, "*NOP", Implicit, 2, 1, nop),
+    op!(0xdb, "*DCP", AbsoluteYW, 7, 3, dcp),
+    op!(0xdc, "*NOP", AbsoluteX, 4, 3, nop),
+    op!(0xdd, "CMP", AbsoluteX, 4, 3, cmp),
+    op!(0xde, "DEC", AbsoluteXW, 7, 3, dec),
+    op!(0xdf, "*DCP", AbsoluteXW, 7, 3, dcp),
+    // ---------------------------------------------------------------------------------------------
+    op!(0xe0, "CPX", Immediate, 2, 2, cpx),
+    op!(0xe1, "SBC", IndexedIndirect, 6, 2, sbc),
+    op!(0xe2, "*NOP", Immediate, 2, 2, nop),
+    op!(0xe3, "*ISB", IndexedIndirect, 8, 2, isb),
+    op!(0xe4, "CPX", ZeroPage, 3, 2, cpx),
+    op!(0xe5, "SBC", ZeroPage, 3, 2, sbc),
+    op!(0xe6, "INC", ZeroPage, 5, 2, inc),
+    op!(0xe7, "*ISB", ZeroPage, 5, 2, isb),
+    op!(0xe8, "INX", Implicit, 2, 1, inx),
+    op!(0xe9, "SBC", Immediate, 2, 2, sbc),
+    op!(0xea, "NOP", Implicit, 2, 1, nop),
+    op!(0xeb, "*SBC", Immediate, 2, 2, sbc),
+    op!(0xec, "CPX", Absolute, 4, 3, cpx),
+    op!(0xed, "SBC", Absolute, 4, 3, sbc),
+    op!(0xee, "INC", Absolute, 6, 3, inc),
+    op!(0xef, "*ISB", Absolute, 6, 3, isb),
+    // ---------------------------------------------------------------------------------------------
+    op!(0xf0, "BEQ", Relative, 2, 2, beq),
+    op!(0xf1, "SBC", IndirectIndexed, 5, 2, sbc),
+    op!(0xf2, "*JAM", Implicit, 2, 1, jam),
+    op!(0xf3, "*ISB", IndirectIndexedW, 8, 2, isb),
+    op!(0xf4, "*NOP", ZeroPageX, 4, 2, nop),
+    op!(0xf5, "SBC", ZeroPageX, 4, 2, sbc),
+    op!(0xf6, "INC", ZeroPageX, 6, 2, inc),
+    op!(0xf7, "*ISB", ZeroPageX, 6, 2, isb),
+    op!(0xf8, "SED", Implicit, 2, 1, sed),
+    op!(0xf9, "SBC", AbsoluteY, 4, 3, sbc),
+    op!(0xfa, "*NOP", Implicit, 2, 1, nop),
+    op!(0xfb, "*ISB", AbsoluteYW, 7, 3, isb),
+    op!(0xfc, "*NOP", AbsoluteX, 4, 3, nop),
+    op!(0xfd, "SBC", AbsoluteX, 4, 3, sbc),
+    op!(0xfe, "INC", AbsoluteXW, 7, 3, inc),
+    op!(0xff, "*ISB", AbsoluteXW, 7, 3, isb),
+];
+
+// Catches rows that are missing or out of order
+const _: () = {
+    let mut i = 0;
+    while i < TABLE.len() {
+        assert!(TABLE[i].hex as usize == i);
+        i += 1;
+    }
+};
@@ -0,0 +1,84 @@
+use super::{CpuBus, IRQSource};
+
+const RAM_SIZE: usize = 0x10000;
+
+/// One cycle of bus activity, as logged by `RamBus`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusAccess {
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+/// 64KB of RAM and nothing else, for running the CPU away from the NES: test benches, or 6502
+/// code that never touches the console's hardware. The interrupt lines are plain fields the
+/// caller sets and clears
+pub struct RamBus {
+    pub ram: Vec<u8>,
+    pub nmi: bool,
+    pub irq: bool,
+    // Every read and write since logging was turned on, in order
+    pub log: Vec<BusAccess>,
+    logging_enabled: bool,
+}
+
+impl Default for RamBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RamBus {
+    pub fn new() -> Self {
+        RamBus {
+            ram: vec![0; RAM_SIZE],
+            nmi: false,
+            irq: false,
+            log: vec![],
+            logging_enabled: false,
+        }
+    }
+
+    /// Copies `data` in from `addr` up, wrapping past $FFFF
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        data.iter().enumerate().for_each(|(i, &val)| {
+            self.ram[addr.wrapping_add(i as u16) as usize] = val;
+        });
+    }
+
+    pub fn enable_logging(&mut self) {
+        self.logging_enabled = true;
+    }
+}
+
+impl CpuBus for RamBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.ram[addr as usize];
+        if self.logging_enabled {
+            self.log.push(BusAccess::Read(addr, val));
+        }
+        val
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.ram[addr as usize] = data;
+        if self.logging_enabled {
+            self.log.push(BusAccess::Write(addr, data));
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn nmi(&self) -> bool {
+        self.nmi
+    }
+
+    fn irq(&self) -> IRQSource {
+        if self.irq {
+            IRQSource::EXT
+        } else {
+            IRQSource::empty()
+        }
+    }
+}
@@ -1,19 +1,16 @@
 use std::io::Write;
 
-use super::{op::OPS, AddressingMode, CPU};
+use super::{op::OPS, AddressingMode, CpuBus, Status, CPU};
 
 pub trait Loggable {
     fn log(&mut self);
 }
 
-impl Loggable for CPU<'_> {
+impl<B: CpuBus> Loggable for CPU<B> {
     fn log(&mut self) {
         if self.logging_enabled {
             let code = self.read_trace(self.pc);
-            let op = OPS[match OPS.binary_search_by_key(&code, |op| op.hex) {
-                Ok(i) => i,
-                Err(_) => panic!("Invalid opcode: {:02x}", code),
-            }];
+            let op = OPS[code as usize];
 
             let begin = self.pc;
             let mut hex_dump = vec![];
@@ -30,7 +27,7 @@ impl Loggable for CPU<'_> {
                         .get_absolute_addr_trace(op.addressing_mode, begin + 1)
                         .unwrap()
                         .0;
-                    (addr, self.read_trace(addr))
+                    (addr, self.read_logged(addr))
                 }
             };
 
@@ -46,7 +43,7 @@ impl Loggable for CPU<'_> {
                     match op.addressing_mode {
                         AddressingMode::Immediate => format!("#${:02x}", address),
                         AddressingMode::ZeroPage => {
-                            format!("${:02x} = {:02x}", mem_addr, self.read_trace(mem_addr))
+                            format!("${:02x} = {:02x}", mem_addr, self.read_logged(mem_addr))
                         }
                         AddressingMode::ZeroPageX => format!(
                             "${:02x},X @ {:02x} = {:02x}",
@@ -149,8 +146,7 @@ impl Loggable for CPU<'_> {
                 .to_string();
 
             let cycles = self.cycle_count;
-            let ppu_scanline = self.bus.ppu.scanline;
-            let ppu_cycle = self.bus.ppu.cycle;
+            let (ppu_scanline, ppu_cycle) = self.bus.ppu_position();
 
             let msg = format!(
                 "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x} PPU:{:3},{:3} CYC:{}",
@@ -158,7 +154,7 @@ impl Loggable for CPU<'_> {
                 self.acc,
                 self.x,
                 self.y,
-                self.status,
+                (self.status.bits() & !Status::BREAK.bits()) | Status::BREAK2.bits(),
                 self.sp,
                 ppu_scanline,
                 ppu_cycle,
@@ -170,3 +166,15 @@ impl Loggable for CPU<'_> {
         }
     }
 }
+
+impl<B: CpuBus> CPU<B> {
+    // The value shown after an operand's address. Nintendulator doesn't read the write-only APU
+    // and I/O registers, and logs them as 00
+    fn read_logged(&self, addr: u16) -> u8 {
+        if matches!(addr, 0x4000..=0x4014 | 0x4018..=0x401f) {
+            0
+        } else {
+            self.read_trace(addr)
+        }
+    }
+}
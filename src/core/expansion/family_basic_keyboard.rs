@@ -0,0 +1,166 @@
+use super::{ExpansionDevice, ExpansionDeviceType};
+
+// Keys are laid out in matrix order: 9 rows of 2 columns, each column returning 4 keys on
+// $4017 bits 1-4. https://www.nesdev.org/wiki/Family_BASIC_Keyboard
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyboardKey {
+    // Row 0
+    F8,
+    Return,
+    LeftBracket,
+    RightBracket,
+    Kana,
+    RightShift,
+    Yen,
+    Stop,
+    // Row 1
+    F7,
+    At,
+    Colon,
+    Semicolon,
+    Underscore,
+    Slash,
+    Minus,
+    Caret,
+    // Row 2
+    F6,
+    O,
+    L,
+    K,
+    Period,
+    Comma,
+    P,
+    Num0,
+    // Row 3
+    F5,
+    I,
+    U,
+    J,
+    M,
+    N,
+    Num9,
+    Num8,
+    // Row 4
+    F4,
+    Y,
+    G,
+    H,
+    B,
+    V,
+    Num7,
+    Num6,
+    // Row 5
+    F3,
+    T,
+    R,
+    D,
+    F,
+    C,
+    Num5,
+    Num4,
+    // Row 6
+    F2,
+    W,
+    S,
+    A,
+    X,
+    Z,
+    E,
+    Num3,
+    // Row 7
+    F1,
+    Escape,
+    Q,
+    Ctrl,
+    LeftShift,
+    Grph,
+    Num1,
+    Num2,
+    // Row 8
+    ClrHome,
+    Up,
+    Right,
+    Left,
+    Down,
+    Space,
+    Del,
+    Ins,
+}
+
+const ROW_COUNT: u8 = 9;
+const KEY_COUNT: usize = ROW_COUNT as usize * 8;
+
+pub struct FamilyBasicKeyboard {
+    row: u8,
+    column: u8,
+    enabled: bool,
+    keys: [bool; KEY_COUNT],
+}
+
+impl Default for FamilyBasicKeyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FamilyBasicKeyboard {
+    pub fn new() -> Self {
+        FamilyBasicKeyboard {
+            row: 0,
+            column: 0,
+            enabled: false,
+            keys: [false; KEY_COUNT],
+        }
+    }
+
+    fn get_active_keys(&self) -> u8 {
+        let start = self.row as usize * 8 + self.column as usize * 4;
+        self.keys[start..start + 4]
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, pressed)| acc | (u8::from(*pressed) << i))
+    }
+}
+
+impl ExpansionDevice for FamilyBasicKeyboard {
+    fn get_type(&self) -> ExpansionDeviceType {
+        ExpansionDeviceType::FamilyBasicKeyboard
+    }
+
+    // $4016 write: [.... .KCR]
+    //   K = Enable keyboard matrix
+    //   C = Column select, going from 1 to 0 advances to the next row
+    //   R = Reset to row 0
+    fn write(&mut self, data: u8) {
+        let prev_column = self.column;
+        self.column = (data & 0x02) >> 1;
+        if self.column == 0 && prev_column == 1 {
+            self.row = (self.row + 1) % (ROW_COUNT + 1);
+        }
+        if data & 0x01 != 0 {
+            self.row = 0;
+        }
+        self.enabled = data & 0x04 != 0;
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_trace(addr)
+    }
+
+    // Keys are active low on $4017 bits 1-4. Once every row has been scanned, the keyboard reports
+    // no keys pressed
+    fn read_trace(&self, addr: u16) -> u8 {
+        if addr != 0x4017 || !self.enabled {
+            return 0;
+        }
+        if self.row < ROW_COUNT {
+            (!self.get_active_keys() << 1) & 0x1e
+        } else {
+            0x1e
+        }
+    }
+
+    fn set_key(&mut self, key: KeyboardKey, pressed: bool) {
+        self.keys[key as usize] = pressed;
+    }
+}
@@ -0,0 +1,77 @@
+use crate::config::Config;
+use crate::ines_parser::NESFile;
+
+use self::family_basic_keyboard::{FamilyBasicKeyboard, KeyboardKey};
+
+pub mod family_basic_keyboard;
+
+// NES 2.0 default expansion device IDs, https://www.nesdev.org/wiki/NES_2.0#Default_Expansion_Device
+const FAMILY_BASIC_KEYBOARD_ID: u8 = 0x23;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExpansionDeviceType {
+    None,
+    FamilyBasicKeyboard,
+}
+
+impl ExpansionDeviceType {
+    pub fn from_config_str(val: &str) -> Option<Self> {
+        match val {
+            "none" => Some(ExpansionDeviceType::None),
+            "family_basic_keyboard" => Some(ExpansionDeviceType::FamilyBasicKeyboard),
+            _ => None,
+        }
+    }
+
+    pub fn from_header_id(id: u8) -> Self {
+        match id {
+            FAMILY_BASIC_KEYBOARD_ID => ExpansionDeviceType::FamilyBasicKeyboard,
+            _ => ExpansionDeviceType::None,
+        }
+    }
+}
+
+pub type BoxedExpansionDevice = Box<dyn ExpansionDevice + Send>;
+
+pub struct ExpansionDeviceFactory;
+
+impl ExpansionDeviceFactory {
+    /// The `expansion_device` config option takes precedence over the device the ROM header asks
+    /// for
+    pub fn from_file(file: &NESFile) -> Option<BoxedExpansionDevice> {
+        let typ = Config::get_string("expansion_device")
+            .and_then(|val| ExpansionDeviceType::from_config_str(&val))
+            .unwrap_or_else(|| {
+                ExpansionDeviceType::from_header_id(file.get_default_expansion_device())
+            });
+        Self::from_type(typ)
+    }
+
+    /// The device the ROM header asks for, ignoring config.toml
+    pub fn from_header(file: &NESFile) -> Option<BoxedExpansionDevice> {
+        Self::from_type(ExpansionDeviceType::from_header_id(
+            file.get_default_expansion_device(),
+        ))
+    }
+
+    pub fn from_type(typ: ExpansionDeviceType) -> Option<BoxedExpansionDevice> {
+        match typ {
+            ExpansionDeviceType::None => None,
+            ExpansionDeviceType::FamilyBasicKeyboard => Some(Box::new(FamilyBasicKeyboard::new())),
+        }
+    }
+}
+
+/// A device plugged into the Famicom expansion port. It sees every write to $4016 and can drive
+/// the upper data lines of $4016/$4017 reads
+pub trait ExpansionDevice {
+    fn get_type(&self) -> ExpansionDeviceType;
+
+    fn write(&mut self, data: u8);
+
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn read_trace(&self, addr: u16) -> u8;
+
+    fn set_key(&mut self, _key: KeyboardKey, _pressed: bool) {}
+}
@@ -1,14 +1,18 @@
 use sdl2::pixels::Color;
+use crate::core::ppu::palettes::Palette;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use std::path::Path;
 use image::{ImageResult, save_buffer};
 use image::ColorType;
+use crate::core::savestate::savestate;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Frame {
     pub image: [u8; 256 * 240 * 3],
-    pub is_zero: [[bool; 256]; 240],
+    // Pre-palette output, the 6 bit colour index with the PPUMASK emphasis bits in bits 6-8. Lets
+    // palettes and video filters be applied after the fact
+    pub indices: [u16; 256 * 240],
 }
 
 impl Default for Frame {
@@ -21,11 +25,12 @@ impl Frame {
     pub fn new() -> Frame {
         Frame {
             image: [0; 256 * 240 * 3],
-            is_zero: [[false; 256]; 240],
+            indices: [0; 256 * 240],
         }
     }
 
-    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+    pub fn set_pixel(&mut self, x: usize, y: usize, palette_index: u16, color: Color) {
+        self.indices[y * 256 + x] = palette_index;
         let index = (y * 256 + x) * 3;
         self.image[index] = color.r;
         self.image[index + 1] = color.g;
@@ -38,7 +43,24 @@ impl Frame {
         hasher.finish()
     }
 
+    /// Redraws the RGB image from the palette indices
+    pub fn repalettize(&mut self, palette: &Palette) {
+        for (pixel, index) in self.image.chunks_exact_mut(3).zip(self.indices.iter()) {
+            let color = palette.get_indexed(*index);
+            pixel.copy_from_slice(&[color.r, color.g, color.b]);
+        }
+    }
+
+    // Same picture under any palette gives the same hash
+    pub fn get_index_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.indices.hash(&mut hasher);
+        hasher.finish()
+    }
+
     pub fn save_buffer(&self, path: impl AsRef<Path>) -> ImageResult<()> {
         save_buffer(path, &self.image, 256, 240, ColorType::Rgb8)
     }
 }
+
+savestate!(Frame { image, indices });
@@ -1,5 +1,7 @@
 use bitflags::bitflags;
 
+use crate::core::savestate::{savestate, savestate_bits};
+
 bitflags! {
     pub struct Buttons: u8 {
         const A =       0b0000_0001;
@@ -55,3 +57,11 @@ impl Joypad {
         self.buttons.bits() & (1 << self.button_idx) >> self.button_idx
     }
 }
+
+savestate_bits!(Buttons);
+
+savestate!(Joypad {
+    is_strobe_on,
+    button_idx,
+    buttons,
+});
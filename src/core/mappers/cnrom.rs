@@ -1,4 +1,5 @@
 use super::{Mapper, Mirroring};
+use crate::core::savestate::savestate;
 
 #[derive(Clone)]
 enum PRGRomMode {
@@ -128,3 +129,11 @@ impl Mapper for CNROM {
         self.nametables[idx][addr as usize]
     }
 }
+
+// CHR is saved in case it's RAM
+savestate!(CNROM {
+    prg_ram,
+    chr_rom,
+    nametables,
+    bank_select,
+});
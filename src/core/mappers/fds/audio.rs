@@ -0,0 +1,294 @@
+use crate::core::savestate::savestate;
+
+// $4089's master volume, as a multiplier out of 36
+const MASTER_VOLUMES: [u32; 4] = [36, 24, 17, 14];
+// How each 3 bit entry of the modulation table moves the mod counter. 4 resets it to 0
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+const MOD_RESET: u8 = 4;
+
+// The FDS at full volume is about 2.4 times as loud as a 2A03 pulse channel at full volume, which
+// is 0.149 of full scale
+const FULL_SCALE: f32 = 0.36;
+
+/// The volume or modulation envelope. With the envelope off the gain is whatever was last written
+#[derive(Clone, Default)]
+struct Envelope {
+    speed: u8,
+    gain: u8,
+    increase: bool,
+    off: bool,
+    timer: u32,
+}
+
+impl Envelope {
+    fn write(&mut self, val: u8, master_speed: u8) {
+        self.speed = val & 0x3F;
+        self.increase = val & 0x40 != 0;
+        self.off = val & 0x80 != 0;
+        self.reset_timer(master_speed);
+        if self.off {
+            self.gain = self.speed;
+        }
+    }
+
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (u32::from(self.speed) + 1) * u32::from(master_speed);
+    }
+
+    // Steps the gain towards 0 or 32 when the timer runs out, returning whether it did
+    fn tick(&mut self, master_speed: u8) -> bool {
+        if self.off || master_speed == 0 {
+            return false;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return false;
+        }
+        self.reset_timer(master_speed);
+        if self.increase && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+        true
+    }
+}
+
+savestate!(Envelope {
+    speed,
+    gain,
+    increase,
+    off,
+    timer
+});
+
+/// The Disk System's sound: one channel playing a 64 step, 6-bit wavetable, with its pitch bent by
+/// a second table through the modulator. Registers are $4040-$408A, see
+/// <https://www.nesdev.org/wiki/FDS_audio>
+#[derive(Clone)]
+pub struct FdsAudio {
+    wave_table: [u8; 64],
+    // Writing the wave table needs $4089 bit 7, which also holds the output
+    wave_write: bool,
+    wave_halted: bool,
+    wave_pos: u8,
+    wave_accumulator: u16,
+    wave_freq: u16,
+    volume: Envelope,
+    envelopes_halted: bool,
+    master_volume: u8,
+    master_speed: u8,
+
+    mod_table: [u8; 64],
+    mod_pos: u8,
+    mod_halted: bool,
+    mod_accumulator: u16,
+    mod_freq: u16,
+    // 7-bit signed
+    mod_counter: i8,
+    mod_envelope: Envelope,
+    // Pitch added to the wave's frequency
+    mod_output: i32,
+
+    output: u8,
+}
+
+impl Default for FdsAudio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FdsAudio {
+    #[must_use]
+    pub fn new() -> Self {
+        FdsAudio {
+            wave_table: [0; 64],
+            wave_write: false,
+            wave_halted: true,
+            wave_pos: 0,
+            wave_accumulator: 0,
+            wave_freq: 0,
+            volume: Envelope::default(),
+            envelopes_halted: false,
+            master_volume: 0,
+            master_speed: 0xE8,
+            mod_table: [0; 64],
+            mod_pos: 0,
+            mod_halted: true,
+            mod_accumulator: 0,
+            mod_freq: 0,
+            mod_counter: 0,
+            mod_envelope: Envelope::default(),
+            mod_output: 0,
+            output: 0,
+        }
+    }
+
+    /// Reads $4040-$407F (the wave table), $4090 (volume gain) and $4092 (mod gain). Only the low
+    /// 6 bits are driven
+    #[must_use]
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x407F => Some(self.wave_table[addr as usize & 0x3F]),
+            0x4090 => Some(self.volume.gain),
+            0x4092 => Some(self.mod_envelope.gain),
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => {
+                self.wave_table[addr as usize & 0x3F] = val & 0x3F;
+            }
+            0x4080 => self.volume.write(val, self.master_speed),
+            0x4082 => self.wave_freq = (self.wave_freq & 0xF00) | u16::from(val),
+            0x4083 => {
+                self.wave_freq = (self.wave_freq & 0xFF) | (u16::from(val & 0x0F) << 8);
+                self.wave_halted = val & 0x80 != 0;
+                self.envelopes_halted = val & 0x40 != 0;
+                if self.wave_halted {
+                    self.wave_pos = 0;
+                    self.wave_accumulator = 0;
+                }
+                if self.envelopes_halted {
+                    self.volume.reset_timer(self.master_speed);
+                    self.mod_envelope.reset_timer(self.master_speed);
+                }
+            }
+            0x4084 => self.mod_envelope.write(val, self.master_speed),
+            0x4085 => {
+                self.set_mod_counter(val & 0x7F);
+                self.update_mod_output();
+            }
+            0x4086 => self.mod_freq = (self.mod_freq & 0xF00) | u16::from(val),
+            0x4087 => {
+                self.mod_freq = (self.mod_freq & 0xFF) | (u16::from(val & 0x0F) << 8);
+                self.mod_halted = val & 0x80 != 0;
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            }
+            // Each write fills two entries, and only while the modulator is halted
+            0x4088 if self.mod_halted => {
+                self.mod_table[self.mod_pos as usize] = val & 0x07;
+                self.mod_table[(self.mod_pos as usize + 1) & 0x3F] = val & 0x07;
+                self.mod_pos = (self.mod_pos + 2) & 0x3F;
+            }
+            0x4089 => {
+                self.master_volume = val & 0x03;
+                self.wave_write = val & 0x80 != 0;
+            }
+            0x408A => self.master_speed = val,
+            _ => {}
+        }
+    }
+
+    // The counter is 7-bit signed, `val` wraps into -64..=63
+    fn set_mod_counter(&mut self, val: u8) {
+        self.mod_counter = ((val << 1) as i8) >> 1;
+    }
+
+    // Works out the pitch the modulator adds to the wave, including the hardware's odd rounding.
+    // See <https://www.nesdev.org/wiki/FDS_audio#Frequency_calculation>
+    fn update_mod_output(&mut self) {
+        let mut temp = i32::from(self.mod_counter) * i32::from(self.mod_envelope.gain);
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+
+        temp *= i32::from(self.wave_freq);
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        self.mod_output = temp;
+    }
+
+    /// Runs one CPU cycle
+    pub fn clock(&mut self) {
+        if !self.wave_halted && !self.envelopes_halted {
+            self.volume.tick(self.master_speed);
+            if self.mod_envelope.tick(self.master_speed) {
+                self.update_mod_output();
+            }
+        }
+
+        if !self.mod_halted && self.mod_freq > 0 {
+            let (sum, overflow) = self.mod_accumulator.overflowing_add(self.mod_freq);
+            self.mod_accumulator = sum;
+            if overflow {
+                let step = self.mod_table[self.mod_pos as usize];
+                let counter = if step == MOD_RESET {
+                    0
+                } else {
+                    self.mod_counter.wrapping_add(MOD_STEPS[step as usize]) as u8
+                };
+                self.set_mod_counter(counter);
+                self.mod_pos = (self.mod_pos + 1) & 0x3F;
+                self.update_mod_output();
+            }
+        }
+
+        // The wave holds still while the table is being written
+        if !self.wave_write {
+            self.update_output();
+        }
+        if self.wave_halted || self.wave_write {
+            return;
+        }
+        let pitch = i32::from(self.wave_freq) + self.mod_output;
+        if pitch > 0 {
+            let (sum, overflow) = self.wave_accumulator.overflowing_add(pitch as u16);
+            self.wave_accumulator = sum;
+            if overflow {
+                self.wave_pos = (self.wave_pos + 1) & 0x3F;
+            }
+        }
+    }
+
+    fn update_output(&mut self) {
+        let level =
+            u32::from(self.volume.gain.min(32)) * MASTER_VOLUMES[self.master_volume as usize];
+        let sample = u32::from(self.wave_table[self.wave_pos as usize]);
+        self.output = (sample * level / 1152) as u8;
+    }
+
+    /// The channel's level as a fraction of full scale, for the mixer
+    #[must_use]
+    pub fn output(&self) -> f32 {
+        f32::from(self.output) / 63. * FULL_SCALE
+    }
+}
+
+// The master speed is set by the BIOS on boot like the other registers, so it's saved with them
+savestate!(FdsAudio {
+    wave_table,
+    wave_write,
+    wave_halted,
+    wave_pos,
+    wave_accumulator,
+    wave_freq,
+    volume,
+    envelopes_halted,
+    master_volume,
+    master_speed,
+    mod_table,
+    mod_pos,
+    mod_halted,
+    mod_accumulator,
+    mod_freq,
+    mod_counter,
+    mod_envelope,
+    mod_output,
+    output,
+});
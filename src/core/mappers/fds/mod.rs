@@ -0,0 +1,513 @@
+use self::audio::FdsAudio;
+use super::{Mapper, Mirroring};
+use crate::core::savestate::{invalid_data, savestate};
+use crate::fds_parser::SIDE_SIZE;
+use std::io;
+
+pub mod audio;
+
+// The drive's view of a side: the blocks from the image with the gaps, start marks and CRCs put
+// back in, padded out to the length of the disk
+const GAPPED_SIDE_SIZE: usize = 80000;
+// 28300 bits of lead in before the first block, and 976 bits between blocks
+const LEAD_IN: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+const BLOCK_START: u8 = 0x80;
+// The CRC isn't stored in the image, and the BIOS only checks the drive's error flag
+const FAKE_CRC: [u8; 2] = [0x4D, 0x62];
+
+// CPU cycles for the head to move one byte along
+const BYTE_DELAY: u32 = 150;
+// CPU cycles from the motor starting to the head reaching the start of the disk
+const REWIND_DELAY: u32 = 50000;
+// A new side goes in after about a second with the drive empty, so the game sees the swap
+const SWAP_DELAY: u32 = 1_800_000;
+
+// The length of a block, from its type. File data is as long as the file header before it says
+fn block_len(side: &[u8], pos: usize, file_size: &mut usize) -> Option<usize> {
+    match side.get(pos)? {
+        1 => Some(56),
+        2 => Some(2),
+        3 => {
+            *file_size = usize::from(*side.get(pos + 13)?) | usize::from(*side.get(pos + 14)?) << 8;
+            Some(16)
+        }
+        4 => Some(1 + *file_size),
+        _ => None,
+    }
+}
+
+// The blocks of `side` with their gaps, start marks and CRCs, not yet padded to the disk's length
+fn gap_blocks(side: &[u8]) -> Vec<u8> {
+    let mut gapped = vec![0; LEAD_IN];
+    let mut pos = 0;
+    let mut file_size = 0;
+    while let Some(len) = block_len(side, pos, &mut file_size) {
+        if pos + len > side.len() {
+            break;
+        }
+        gapped.push(BLOCK_START);
+        gapped.extend_from_slice(&side[pos..pos + len]);
+        gapped.extend_from_slice(&FAKE_CRC);
+        gapped.extend_from_slice(&[0; BLOCK_GAP]);
+        pos += len;
+    }
+    gapped
+}
+
+fn add_gaps(side: &[u8]) -> Vec<u8> {
+    let mut gapped = gap_blocks(side);
+    gapped.resize(GAPPED_SIDE_SIZE, 0);
+    gapped
+}
+
+/// Whether a side from a .fds image still fits on the disk once the gaps between its blocks are
+/// put back. The drive would never reach the end of one that doesn't
+#[must_use]
+pub fn fits_on_disk(side: &[u8]) -> bool {
+    gap_blocks(side).len() <= GAPPED_SIDE_SIZE
+}
+
+// Undoes `add_gaps`, finding the blocks by their start marks so files the game wrote are kept
+fn strip_gaps(gapped: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(SIDE_SIZE);
+    let mut pos = 0;
+    let mut file_size = 0;
+    loop {
+        while gapped.get(pos) == Some(&0) {
+            pos += 1;
+        }
+        if gapped.get(pos) != Some(&BLOCK_START) {
+            break;
+        }
+        pos += 1;
+        let Some(len) = block_len(gapped, pos, &mut file_size) else {
+            break;
+        };
+        let end = (pos + len).min(gapped.len());
+        side.extend_from_slice(&gapped[pos..end]);
+        pos = end + FAKE_CRC.len();
+    }
+    side.resize(SIDE_SIZE, 0);
+    side
+}
+
+/// The Famicom Disk System: the RAM adapter's 32 KB of PRG-RAM and 8 KB of CHR-RAM, the BIOS, the
+/// disk drive and the wavetable channel. See <https://www.nesdev.org/wiki/Family_Computer_Disk_System>
+#[derive(Clone)]
+pub struct FDS {
+    bios: Vec<u8>,
+    ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+    nametables: [[u8; 0x400]; 2],
+    horizontal_mirroring: bool,
+    audio: FdsAudio,
+
+    // Every side back to back, GAPPED_SIDE_SIZE bytes each
+    disk: Vec<u8>,
+    sides: usize,
+    side: Option<usize>,
+    next_side: Option<usize>,
+    swap_delay: u32,
+
+    disk_regs_enabled: bool,
+    sound_regs_enabled: bool,
+
+    timer_reload: u16,
+    timer_counter: u16,
+    timer_repeat: bool,
+    timer_enabled: bool,
+    timer_irq: bool,
+
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    crc_control: bool,
+    previous_crc_control: bool,
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+    disk_irq: bool,
+
+    read_data: u8,
+    write_data: u8,
+    transfer_complete: bool,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    position: usize,
+    delay: u32,
+    crc: u16,
+}
+
+impl FDS {
+    /// `bios` is the 8 KB disksys.rom, `sides` the raw sides from the image. Side A of the first
+    /// disk starts in the drive
+    #[must_use]
+    pub fn new(bios: Vec<u8>, sides: &[Vec<u8>]) -> Self {
+        FDS {
+            bios,
+            ram: vec![0; 0x8000],
+            chr_ram: vec![0; 0x2000],
+            nametables: [[0; 0x400]; 2],
+            horizontal_mirroring: false,
+            audio: FdsAudio::new(),
+            disk: sides.iter().flat_map(|side| add_gaps(side)).collect(),
+            sides: sides.len(),
+            side: (!sides.is_empty()).then_some(0),
+            next_side: None,
+            swap_delay: 0,
+            disk_regs_enabled: false,
+            sound_regs_enabled: false,
+            timer_reload: 0,
+            timer_counter: 0,
+            timer_repeat: false,
+            timer_enabled: false,
+            timer_irq: false,
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: false,
+            crc_control: false,
+            previous_crc_control: false,
+            disk_ready: false,
+            disk_irq_enabled: false,
+            disk_irq: false,
+            read_data: 0,
+            write_data: 0,
+            transfer_complete: false,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            position: 0,
+            delay: 0,
+            crc: 0,
+        }
+    }
+
+    fn disk_byte(&mut self) -> Option<&mut u8> {
+        let side = self.side?;
+        self.disk.get_mut(side * GAPPED_SIDE_SIZE + self.position)
+    }
+
+    // The CRC-16 the drive works out over each block, start mark included
+    fn update_crc(&mut self, val: u8) {
+        let mut val = u16::from(val);
+        for _ in 0..8 {
+            let carry = self.crc & 1 != 0;
+            self.crc = (self.crc >> 1) | ((val & 1) << 15);
+            val >>= 1;
+            if carry {
+                self.crc ^= 0x8408;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.timer_enabled {
+            return;
+        }
+        if self.timer_counter == 0 {
+            self.timer_irq = true;
+            self.timer_counter = self.timer_reload;
+            self.timer_enabled = self.timer_repeat;
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    // Moves the head one CPU cycle along. See <https://www.nesdev.org/wiki/FDS_disk_drive>
+    fn clock_drive(&mut self) {
+        if self.swap_delay > 0 {
+            self.swap_delay -= 1;
+            if self.swap_delay == 0 {
+                self.side = self.next_side.take();
+            }
+        }
+
+        if self.side.is_none() || !self.motor_on {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = REWIND_DELAY;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let mut irq = self.disk_irq_enabled;
+        if self.read_mode {
+            let val = self.disk_byte().map_or(0, |val| *val);
+            if !self.previous_crc_control {
+                self.update_crc(val);
+            }
+            if !self.disk_ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if val != 0 && !self.gap_ended {
+                // The start mark ends the gap, and isn't passed on
+                self.gap_ended = true;
+                irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = val;
+                self.disk_irq |= irq;
+            }
+        } else {
+            let mut val = 0;
+            if !self.crc_control {
+                self.transfer_complete = true;
+                val = self.write_data;
+                self.disk_irq |= irq;
+            }
+            if !self.disk_ready {
+                val = 0;
+            }
+            if self.crc_control {
+                if !self.previous_crc_control {
+                    self.update_crc(0);
+                    self.update_crc(0);
+                }
+                val = self.crc as u8;
+                self.crc >>= 8;
+            } else {
+                self.update_crc(val);
+            }
+            if let Some(byte) = self.disk_byte() {
+                *byte = val;
+            }
+            self.gap_ended = false;
+        }
+
+        self.previous_crc_control = self.crc_control;
+        self.position += 1;
+        if self.position >= GAPPED_SIDE_SIZE {
+            self.motor_on = false;
+            self.disk_irq |= irq;
+        } else {
+            self.delay = BYTE_DELAY;
+        }
+    }
+
+    fn read_register(&mut self, addr: u16) -> Option<u8> {
+        let val = self.peek_expansion_area(addr);
+        match addr {
+            0x4030 => {
+                self.transfer_complete = false;
+                self.timer_irq = false;
+                self.disk_irq = false;
+            }
+            0x4031 => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            _ => {}
+        }
+        val
+    }
+
+    fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | u16::from(val),
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | (u16::from(val) << 8),
+            0x4022 => {
+                self.timer_repeat = val & 0x01 != 0;
+                self.timer_enabled = val & 0x02 != 0 && self.disk_regs_enabled;
+                if self.timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_regs_enabled = val & 0x01 != 0;
+                self.sound_regs_enabled = val & 0x02 != 0;
+                if !self.disk_regs_enabled {
+                    self.timer_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 if self.disk_regs_enabled => {
+                self.write_data = val;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 if self.disk_regs_enabled => {
+                self.motor_on = val & 0x01 != 0;
+                self.reset_transfer = val & 0x02 != 0;
+                self.read_mode = val & 0x04 != 0;
+                self.horizontal_mirroring = val & 0x08 != 0;
+                self.crc_control = val & 0x10 != 0;
+                self.disk_ready = val & 0x40 != 0;
+                self.disk_irq_enabled = val & 0x80 != 0;
+                self.disk_irq = false;
+            }
+            0x4040..=0x408A if self.sound_regs_enabled => self.audio.write(addr, val),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for FDS {
+    fn get_mirroring(&self) -> Mirroring {
+        if self.horizontal_mirroring {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn read_chr_rom(&self, addr: u16) -> u8 {
+        self.chr_ram[addr as usize]
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0xDFFF => self.ram[(addr - 0x6000) as usize],
+            0xE000..=0xFFFF => self.bios[(addr - 0xE000) as usize],
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4020..=0x5FFF => self.write_register(addr, data),
+            0x6000..=0xDFFF => self.ram[(addr - 0x6000) as usize] = data,
+            _ => {}
+        }
+    }
+
+    fn write_chr_rom(&mut self, addr: u16, data: u8) {
+        self.chr_ram[addr as usize] = data;
+    }
+
+    fn write_nametable_idx(&mut self, idx: usize, addr: u16, val: u8) {
+        self.nametables[idx][addr as usize] = val;
+    }
+
+    fn read_nametable_idx(&self, idx: usize, addr: u16) -> u8 {
+        self.nametables[idx][addr as usize]
+    }
+
+    fn read_expansion_area(&mut self, addr: u16) -> Option<u8> {
+        self.read_register(addr)
+    }
+
+    fn peek_expansion_area(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4030 => Some(
+                u8::from(self.timer_irq)
+                    | u8::from(self.transfer_complete) << 1
+                    | u8::from(self.end_of_head) << 6,
+            ),
+            0x4031 => Some(self.read_data),
+            0x4032 => {
+                let no_disk = self.side.is_none();
+                Some(
+                    u8::from(no_disk)
+                        | u8::from(no_disk || !self.scanning) << 1
+                        | u8::from(no_disk) << 2,
+                )
+            }
+            // Bit 7 is the battery, which is always good
+            0x4033 => Some(0x80),
+            _ => self.audio.read(addr),
+        }
+    }
+
+    fn tick(&mut self) {
+        self.clock_timer();
+        self.audio.clock();
+        self.clock_drive();
+    }
+
+    fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn expansion_audio(&self) -> f32 {
+        self.audio.output()
+    }
+
+    // The disk as a .fds image without the header, with whatever the game has written to it
+    fn dump_save(&self) -> Vec<u8> {
+        self.disk
+            .chunks(GAPPED_SIDE_SIZE)
+            .flat_map(strip_gaps)
+            .collect()
+    }
+
+    fn load_save(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.len() != self.sides * SIDE_SIZE {
+            return Err(invalid_data("disk save is for a different number of sides"));
+        }
+        self.disk = data.chunks(SIDE_SIZE).flat_map(add_gaps).collect();
+        Ok(())
+    }
+
+    fn disk_sides(&self) -> usize {
+        self.sides
+    }
+
+    fn disk_side(&self) -> Option<usize> {
+        self.side
+    }
+
+    fn insert_disk(&mut self, side: Option<usize>) {
+        self.side = None;
+        self.next_side = side.filter(|&side| side < self.sides);
+        self.swap_delay = if self.next_side.is_some() {
+            SWAP_DELAY
+        } else {
+            0
+        };
+    }
+}
+
+// The BIOS is ROM, everything else is saved. The disk goes in too, since games write to it
+savestate!(FDS {
+    ram,
+    chr_ram,
+    nametables,
+    horizontal_mirroring,
+    audio,
+    disk,
+    side,
+    next_side,
+    swap_delay,
+    disk_regs_enabled,
+    sound_regs_enabled,
+    timer_reload,
+    timer_counter,
+    timer_repeat,
+    timer_enabled,
+    timer_irq,
+    motor_on,
+    reset_transfer,
+    read_mode,
+    crc_control,
+    previous_crc_control,
+    disk_ready,
+    disk_irq_enabled,
+    disk_irq,
+    read_data,
+    write_data,
+    transfer_complete,
+    end_of_head,
+    scanning,
+    gap_ended,
+    position,
+    delay,
+    crc,
+});
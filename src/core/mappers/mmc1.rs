@@ -1,4 +1,6 @@
 use crate::core::mappers::{Mapper, Mirroring};
+use crate::core::savestate::savestate;
+use std::io;
 
 enum PRGMode {
     PRG16k,
@@ -317,12 +319,30 @@ impl Mapper for MMC1 {
         }
     }
 
-    fn dump_save(&self) -> &[u8] {
-        self.prg_ram.as_slice()
+    fn dump_save(&self) -> Vec<u8> {
+        self.prg_ram.clone()
     }
 
-    fn load_save(&mut self, data: &[u8]) {
+    fn load_save(&mut self, data: &[u8]) -> io::Result<()> {
         dbg!(data.len());
         self.prg_ram = data.to_vec();
+        Ok(())
     }
 }
+
+savestate!(State {
+    control_reg,
+    chr_bank_0_reg,
+    chr_bank_1_reg,
+    prg_bank_reg,
+});
+
+// CHR is saved in case it's RAM
+savestate!(MMC1 {
+    temp_reg,
+    shift_count,
+    state,
+    prg_ram,
+    chr_rom,
+    nametables,
+});
@@ -1,10 +1,11 @@
-use std::sync::{Arc, Mutex};
-
+use crate::core::savestate::Savestate;
 use crate::ines_parser::{Flags1Enum, NESFile};
+use std::io;
 
-use self::{cnrom::CNROM, mmc1::MMC1, nrom::NROM};
+use self::{cnrom::CNROM, fds::FDS, mmc1::MMC1, nrom::NROM};
 
 pub mod cnrom;
+pub mod fds;
 pub mod mmc1;
 pub mod nrom;
 
@@ -16,8 +17,6 @@ pub enum Mirroring {
     SingleScreenB,
 }
 
-pub type SharedMapper = Arc<Mutex<Box<dyn Mapper + Send>>>;
-
 pub struct MapperFactory;
 
 macro_rules! mappers {
@@ -50,11 +49,14 @@ macro_rules! mappers {
 
 impl MapperFactory {
     pub fn from_file(file: &NESFile) -> Box<dyn Mapper + Send> {
+        if let Some(disk) = &file.disk {
+            return Box::new(FDS::new(file.prg_rom_area.clone(), &disk.sides));
+        }
         mappers!(file, (0, NROM), (1, MMC1), (3, CNROM))
     }
 }
 
-pub trait Mapper {
+pub trait Mapper: Savestate {
     fn get_mirroring(&self) -> Mirroring;
 
     fn read_chr_rom(&self, addr: u16) -> u8;
@@ -80,6 +82,30 @@ pub trait Mapper {
         self.write(addr + 1, (data >> 8) as u8);
     }
 
+    /// Reads from $4020-$5FFF. Most boards leave it unconnected, so the default drives nothing and
+    /// the CPU sees open bus
+    fn read_expansion_area(&mut self, addr: u16) -> Option<u8> {
+        self.peek_expansion_area(addr)
+    }
+
+    /// `read_expansion_area` without side effects, for the tracer
+    fn peek_expansion_area(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    /// Called once every CPU cycle, for boards with timers or sound of their own
+    fn tick(&mut self) {}
+
+    /// Whether the board is holding the IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// The board's own sound as a fraction of full scale, mixed in after the APU
+    fn expansion_audio(&self) -> f32 {
+        0.
+    }
+
     fn read_trace(&self, addr: u16) -> u8 {
         self.read(addr)
     }
@@ -122,9 +148,25 @@ pub trait Mapper {
         }
     }
 
-    fn dump_save(&self) -> &[u8] {
-        &[]
+    fn dump_save(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    /// Restores what `dump_save` gave, failing when it can't be from this cartridge
+    fn load_save(&mut self, _data: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Number of disk sides there are to insert. Zero for everything but the Disk System
+    fn disk_sides(&self) -> usize {
+        0
+    }
+
+    /// The side in the drive, if there is one
+    fn disk_side(&self) -> Option<usize> {
+        None
     }
 
-    fn load_save(&mut self, _data: &[u8]) {}
+    /// Ejects the disk, then puts `side` in if it's given
+    fn insert_disk(&mut self, _side: Option<usize>) {}
 }
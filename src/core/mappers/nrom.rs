@@ -1,4 +1,5 @@
 use super::{Mapper, Mirroring};
+use crate::core::savestate::savestate;
 
 #[derive(Clone)]
 enum PRGRomMode {
@@ -117,3 +118,10 @@ impl Mapper for NROM {
         self.nametables[idx][addr as usize]
     }
 }
+
+// CHR is saved in case it's RAM
+savestate!(NROM {
+    prg_ram,
+    chr_rom,
+    nametables,
+});
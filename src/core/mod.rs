@@ -2,7 +2,10 @@ pub mod apu;
 pub mod bus;
 pub mod console;
 pub mod cpu;
+pub mod expansion;
 pub mod frame;
 pub mod joypad;
 pub mod mappers;
+pub mod nsf;
 pub mod ppu;
+pub mod savestate;
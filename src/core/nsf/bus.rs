@@ -0,0 +1,188 @@
+use crate::core::apu::APU;
+use crate::core::cpu::{CpuBus, IRQSource};
+use crate::core::mappers::fds::audio::FdsAudio;
+use crate::nsf_parser::{ExpansionAudio, NsfFile};
+
+const BANK_SIZE: usize = 0x1000;
+
+/// Where the player parks the CPU between calls. The space belongs to the PPU, which an NSF
+/// player doesn't have, so nothing a rip does can land on it
+pub const IDLE_LOOP: u16 = 0x3F00;
+// JMP IDLE_LOOP
+const IDLE_LOOP_CODE: [u8; 3] = [0x4C, IDLE_LOOP as u8, (IDLE_LOOP >> 8) as u8];
+
+/// The hardware an NSF rip sees: 2KB of RAM, the APU, 8KB of RAM at $6000-$7FFF and the rip's
+/// data at $8000-$FFFF, in 4KB banks picked by writing $5FF8-$5FFF when it's bankswitched.
+/// Rips for the Disk System get its sound, and RAM over all of $6000-$FFFF that the banks are
+/// copied into, with $5FF6-$5FF7 for the two banks at $6000-$7FFF
+pub struct NsfBus {
+    pub apu: APU,
+    pub ram: [u8; 0x800],
+    pub prg_ram: [u8; 0x2000],
+    // Data padded out to whole banks
+    rom: Vec<u8>,
+    initial_banks: [u8; 8],
+    // Bank at each 4KB of $8000-$FFFF
+    banks: [u8; 8],
+    bankswitched: bool,
+    cpu_cycle: u64,
+    fds: Option<FdsAudio>,
+    // The Disk System's RAM at $6000-$FFFF, empty for other rips
+    fds_ram: Vec<u8>,
+    initial_fds_banks: [u8; 2],
+}
+
+impl NsfBus {
+    pub fn new(file: &NsfFile) -> Self {
+        // Bankswitched data starts at the load address' offset into its bank, otherwise it sits
+        // at the load address in a flat 32KB
+        let fds = file.expansion.contains(ExpansionAudio::FDS);
+        let (rom, initial_banks) = match file.banks {
+            Some(banks) => {
+                let padding = file.load_addr as usize & (BANK_SIZE - 1);
+                let mut rom = vec![0; padding];
+                rom.extend_from_slice(&file.data);
+                rom.resize(rom.len().div_ceil(BANK_SIZE) * BANK_SIZE, 0);
+                (rom, banks)
+            }
+            // Disk System rips can load from $6000, so theirs starts two banks lower
+            None => {
+                let base = if fds { 0x6000 } else { 0x8000 };
+                let mut rom = vec![0; (0x10000 - base) / BANK_SIZE * BANK_SIZE];
+                let start = file.load_addr as usize - base;
+                let len = file.data.len().min(rom.len() - start);
+                rom[start..start + len].copy_from_slice(&file.data[..len]);
+                let first = ((0x8000 - base) / BANK_SIZE) as u8;
+                (rom, std::array::from_fn(|i| first + i as u8))
+            }
+        };
+        let initial_fds_banks = match file.banks {
+            Some(banks) => [banks[6], banks[7]],
+            None => [0, 1],
+        };
+
+        NsfBus {
+            apu: APU::new(),
+            ram: [0; 0x800],
+            prg_ram: [0; 0x2000],
+            rom,
+            initial_banks,
+            banks: initial_banks,
+            bankswitched: file.banks.is_some(),
+            cpu_cycle: 0,
+            fds: fds.then(FdsAudio::new),
+            fds_ram: if fds { vec![0; 0xA000] } else { Vec::new() },
+            initial_fds_banks,
+        }
+    }
+
+    /// Clears RAM and puts the banks back the way the header has them, ready for INIT. The APU is
+    /// reset by the player through its registers
+    pub fn init(&mut self) {
+        self.ram.fill(0);
+        self.prg_ram.fill(0);
+        self.banks = self.initial_banks;
+        if let Some(fds) = &mut self.fds {
+            *fds = FdsAudio::new();
+            self.fds_ram.fill(0);
+            for (slot, bank) in self
+                .initial_fds_banks
+                .into_iter()
+                .chain(self.banks)
+                .enumerate()
+            {
+                self.copy_bank(slot, bank);
+            }
+        }
+    }
+
+    // Copies `bank` into the Disk System's RAM at $6000 + `slot` * 4KB
+    fn copy_bank(&mut self, slot: usize, bank: u8) {
+        let offset = (bank as usize * BANK_SIZE) % self.rom.len();
+        self.fds_ram[slot * BANK_SIZE..(slot + 1) * BANK_SIZE]
+            .copy_from_slice(&self.rom[offset..offset + BANK_SIZE]);
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = self.banks[(addr as usize - 0x8000) / BANK_SIZE] as usize;
+        let offset = bank * BANK_SIZE + (addr as usize & (BANK_SIZE - 1));
+        self.rom[offset % self.rom.len()]
+    }
+
+    fn read_memory(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.ram[addr as usize & 0x7FF]),
+            IDLE_LOOP..=0x3F02 => Some(IDLE_LOOP_CODE[(addr - IDLE_LOOP) as usize]),
+            0x4040..=0x4092 => self.fds.as_ref().and_then(|fds| fds.read(addr)),
+            0x6000..=0xFFFF if self.fds.is_some() => Some(self.fds_ram[addr as usize - 0x6000]),
+            0x6000..=0x7FFF => Some(self.prg_ram[addr as usize - 0x6000]),
+            0x8000..=0xFFFF => Some(self.read_rom(addr)),
+            _ => None,
+        }
+    }
+}
+
+impl CpuBus for NsfBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4015 => self.apu.read_status(),
+            _ => self.read_memory(addr).unwrap_or(0),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram[addr as usize & 0x7FF] = data,
+            0x4000..=0x4017 => self.apu.write_register(addr, data, self.cpu_cycle),
+            0x4040..=0x408A => {
+                if let Some(fds) = &mut self.fds {
+                    fds.write(addr, data);
+                }
+            }
+            0x5FF6..=0x5FFF if self.bankswitched && self.fds.is_some() => {
+                self.copy_bank(addr as usize - 0x5FF6, data);
+            }
+            0x5FF8..=0x5FFF if self.bankswitched => {
+                self.banks[addr as usize - 0x5FF8] = data;
+            }
+            0x6000..=0xFFFF if self.fds.is_some() => self.fds_ram[addr as usize - 0x6000] = data,
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000] = data,
+            _ => {}
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x4015 => self.apu.read_status_trace(),
+            _ => self.read_memory(addr).unwrap_or(0),
+        }
+    }
+
+    fn tick(&mut self, cycle: u64) {
+        self.cpu_cycle = cycle;
+        if let Some(fds) = &mut self.fds {
+            fds.clock();
+            self.apu.set_expansion_output(fds.output());
+        }
+        self.apu.clock();
+    }
+
+    fn irq(&self) -> IRQSource {
+        let mut source = IRQSource::empty();
+        source.set(IRQSource::FRAME_COUNTER, self.apu.irq_pending());
+        source.set(IRQSource::DMC, self.apu.dmc.irq_flag);
+        source
+    }
+
+    fn dmc_dma_requested(&self) -> bool {
+        self.apu.need_dmc_transfer()
+    }
+
+    fn dmc_dma_addr(&self) -> u16 {
+        self.apu.dmc.current_addr
+    }
+
+    fn finish_dmc_dma(&mut self, data: u8) {
+        self.apu.dmc.set_dmc_read_buffer(data);
+    }
+}
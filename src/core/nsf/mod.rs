@@ -0,0 +1,262 @@
+use std::time::Duration;
+
+use crate::core::apu::{Region, APU};
+use crate::core::cpu::{CpuBus, Status, CPU};
+use crate::nsf_parser::{ExpansionAudio, NsfFile, NsfRegion};
+
+use self::bus::IDLE_LOOP;
+
+pub use self::bus::NsfBus;
+
+pub mod bus;
+
+/// How long a song plays when the rip doesn't say, and how long it then takes to fade out
+pub const DEFAULT_TRACK_LENGTH: Duration = Duration::from_secs(150);
+pub const DEFAULT_FADE_LENGTH: Duration = Duration::from_secs(8);
+
+// CPU cycles in a frame, the amount `step_frame` runs
+const CYCLES_PER_FRAME: f64 = 29780.5;
+const PAL_CYCLES_PER_FRAME: f64 = 33247.5;
+
+/// Plays an NSF rip the way an NSF player cartridge would: INIT once with the song number in A,
+/// then PLAY at the rate the header asks for. Between calls the CPU spins in an idle loop, so the
+/// APU keeps running on the CPU's clock. PAL rips run on PAL timing, dual region rips as NTSC
+pub struct NsfPlayer {
+    cpu: CPU<NsfBus>,
+    file: NsfFile,
+    song: u8,
+    // Cycle INIT was called on
+    song_start: u64,
+    // Cycles between PLAY calls, and the cycle the next one is due on
+    play_period: f64,
+    next_play: f64,
+    frame_end: f64,
+    cycles_per_frame: f64,
+    default_length: Duration,
+    default_fade: Duration,
+    // Samples from frames that haven't been drained yet
+    audio: Vec<i16>,
+}
+
+impl NsfPlayer {
+    /// Loads `file` and starts its first song
+    #[must_use]
+    pub fn new(file: NsfFile) -> Self {
+        let unsupported = (file.expansion - ExpansionAudio::FDS).names();
+        if !unsupported.is_empty() {
+            println!(
+                "{} audio isn't emulated, only the 2A03 part of this rip will play",
+                unsupported.join(", ")
+            );
+        }
+
+        let (region, speed, cycles_per_frame) = match file.region {
+            NsfRegion::Pal => (Region::Pal, file.pal_speed, PAL_CYCLES_PER_FRAME),
+            NsfRegion::Ntsc | NsfRegion::Dual => (Region::Ntsc, file.ntsc_speed, CYCLES_PER_FRAME),
+        };
+        let mut cpu = CPU::new(NsfBus::new(&file));
+        cpu.bus.apu.set_region(region);
+        let mut player = NsfPlayer {
+            play_period: f64::from(speed) * cpu.bus.apu.clock_rate() / 1_000_000.,
+            cpu,
+            song: file.starting_song,
+            song_start: 0,
+            next_play: 0.,
+            frame_end: 0.,
+            cycles_per_frame,
+            default_length: DEFAULT_TRACK_LENGTH,
+            default_fade: DEFAULT_FADE_LENGTH,
+            audio: Vec::new(),
+            file,
+        };
+        player.play_song(player.song);
+        player
+    }
+
+    #[must_use]
+    pub const fn file(&self) -> &NsfFile {
+        &self.file
+    }
+
+    /// The song playing, zero based
+    #[must_use]
+    pub const fn song(&self) -> u8 {
+        self.song
+    }
+
+    /// Starts `song` from the beginning: clears RAM, silences the APU and calls INIT. PLAY starts
+    /// once INIT returns
+    pub fn play_song(&mut self, song: u8) {
+        self.song = song.min(self.file.total_songs - 1);
+
+        let cpu = &mut self.cpu;
+        cpu.bus.init();
+        (0x4000..=0x4013).for_each(|addr| cpu.bus.write(addr, 0));
+        cpu.bus.write(0x4015, 0);
+        cpu.bus.write(0x4015, 0x0F);
+        cpu.bus.write(0x4017, 0x40);
+
+        cpu.acc = self.song;
+        cpu.x = u8::from(self.file.region == NsfRegion::Pal);
+        cpu.y = 0;
+        cpu.sp = 0xFD;
+        cpu.status = Status::INTERRUPT_DISABLE;
+        self.call(self.file.init_addr);
+
+        self.song_start = self.cpu.cycle_count;
+        self.next_play = self.song_start as f64 + self.play_period;
+    }
+
+    /// Moves through the playlist, wrapping at either end
+    pub fn next_song(&mut self) {
+        self.step_playlist(1);
+    }
+
+    pub fn previous_song(&mut self) {
+        self.step_playlist(self.file.playlist.len() - 1);
+    }
+
+    fn step_playlist(&mut self, step: usize) {
+        let playlist = &self.file.playlist;
+        let pos = playlist
+            .iter()
+            .position(|&song| song == self.song)
+            .unwrap_or(0);
+        self.play_song(playlist[(pos + step) % playlist.len()]);
+    }
+
+    // Calls `routine` as if with JSR from the idle loop, so its RTS lands back there
+    fn call(&mut self, routine: u16) {
+        let cpu = &mut self.cpu;
+        for byte in (IDLE_LOOP - 1).to_be_bytes() {
+            cpu.bus.ram[0x100 + cpu.sp as usize] = byte;
+            cpu.sp = cpu.sp.wrapping_sub(1);
+        }
+        cpu.pc = routine;
+    }
+
+    /// Runs for the length of a frame in the rip's region, calling PLAY whenever it's due. A PLAY
+    /// that runs long pushes the next call back instead of leaving a backlog of them
+    pub fn step_frame(&mut self) {
+        let fade_from = self.gain();
+        self.frame_end += self.cycles_per_frame;
+        while (self.cpu.cycle_count as f64) < self.frame_end {
+            let cycle = self.cpu.cycle_count as f64;
+            if self.cpu.pc == IDLE_LOOP && cycle >= self.next_play {
+                self.call(self.file.play_addr);
+                self.next_play = (self.next_play + self.play_period).max(cycle);
+            }
+            self.cpu.step();
+        }
+
+        let start = self.audio.len();
+        let channels = self.audio_channels();
+        self.cpu.bus.apu.end_frame(&mut self.audio);
+        let fade_to = self.gain();
+        if fade_to < 1. {
+            let frames = ((self.audio.len() - start) / channels).max(1) as f32;
+            for (i, frame) in self.audio[start..].chunks_mut(channels).enumerate() {
+                let gain = fade_from + (fade_to - fade_from) * i as f32 / frames;
+                frame
+                    .iter_mut()
+                    .for_each(|sample| *sample = (f32::from(*sample) * gain) as i16);
+            }
+        }
+
+        // Keep a little over a second when nobody's draining, dropping the oldest samples
+        let limit = 64 * 1024 * channels;
+        if self.audio.len() > limit {
+            self.audio.drain(..self.audio.len() - limit);
+        }
+    }
+
+    // Volume through the fade at the end of the song
+    fn gain(&self) -> f32 {
+        let (length, fade) = (self.track_length(self.song), self.track_fade(self.song));
+        let faded = self.elapsed().saturating_sub(length);
+        if faded.is_zero() {
+            1.
+        } else if faded >= fade {
+            0.
+        } else {
+            1. - faded.as_secs_f32() / fade.as_secs_f32()
+        }
+    }
+
+    /// Time since the song started
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        let cycles = self.cpu.cycle_count - self.song_start;
+        Duration::from_secs_f64(cycles as f64 / self.cpu.bus.apu.clock_rate())
+    }
+
+    /// Whether the song has played out its length and fade
+    #[must_use]
+    pub fn finished(&self) -> bool {
+        self.elapsed() >= self.track_length(self.song) + self.track_fade(self.song)
+    }
+
+    /// How long `song` plays before fading, from the NSFe if it says
+    #[must_use]
+    pub fn track_length(&self, song: u8) -> Duration {
+        self.file.tracks[song as usize]
+            .length
+            .unwrap_or(self.default_length)
+    }
+
+    #[must_use]
+    pub fn track_fade(&self, song: u8) -> Duration {
+        self.file.tracks[song as usize]
+            .fade
+            .unwrap_or(self.default_fade)
+    }
+
+    /// Length and fade for songs the rip has no times for
+    pub fn set_default_length(&mut self, length: Duration, fade: Duration) {
+        self.default_length = length;
+        self.default_fade = fade;
+    }
+
+    /// Plays `song` from the beginning to the end of its fade, returning all of it
+    pub fn render_song(&mut self, song: u8) -> Vec<i16> {
+        self.play_song(song);
+        self.audio.clear();
+        let mut out = Vec::new();
+        while !self.finished() {
+            self.step_frame();
+            self.drain_audio(&mut out);
+        }
+        out
+    }
+
+    /// Moves the samples generated since the last call onto the end of `out`. Interleaved
+    /// left/right when the mixer is in stereo
+    pub fn drain_audio(&mut self, out: &mut Vec<i16>) {
+        out.append(&mut self.audio);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.cpu.bus.apu.set_sample_rate(sample_rate);
+    }
+
+    /// How many times a second `step_frame` needs calling to play in real time
+    #[must_use]
+    pub fn frame_rate(&self) -> f64 {
+        self.cpu.bus.apu.clock_rate() / self.cycles_per_frame
+    }
+
+    #[must_use]
+    pub fn audio_channels(&self) -> usize {
+        self.cpu.bus.apu.output_channels()
+    }
+
+    /// For settings like the mixer and output filter
+    pub fn apu_mut(&mut self) -> &mut APU {
+        &mut self.cpu.bus.apu
+    }
+
+    #[must_use]
+    pub const fn cpu(&self) -> &CPU<NsfBus> {
+        &self.cpu
+    }
+}
@@ -0,0 +1,25 @@
+/// Debug switches for what ends up in the frame. They only change the picture, sprite 0 hit and
+/// everything else the CPU can see are worked out as if all layers were shown
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RenderLayers {
+    pub background: bool,
+    pub sprites: bool,
+    // Only draw sprites with the priority bit set, the ones behind the background
+    pub priority_sprites_only: bool,
+}
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        Self {
+            background: true,
+            sprites: true,
+            priority_sprites_only: false,
+        }
+    }
+}
+
+impl RenderLayers {
+    pub(super) const fn shows_sprite(&self, behind_background: bool) -> bool {
+        self.sprites && (behind_background || !self.priority_sprites_only)
+    }
+}
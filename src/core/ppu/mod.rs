@@ -1,12 +1,18 @@
 use crate::core::frame::Frame;
-use crate::core::mappers::SharedMapper;
+use crate::core::mappers::Mapper;
 use crate::core::ppu::palettes::Palette;
+use crate::core::savestate::{savestate, Savestate};
 
+use self::layers::RenderLayers;
 use self::registers::{control::Control, mask::Mask, status::Status};
 
+pub mod layers;
 pub mod palettes;
 mod registers;
 
+// About half a second, real consoles take somewhere around 600ms
+const OPEN_BUS_DECAY_FRAMES: usize = 30;
+
 pub enum DMAFlag {
     Enabled(u8),
     Disabled,
@@ -23,17 +29,40 @@ struct Tile {
 
 #[derive(Debug, Default, Clone, Copy)]
 struct Sprite {
-    offset_y: u8,
-    tile_addr: u16,
     palette_offset: u32,
     priority: bool,
     flip_horizontal: bool,
-    flip_vertical: bool,
     sprite_x: u8,
     low_byte: u8,
     high_byte: u8,
 }
 
+impl Sprite {
+    fn new(attr: u8, sprite_x: u8, low_byte: u8, high_byte: u8) -> Self {
+        Sprite {
+            palette_offset: (((attr & 0x03) << 2) | 0x10) as u32,
+            priority: attr & 0x20 == 0x20,
+            flip_horizontal: attr & 0x40 == 0x40,
+            sprite_x,
+            low_byte,
+            high_byte,
+        }
+    }
+
+    // 2 bit colour of the sprite at `cycle`, 0 when transparent or not under the dot
+    fn color_at(&self, cycle: u64) -> u8 {
+        let shift = cycle as i32 - self.sprite_x as i32 - 1;
+        if !(0..8).contains(&shift) {
+            return 0;
+        }
+        if self.flip_horizontal {
+            ((self.low_byte >> shift) & 0x01) | ((self.high_byte >> shift) & 0x01) << 1
+        } else {
+            ((self.low_byte << shift) & 0x80) >> 7 | ((self.high_byte << shift) & 0x80) >> 6
+        }
+    }
+}
+
 pub struct PPU {
     // PPU Registers
     ctrl: Control,
@@ -46,6 +75,14 @@ pub struct PPU {
     sprite_ram: [u8; 0x100],
     // Contains the 8 sprites that will be drawn on the next scanline
     sprite_tiles: [Sprite; 8],
+    // In-range sprites past the first 8, only drawn with the sprite limit removed. Sprite eval,
+    // the overflow flag and sprite 0 hit never see these
+    remove_sprite_limit: bool,
+    extra_sprites: Vec<Sprite>,
+    layers: RenderLayers,
+    // Set at power on and by reset until the end of the next vblank, $2000, $2001, $2005 and $2006
+    // ignore writes until then
+    warming_up: bool,
 
     pub(crate) cycle: u64,
     pub(crate) scanline: i16,
@@ -54,7 +91,6 @@ pub struct PPU {
     pub curr_frame: Frame,
 
     pub nmi_generated: bool,
-    mapper: SharedMapper,
 
     // Represents the first cycle a BG pixel or sprite can be draw. Modified by mask and enable
     // flags, but is otherwise 0
@@ -108,11 +144,19 @@ pub struct PPU {
     update_vram_addr: u16,
     master_clock: u64,
     pub open_bus: u8,
+    // Frame each open bus bit was last driven
+    open_bus_stamps: [usize; 8],
     pub sprite_dma_transfer: DMAFlag,
 }
 
+impl Default for PPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PPU {
-    pub fn new(mapper: SharedMapper) -> PPU {
+    pub fn new() -> PPU {
         PPU {
             ctrl: Control::new(),
             status_flags: Status::new(),
@@ -121,6 +165,10 @@ impl PPU {
             sprite_ram_addr: 0,
             sprite_ram: [0; 0x100],
             sprite_tiles: [Sprite::default(); 8],
+            remove_sprite_limit: false,
+            extra_sprites: Vec::with_capacity(56),
+            layers: RenderLayers::default(),
+            warming_up: true,
             palette: [
                 0x09, 0x01, 0x00, 0x01, 0x00, 0x02, 0x02, 0x0D, 0x08, 0x10, 0x08, 0x24, 0x00, 0x00,
                 0x04, 0x2C, 0x09, 0x01, 0x34, 0x03, 0x00, 0x04, 0x00, 0x14, 0x08, 0x3A, 0x00, 0x02,
@@ -131,7 +179,6 @@ impl PPU {
             scanline: 0,
             curr_frame: Frame::new(),
             nmi_generated: false,
-            mapper,
             minimum_draw_bg_cycle: 0,
             minimum_draw_sprite_cycle: 0,
             high_bit_shift: 0,
@@ -169,6 +216,7 @@ impl PPU {
             update_vram_addr: 0,
             master_clock: 0,
             open_bus: 0,
+            open_bus_stamps: [0; 8],
             sprite_dma_transfer: DMAFlag::Disabled,
         }
     }
@@ -197,7 +245,7 @@ impl PPU {
         self.rendering_enabled
     }
 
-    fn load_tile_info(&mut self) {
+    fn load_tile_info(&mut self, mapper: &mut dyn Mapper) {
         if self.is_rendering_enabled() {
             // First 240 scanlines run on 8 cycle intervals for tile loading, we'll emulate the
             // fetch on the first cycle of a memory access
@@ -214,7 +262,7 @@ impl PPU {
                     self.low_bit_shift |= self.next_tile.low as u16;
                     self.high_bit_shift |= self.next_tile.high as u16;
 
-                    let tile_index = self.read_vram(self.get_nametable_addr()) as u16;
+                    let tile_index = self.read_vram(mapper, self.get_nametable_addr()) as u16;
                     let tile_addr = (tile_index << 4)
                         | (self.vram_addr >> 12)
                         | self.get_background_pattern_addr();
@@ -224,14 +272,14 @@ impl PPU {
                 3 => {
                     // ORs 2nd bits of coarse x and y scrolls -> YX0
                     let shift = ((self.vram_addr >> 4) & 0x04) | (self.vram_addr & 0x02);
-                    self.next_tile.palette_offset =
-                        (((self.read_vram(self.get_attribute_addr()) >> shift) & 0x03) << 2) as u32;
+                    let attribute = self.read_vram(mapper, self.get_attribute_addr());
+                    self.next_tile.palette_offset = (((attribute >> shift) & 0x03) << 2) as u32;
                 }
                 5 => {
-                    self.next_tile.low = self.read_vram(self.next_tile.tile_addr);
+                    self.next_tile.low = self.read_vram(mapper, self.next_tile.tile_addr);
                 }
                 7 => {
-                    self.next_tile.high = self.read_vram(self.next_tile.tile_addr + 8);
+                    self.next_tile.high = self.read_vram(mapper, self.next_tile.tile_addr + 8);
                 }
                 _ => {}
             }
@@ -276,16 +324,23 @@ impl PPU {
         };
     }
 
-    pub fn run_to(&mut self, cycle: u64) -> bool {
+    /// Holds the PPU back until the CPU's master clock reaches `master_clock`. Consoles don't
+    /// power the two on in step, so this lets a fresh PPU line up with another emulator's logs
+    pub fn set_master_clock(&mut self, master_clock: u64) {
+        self.master_clock = master_clock;
+    }
+
+    // The cartridge belongs to the bus, which lends it out for pattern and nametable fetches
+    pub fn run_to(&mut self, cycle: u64, mapper: &mut dyn Mapper) -> bool {
         let mut new_frame = false;
         while self.master_clock + 4 <= cycle {
-            new_frame |= self.run();
+            new_frame |= self.run(mapper);
             self.master_clock += 4;
         }
         new_frame
     }
 
-    fn run(&mut self) -> bool {
+    fn run(&mut self, mapper: &mut dyn Mapper) -> bool {
         if self.cycle > 339 {
             self.cycle = 0;
             self.scanline += 1;
@@ -295,18 +350,20 @@ impl PPU {
                 self.update_minimum_draw_cycles();
             }
             if self.scanline == -1 {
+                self.warming_up = false;
                 self.status_flags.set(Status::SPRITE_OVERFLOW, false);
                 self.status_flags.set(Status::SPRITE_ZERO_HIT, false);
                 self.curr_frame = Frame::new();
             } else if self.scanline == 240 {
                 self.set_bus_address(self.vram_addr);
                 self.frame_count += 1;
+                self.decay_open_bus();
                 return true;
             }
         } else {
             self.cycle += 1;
             if self.scanline < 240 {
-                self.process_scanline();
+                self.process_scanline(mapper);
             } else if self.cycle == 1 && self.scanline == 241 {
                 if !self.prevent_vbl_flag {
                     self.status_flags.set(Status::VBLANK, true);
@@ -325,20 +382,29 @@ impl PPU {
         false
     }
 
-    fn load_sprite_tile_info(&mut self) {
+    fn load_sprite_tile_info(&mut self, mapper: &mut dyn Mapper) {
         let sprite_addr = self.sprite_index as u16 * 4;
-        self.load_sprite(sprite_addr as usize);
+        self.load_sprite(mapper, sprite_addr as usize);
     }
 
-    fn load_sprite(&mut self, sprite_addr: usize) {
+    fn load_sprite(&mut self, mapper: &mut dyn Mapper, sprite_addr: usize) {
         let data: &[u8] = &self.secondary_sprite_ram[sprite_addr..sprite_addr + 4];
-        let sprite_y = data[0];
-        let tile_idx = data[1];
-        let attr = data[2];
-        let sprite_x = data[3];
+        let (sprite_y, tile_idx, attr, sprite_x) = (data[0], data[1], data[2], data[3]);
+        let tile_addr = self.sprite_row_addr(sprite_y, tile_idx, attr);
+
+        if self.sprite_index < self.sprite_count && sprite_y < 240 {
+            let low_byte = self.read_vram(mapper, tile_addr);
+            let high_byte = self.read_vram(mapper, tile_addr + 8);
+            self.sprite_tiles[self.sprite_index as usize] =
+                Sprite::new(attr, sprite_x, low_byte, high_byte);
+            self.mark_sprite_dots(sprite_x);
+        }
+
+        self.sprite_index += 1;
+    }
 
-        let background_priority = attr & 0x20 == 0x20;
-        let horizontal_mirror = attr & 0x40 == 0x40;
+    // Pattern table address of the row of a sprite on the next scanline
+    fn sprite_row_addr(&self, sprite_y: u8, tile_idx: u8, attr: u8) -> u16 {
         let vertical_mirror = attr & 0x80 == 0x80;
 
         let line_offset = if vertical_mirror {
@@ -351,7 +417,7 @@ impl PPU {
             (self.scanline - sprite_y as i16) as u8
         };
 
-        let tile_addr = if self.ctrl.contains(Control::SPRITE_SIZE) {
+        if self.ctrl.contains(Control::SPRITE_SIZE) {
             let tile_addr_1 =
                 ((tile_idx as u16 & 0x01) * 0x1000) | ((tile_idx as u16 & !0x01) << 4);
             let tile_addr_2 = (if line_offset >= 8 {
@@ -367,50 +433,123 @@ impl PPU {
                 } else {
                     0x0000
                 }) + line_offset as u16)
-        };
+        }
+    }
 
-        if self.sprite_index < self.sprite_count && sprite_y < 240 {
-            let low_byte = self.read_vram(tile_addr);
-            let high_byte = self.read_vram(tile_addr + 8);
-            let info = &mut self.sprite_tiles[self.sprite_index as usize];
-            info.priority = background_priority;
-            info.flip_horizontal = horizontal_mirror;
-            info.flip_vertical = vertical_mirror;
-            info.palette_offset = (((attr & 0x03) << 2) | 0x10) as u32;
-            info.low_byte = low_byte;
-            info.high_byte = high_byte;
-            info.tile_addr = tile_addr;
-            info.offset_y = line_offset;
-            info.sprite_x = sprite_x;
-            if self.scanline >= 0 {
-                let mut i = 0;
-                while i < 8 && (sprite_x as u16 + i + 1) < 257 {
-                    self.has_sprite[(sprite_x as u16 + i + 1) as usize] = true;
-                    i += 1;
-                }
+    fn mark_sprite_dots(&mut self, sprite_x: u8) {
+        if self.scanline >= 0 {
+            let mut i = 0;
+            while i < 8 && (sprite_x as u16 + i + 1) < 257 {
+                self.has_sprite[(sprite_x as u16 + i + 1) as usize] = true;
+                i += 1;
             }
         }
+    }
 
-        self.sprite_index += 1;
+    // Finds the sprites the hardware dropped after the first 8. Pattern data is read straight
+    // from the mapper so the PPU bus, and anything watching it like MMC3's IRQ counter, doesn't
+    // see the extra fetches
+    fn load_extra_sprites(&mut self, mapper: &dyn Mapper) {
+        self.extra_sprites.clear();
+        if self.sprite_count < 8 || self.scanline < 0 {
+            return;
+        }
+        let height = if self.ctrl.contains(Control::SPRITE_SIZE) {
+            16
+        } else {
+            8
+        };
+        let first = self.last_visible_sprite_addr as usize / 4 + 1;
+        for index in first..64 {
+            let data = &self.sprite_ram[index * 4..index * 4 + 4];
+            let (sprite_y, tile_idx, attr, sprite_x) = (data[0], data[1], data[2], data[3]);
+            let row = self.scanline - sprite_y as i16;
+            if sprite_y >= 240 || !(0..height).contains(&row) {
+                continue;
+            }
+            let tile_addr = self.sprite_row_addr(sprite_y, tile_idx, attr);
+            let low_byte = mapper.read_chr_rom(tile_addr);
+            let high_byte = mapper.read_chr_rom(tile_addr + 8);
+            self.extra_sprites
+                .push(Sprite::new(attr, sprite_x, low_byte, high_byte));
+            self.mark_sprite_dots(sprite_x);
+        }
+    }
+
+    /// Draws every sprite on a scanline instead of the first 8, to get rid of flicker. Only
+    /// changes the picture, the CPU still sees the limit through the overflow flag and timing
+    pub fn set_remove_sprite_limit(&mut self, remove: bool) {
+        self.remove_sprite_limit = remove;
+    }
+
+    pub const fn remove_sprite_limit(&self) -> bool {
+        self.remove_sprite_limit
+    }
+
+    /// Back to the power on state, warm up included, keeping the palette and display options
+    pub fn power_cycle(&mut self) {
+        let old = std::mem::take(self);
+        self.colors = old.colors;
+        self.remove_sprite_limit = old.remove_sprite_limit;
+        self.layers = old.layers;
+    }
+
+    /// The reset button clears the control registers and the write latch and starts the warm up
+    /// period again. OAM, VRAM, the palette and the timing carry on. See
+    /// <https://www.nesdev.org/wiki/PPU_power_up_state>
+    pub fn reset(&mut self) {
+        self.write_ppuctrl(0);
+        self.write_ppumask(0);
+        self.w = false;
+        self.x_scroll = 0;
+        self.temp_vram_addr = 0;
+        self.memory_read_buffer = 0;
+        self.warming_up = true;
+    }
+
+    /// Whether writes to $2000, $2001, $2005 and $2006 are still being ignored after power on or a
+    /// reset
+    pub const fn warming_up(&self) -> bool {
+        self.warming_up
+    }
+
+    pub fn set_render_layers(&mut self, layers: RenderLayers) {
+        self.layers = layers;
+    }
+
+    pub const fn render_layers(&self) -> RenderLayers {
+        self.layers
     }
 
     fn draw_pixel(&mut self) {
-        if self.is_rendering_enabled() || ((self.vram_addr & 0x3f00) != 0x3f00) {
+        let palette_addr = if self.is_rendering_enabled() || ((self.vram_addr & 0x3f00) != 0x3f00) {
             let pixel_color = self.get_pixel_color();
-            let color = self.colors.system_palette[(self.palette[(if pixel_color & 0x03 > 0 {
+            if pixel_color & 0x03 > 0 {
                 pixel_color
             } else {
                 0
-            }) as usize]) as usize];
-            self.curr_frame
-                .set_pixel((self.cycle - 1) as usize, self.scanline as usize, color);
+            }
         } else {
-            self.curr_frame.set_pixel(
-                (self.cycle - 1) as usize,
-                self.scanline as usize,
-                self.colors.system_palette[self.palette[(self.vram_addr & 0x1f) as usize] as usize],
-            );
-        }
+            (self.vram_addr & 0x1f) as u8
+        };
+        let color = self
+            .mask
+            .apply_greyscale(self.palette[palette_addr as usize]);
+        let emphasis = self.mask.emphasis();
+        self.curr_frame.set_pixel(
+            (self.cycle - 1) as usize,
+            self.scanline as usize,
+            (emphasis << 6) as u16 | color as u16,
+            self.colors.get(color, emphasis),
+        );
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.colors = palette;
+    }
+
+    pub const fn palette(&self) -> &Palette {
+        &self.colors
     }
 
     fn shift_tile_registers(&mut self) {
@@ -538,9 +677,9 @@ impl PPU {
         }
     }
 
-    fn process_scanline(&mut self) {
+    fn process_scanline(&mut self, mapper: &mut dyn Mapper) {
         if self.cycle <= 256 {
-            self.load_tile_info();
+            self.load_tile_info(mapper);
 
             if self.prev_rendering_enabled && (self.cycle & 0x07) == 0 {
                 self.increment_scroll_x();
@@ -567,42 +706,46 @@ impl PPU {
             if self.cycle == 257 {
                 self.sprite_index = 0;
                 self.has_sprite = [false; 257];
+                self.extra_sprites.clear();
                 if self.prev_rendering_enabled {
                     self.vram_addr = (self.vram_addr & !0x041f) | (self.temp_vram_addr & 0x041f);
                 }
             }
             if self.is_rendering_enabled() {
                 self.sprite_ram_addr = 0;
-                if self.cycle.wrapping_sub(261) % 8 == 0 {
-                    self.load_sprite_tile_info();
-                } else if self.cycle.wrapping_sub(257) % 8 == 0 {
+                if self.cycle.wrapping_sub(261).is_multiple_of(8) {
+                    self.load_sprite_tile_info(mapper);
+                } else if self.cycle.wrapping_sub(257).is_multiple_of(8) {
                     // Garbage NT fetch
-                    self.read_vram(self.get_nametable_addr());
-                } else if self.cycle.wrapping_sub(259) % 8 == 0 {
+                    self.read_vram(mapper, self.get_nametable_addr());
+                } else if self.cycle.wrapping_sub(259).is_multiple_of(8) {
                     // Garbage AT fetch
-                    self.read_vram(self.get_attribute_addr());
+                    self.read_vram(mapper, self.get_attribute_addr());
                 }
 
                 if self.scanline == -1 && self.cycle >= 280 && self.cycle <= 304 {
                     self.vram_addr = (self.vram_addr & !0x7be0) | (self.temp_vram_addr & 0x7be0);
                 }
+                if self.cycle == 320 && self.remove_sprite_limit {
+                    self.load_extra_sprites(mapper);
+                }
             }
         } else if self.cycle >= 321 && self.cycle <= 336 {
             if self.cycle == 321 {
                 if self.is_rendering_enabled() {
                     self.oam_copy_buffer = self.secondary_sprite_ram[0];
                 }
-                self.load_tile_info();
+                self.load_tile_info(mapper);
             } else if self.prev_rendering_enabled && (self.cycle == 328 || self.cycle == 336) {
-                self.load_tile_info();
+                self.load_tile_info(mapper);
                 self.low_bit_shift <<= 8;
                 self.high_bit_shift <<= 8;
                 self.increment_scroll_x()
             } else {
-                self.load_tile_info();
+                self.load_tile_info(mapper);
             }
         } else if (self.cycle == 337 || self.cycle == 339) && self.is_rendering_enabled() {
-            self.read_vram(self.get_nametable_addr());
+            self.read_vram(mapper, self.get_nametable_addr());
             if self.scanline == -1 && self.cycle == 339 && (self.frame_count % 2 == 1) {
                 self.cycle = 340;
             }
@@ -712,9 +855,9 @@ impl PPU {
         }
     }
 
-    pub fn read_ppudata(&mut self, open_bus_mask: &mut u8) -> u8 {
+    pub fn read_ppudata(&mut self, open_bus_mask: &mut u8, mapper: &mut dyn Mapper) -> u8 {
         let mut return_value = self.memory_read_buffer;
-        self.memory_read_buffer = self.read_vram(self.ppu_bus_address & 0x3fff);
+        self.memory_read_buffer = self.read_vram(mapper, self.ppu_bus_address & 0x3fff);
 
         if (self.ppu_bus_address & 0x3fff) >= 0x3f00 {
             return_value = self.read_palette_ram(self.ppu_bus_address) | self.open_bus & 0xc0;
@@ -737,9 +880,9 @@ impl PPU {
         self.palette[addr as usize]
     }
 
-    pub fn read_ppudata_trace(&self, addr: usize) -> u8 {
+    pub fn read_ppudata_trace(&self, addr: usize, mapper: &dyn Mapper) -> u8 {
         match addr {
-            0x0000..=0x1fff => self.mapper.lock().unwrap().read_chr_rom(addr as u16),
+            0x0000..=0x1fff => mapper.read_chr_rom(addr as u16),
             0x2000 => self.ctrl.bits(),
             0x2001 => self.mask.bits(),
             0x2002 => self.status,
@@ -748,7 +891,7 @@ impl PPU {
             0x2005 => self.x_scroll,
             0x2006 => self.temp_vram_addr as u8,
             0x2007 => self.memory_read_buffer,
-            0x2008..=0x2fff => self.mapper.lock().unwrap().read_nametable(addr as u16),
+            0x2008..=0x2fff => mapper.read_nametable(addr as u16),
             0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => self.palette[addr - 0x3f10],
             0x3f00..=0x3fff => self.palette[(addr - 0x3f00) % 0x20],
             _ => panic!("Invalid address {:#X}", addr),
@@ -765,38 +908,48 @@ impl PPU {
             let or_1 = ((self.low_bit_shift << (offset as u16)) & 0x8000) >> 15;
             let or_2 = ((self.high_bit_shift << (offset as u16)) & 0x8000) >> 14;
             sprite_bg_color = (or_1 | or_2) as u8;
-            background_color = sprite_bg_color;
+            // Sprite 0 hit still sees a hidden background
+            if self.layers.background {
+                background_color = sprite_bg_color;
+            }
         }
         if self.has_sprite[self.cycle as usize]
             && self.cycle > self.minimum_draw_sprite_cycle as u64
         {
+            // The first opaque sprite wins, even when it's behind the background
+            let mut front = None;
             for i in 0..self.sprite_count {
-                let shift = self.cycle as i32 - self.sprite_tiles[i as usize].sprite_x as i32 - 1;
-                if (0..8).contains(&shift) {
-                    let sprite = self.sprite_tiles[i as usize];
-                    let sprite_color = if self.sprite_tiles[i as usize].flip_horizontal {
-                        ((sprite.low_byte >> shift) & 0x01)
-                            | ((sprite.high_byte >> shift) & 0x01) << 1
-                    } else {
-                        ((sprite.low_byte << shift) & 0x80) >> 7
-                            | ((sprite.high_byte << shift) & 0x80) >> 6
-                    };
-                    if sprite_color != 0 {
-                        if i == 0
-                            && sprite_bg_color != 0
-                            && self.sprite_0_visible
-                            && self.cycle != 256
-                            && self.mask.contains(Mask::SHOW_BACKGROUND)
-                            && !self.status_flags.contains(Status::SPRITE_ZERO_HIT)
-                            && self.cycle > self.minimum_draw_sprite_cycle as u64
-                        {
-                            self.status_flags.set(Status::SPRITE_ZERO_HIT, true);
-                        }
-                        if background_color == 0 || !self.sprite_tiles[i as usize].priority {
-                            return (sprite.palette_offset + sprite_color as u32) as u8;
-                        }
-                        break;
+                let sprite = self.sprite_tiles[i as usize];
+                let sprite_color = sprite.color_at(self.cycle);
+                if sprite_color != 0 {
+                    if i == 0
+                        && sprite_bg_color != 0
+                        && self.sprite_0_visible
+                        && self.cycle != 256
+                        && self.mask.contains(Mask::SHOW_BACKGROUND)
+                        && !self.status_flags.contains(Status::SPRITE_ZERO_HIT)
+                        && self.cycle > self.minimum_draw_sprite_cycle as u64
+                    {
+                        self.status_flags.set(Status::SPRITE_ZERO_HIT, true);
+                    }
+                    // A hidden sprite lets the ones under it show through
+                    if !self.layers.shows_sprite(sprite.priority) {
+                        continue;
                     }
+                    front = Some((sprite, sprite_color));
+                    break;
+                }
+            }
+            if front.is_none() {
+                front = self.extra_sprites.iter().find_map(|sprite| {
+                    let sprite_color = sprite.color_at(self.cycle);
+                    (sprite_color != 0 && self.layers.shows_sprite(sprite.priority))
+                        .then_some((*sprite, sprite_color))
+                });
+            }
+            if let Some((sprite, sprite_color)) = front {
+                if background_color == 0 || !sprite.priority {
+                    return (sprite.palette_offset + sprite_color as u32) as u8;
                 }
             }
         }
@@ -824,10 +977,8 @@ impl PPU {
     pub fn read_ppustatus(&mut self, open_bus_mask: &mut u8) -> u8 {
         self.w = false;
         self.update_status_flag();
-        let mut ret = self.status;
         *open_bus_mask = 0x1f;
-        Self::process_status_reg_open_bus(open_bus_mask, &mut ret);
-        ret
+        self.status
     }
 
     fn update_status_flag(&mut self) {
@@ -839,13 +990,14 @@ impl PPU {
         }
     }
 
-    pub fn write_ppudata(&mut self, data: u8) {
+    pub fn write_ppudata(&mut self, data: u8, mapper: &mut dyn Mapper) {
         if (self.ppu_bus_address & 0x3fff) >= 0x3f00 {
             self.write_palette_ram(self.ppu_bus_address, data);
         } else if self.scanline >= 240 || !self.is_rendering_enabled() {
-            self.write_vram(self.ppu_bus_address & 0x3fff, data);
+            self.write_vram(mapper, self.ppu_bus_address & 0x3fff, data);
         } else {
             self.write_vram(
+                mapper,
                 self.ppu_bus_address & 0x3fff,
                 (self.ppu_bus_address & 0xff) as u8,
             );
@@ -853,11 +1005,11 @@ impl PPU {
         self.update_video_ram_addr();
     }
 
-    fn write_vram(&mut self, addr: u16, val: u8) {
+    fn write_vram(&mut self, mapper: &mut dyn Mapper, addr: u16, val: u8) {
         self.set_bus_address(addr);
         match addr {
-            0x0000..=0x1fff => self.mapper.lock().unwrap().write_chr_rom(addr, val),
-            0x2000..=0x3eff => self.mapper.lock().unwrap().write_nametable(addr, val),
+            0x0000..=0x1fff => mapper.write_chr_rom(addr, val),
+            0x2000..=0x3eff => mapper.write_nametable(addr, val),
             0x3f00..=0x3fff => self.write_palette_ram(addr, val),
             _ => panic!("Invalid address {:#X}", addr),
         }
@@ -916,36 +1068,32 @@ impl PPU {
         self.sprite_dma_transfer = DMAFlag::Enabled(data);
     }
 
-    fn read_vram(&mut self, addr: u16) -> u8 {
+    fn read_vram(&mut self, mapper: &mut dyn Mapper, addr: u16) -> u8 {
         self.set_bus_address(addr);
         match addr {
-            0x0000..=0x1fff => self.mapper.lock().unwrap().read_chr_rom(addr),
-            0x2000..=0x2fff => self.mapper.lock().unwrap().read_nametable(addr),
-            0x3000..=0x3fff => self.mapper.lock().unwrap().read_nametable(addr - 0x1000),
+            0x0000..=0x1fff => mapper.read_chr_rom(addr),
+            0x2000..=0x2fff => mapper.read_nametable(addr),
+            0x3000..=0x3fff => mapper.read_nametable(addr - 0x1000),
             _ => panic!("Invalid address {:#X}", addr),
         }
     }
 
     pub fn set_open_bus(&mut self, mask: u8, val: u8) {
-        let mut val = val;
-        let mut mask = mask;
-        if mask == 0xff {
-            self.open_bus = val;
-        } else {
-            let mut open_bus = (self.open_bus as u16) << 8;
-            for _i in 0..8 {
-                open_bus >>= 1;
-                if mask & 0x01 != 0 {
-                    if val & 0x01 != 0 {
-                        open_bus |= 0x80;
-                    } else {
-                        open_bus &= 0xff7f;
-                    }
-                }
-                val >>= 1;
-                mask >>= 1;
+        self.open_bus = self.open_bus & !mask | val & mask;
+        for (bit, stamp) in self.open_bus_stamps.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *stamp = self.frame_count;
+            }
+        }
+    }
+
+    // Bits that haven't been driven for a while fade to 0. See
+    // <https://www.nesdev.org/wiki/Open_bus_behavior#PPU_open_bus>
+    fn decay_open_bus(&mut self) {
+        for (bit, stamp) in self.open_bus_stamps.iter().enumerate() {
+            if self.frame_count - stamp > OPEN_BUS_DECAY_FRAMES {
+                self.open_bus &= !(1 << bit);
             }
-            self.open_bus = open_bus as u8;
         }
     }
 
@@ -953,9 +1101,95 @@ impl PPU {
         self.set_open_bus(!mask, val);
         val | self.open_bus & mask
     }
+}
 
-    pub fn process_status_reg_open_bus(open_bus_mask: &mut u8, return_val: &mut u8) {
-        *open_bus_mask = 0x00;
-        *return_val |= 0x1b;
+impl Savestate for DMAFlag {
+    fn save(&self, out: &mut Vec<u8>) {
+        match self {
+            DMAFlag::Enabled(page) => Some(*page),
+            DMAFlag::Disabled => None,
+        }
+        .save(out);
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> std::io::Result<()> {
+        let mut page = None;
+        page.load(input)?;
+        *self = page.map_or(DMAFlag::Disabled, DMAFlag::Enabled);
+        Ok(())
     }
 }
+
+savestate!(Tile {
+    palette_offset,
+    tile_addr,
+    low,
+    high,
+    offset_y,
+});
+
+savestate!(Sprite {
+    palette_offset,
+    priority,
+    flip_horizontal,
+    sprite_x,
+    low_byte,
+    high_byte,
+});
+
+// The palette colours and display options are settings and stay as they are. Extra sprites are
+// refilled every line
+savestate!(PPU {
+    ctrl,
+    status_flags,
+    status,
+    mask,
+    sprite_ram_addr,
+    sprite_ram,
+    sprite_tiles,
+    warming_up,
+    cycle,
+    scanline,
+    palette,
+    curr_frame,
+    nmi_generated,
+    minimum_draw_bg_cycle,
+    minimum_draw_sprite_cycle,
+    high_bit_shift,
+    low_bit_shift,
+    has_sprite,
+    sprite_count,
+    previous_tile,
+    current_tile,
+    next_tile,
+    vram_addr,
+    temp_vram_addr,
+    x_scroll,
+    w,
+    sprite_index,
+    oam_copy_buffer,
+    secondary_sprite_ram,
+    sprite_0_added,
+    sprite_in_range,
+    secondary_oam_addr,
+    overflow_bug_counter,
+    oam_copy_done,
+    sprite_addr_h,
+    sprite_addr_l,
+    first_visible_sprite_addr,
+    last_visible_sprite_addr,
+    sprite_0_visible,
+    frame_count,
+    prev_rendering_enabled,
+    rendering_enabled,
+    need_state_update,
+    prevent_vbl_flag,
+    memory_read_buffer,
+    ppu_bus_address,
+    update_vram_addr_delay,
+    update_vram_addr,
+    master_clock,
+    open_bus,
+    open_bus_stamps,
+    sprite_dma_transfer,
+});
@@ -1,35 +1,143 @@
-use std::{fs::File, io::Read};
+pub mod ntsc;
+pub mod rgb;
+
+use std::io;
+use std::path::{Path, PathBuf};
 
-use itertools::Itertools;
 use sdl2::pixels::Color;
 
+use crate::config::Config;
+
+use self::ntsc::NtscPaletteParams;
+
+static DEFAULT_PALETTE: &[u8] = include_bytes!("ntscpalette.pal");
+
+// Each colour emphasis bit darkens the other two components. Roughly what the 2C02 does, see
+// https://www.nesdev.org/wiki/NTSC_video#Color_Tint_Bits
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+
+#[derive(Clone)]
 pub struct Palette {
-    pub system_palette: [Color; 0x40],
+    // 8 banks of 64 colours, one for each combination of the PPUMASK emphasis bits
+    pub system_palette: [Color; 0x200],
 }
 
 impl Default for Palette {
     fn default() -> Self {
-        Self::from_file("src/core/ppu/palettes/ntscpalette.pal")
+        Self::from_bytes(DEFAULT_PALETTE).unwrap()
     }
 }
 
 impl Palette {
-    pub fn from_file(path: &str) -> Palette {
-        let file = File::open(path).unwrap();
-        let reader = file.bytes();
-        Palette {
-            system_palette: reader
-                .chunks(3)
-                .into_iter()
-                .map(|mut chunk| {
-                    let r = chunk.next().unwrap().unwrap();
-                    let g = chunk.next().unwrap().unwrap();
-                    let b = chunk.next().unwrap().unwrap();
-                    Color { r, g, b, a: 0xff }
-                })
-                .collect::<Vec<Color>>()
-                .try_into()
-                .unwrap(),
+    /// Loads a 64 colour (192 byte) or 512 colour (1536 byte) .pal file. Emphasis banks are
+    /// derived from the base colours when the file doesn't have them
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Palette> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Palette> {
+        let colors = bytes
+            .chunks_exact(3)
+            .map(|chunk| Color {
+                r: chunk[0],
+                g: chunk[1],
+                b: chunk[2],
+                a: 0xff,
+            })
+            .collect::<Vec<Color>>();
+
+        match bytes.len() {
+            0xc0 => Ok(Palette::with_emphasis(colors.try_into().unwrap())),
+            0x600 => Ok(Palette {
+                system_palette: colors.try_into().unwrap(),
+            }),
+            len => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("palette is {len} bytes, expected 192 or 1536"),
+            )),
+        }
+    }
+
+    pub fn with_emphasis(base: [Color; 0x40]) -> Palette {
+        let mut system_palette = [Color::BLACK; 0x200];
+        for (emphasis, bank) in system_palette.chunks_mut(0x40).enumerate() {
+            let red = emphasis & 0x1 != 0;
+            let green = emphasis & 0x2 != 0;
+            let blue = emphasis & 0x4 != 0;
+            for (color, base) in bank.iter_mut().zip(base.iter()) {
+                let attenuate = |val: u8, emphasized: bool| {
+                    let mut val = val as f32;
+                    // A component is dimmed once for every emphasis bit that isn't its own
+                    for other in [red, green, blue] {
+                        if other {
+                            val *= EMPHASIS_ATTENUATION;
+                        }
+                    }
+                    if emphasized {
+                        val /= EMPHASIS_ATTENUATION;
+                    }
+                    val.round() as u8
+                };
+                *color = Color {
+                    r: attenuate(base.r, red),
+                    g: attenuate(base.g, green),
+                    b: attenuate(base.b, blue),
+                    a: 0xff,
+                };
+            }
+        }
+        Palette { system_palette }
+    }
+
+    pub fn get(&self, color: u8, emphasis: usize) -> Color {
+        self.system_palette[(emphasis << 6) | color as usize]
+    }
+
+    // Takes a `Frame::indices` entry
+    pub fn get_indexed(&self, index: u16) -> Color {
+        self.system_palette[index as usize & 0x1ff]
+    }
+}
+
+/// Where the colours come from, picked through config.toml or the palette window
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum PaletteSource {
+    // The bundled 2C02 palette
+    #[default]
+    Default,
+    Ntsc(NtscPaletteParams),
+    // RGB PPU used by the PlayChoice-10 and Vs. System boards. The 2C05 uses the same colours
+    Rp2c03,
+    // Vs. System RGB PPUs with the 2C03's colours in another order, 1 to 4 for RP2C04-0001 to -0004
+    Rp2c04(usize),
+    File(PathBuf),
+}
+
+impl PaletteSource {
+    /// `palette_path` wins over `palette`, which is one of "default", "ntsc", "2c03" or
+    /// "2c04-0001" to "2c04-0004"
+    pub fn from_config() -> Self {
+        if let Some(path) = Config::get_string("palette_path") {
+            return PaletteSource::File(PathBuf::from(path));
+        }
+        match Config::get_string("palette").as_deref() {
+            Some("ntsc") => PaletteSource::Ntsc(NtscPaletteParams::default()),
+            Some("2c03" | "2c05") => PaletteSource::Rp2c03,
+            Some("2c04-0001") => PaletteSource::Rp2c04(1),
+            Some("2c04-0002") => PaletteSource::Rp2c04(2),
+            Some("2c04-0003") => PaletteSource::Rp2c04(3),
+            Some("2c04-0004") => PaletteSource::Rp2c04(4),
+            _ => PaletteSource::Default,
+        }
+    }
+
+    pub fn load(&self) -> io::Result<Palette> {
+        match self {
+            PaletteSource::Default => Ok(Palette::default()),
+            PaletteSource::Ntsc(params) => Ok(params.generate()),
+            PaletteSource::Rp2c03 => Ok(rgb::rp2c03()),
+            PaletteSource::Rp2c04(version) => Ok(rgb::rp2c04(*version)),
+            PaletteSource::File(path) => Palette::from_file(path),
         }
     }
 }
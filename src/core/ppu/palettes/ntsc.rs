@@ -0,0 +1,119 @@
+use std::f32::consts::PI;
+
+use sdl2::pixels::Color;
+
+use super::Palette;
+
+// Signal voltages for each luma level, low then high half of the colour wave.
+// https://www.nesdev.org/wiki/NTSC_video#Brightness_Levels
+const LEVELS: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+// Emphasis pulls the signal down during the affected part of the colour wave
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+/// Knobs for the generated palette. The defaults decode the raw 2C02 signal with no adjustment
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NtscPaletteParams {
+    // Degrees
+    pub hue: f32,
+    pub saturation: f32,
+    pub contrast: f32,
+    pub brightness: f32,
+    // Display gamma to correct for. 1.8 is what the NTSC standard assumes and leaves colours as is
+    pub gamma: f32,
+}
+
+impl Default for NtscPaletteParams {
+    fn default() -> Self {
+        Self {
+            hue: 0.,
+            saturation: 1.,
+            contrast: 1.,
+            brightness: 0.,
+            gamma: 1.8,
+        }
+    }
+}
+
+// The colour wave is 12 half-dot phases long and colour n is high for 6 of them starting at n
+const fn in_color_phase(color: usize, phase: usize) -> bool {
+    (color + phase) % 12 < 6
+}
+
+/// Composite level of palette index `index` (emphasis in bits 6-8) at one of the 12 colour wave
+/// phases, scaled so black is 0 and white is 1
+pub(crate) fn signal_level(index: usize, phase: usize) -> f32 {
+    let color = index & 0x0f;
+    let emphasis = (index >> 6) & 0x07;
+    // $xE and $xF are black whatever the luma bits say
+    let level = if color > 13 { 1 } else { (index >> 4) & 0x03 };
+    let low = LEVELS[level + if color == 0 { 4 } else { 0 }];
+    let high = LEVELS[level + if color < 13 { 4 } else { 0 }];
+
+    let mut signal = if in_color_phase(color, phase) {
+        high
+    } else {
+        low
+    };
+    let emphasized = (emphasis & 0x1 != 0 && in_color_phase(0, phase))
+        || (emphasis & 0x2 != 0 && in_color_phase(4, phase))
+        || (emphasis & 0x4 != 0 && in_color_phase(8, phase));
+    if color < 14 && emphasized {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+    (signal - BLACK) / (WHITE - BLACK)
+}
+
+// Angle of the colour subcarrier at a phase, lined up so that $x6 decodes as red
+pub(crate) fn phase_angle(phase: usize) -> f32 {
+    PI * (phase as f32 + 4.) / 6.
+}
+
+// FCC YIQ to RGB
+pub(crate) fn yiq_to_rgb(y: f32, i: f32, q: f32) -> (f32, f32, f32) {
+    (
+        y + 0.946882 * i + 0.623557 * q,
+        y - 0.274788 * i - 0.635691 * q,
+        y - 1.108545 * i + 1.709007 * q,
+    )
+}
+
+impl NtscPaletteParams {
+    /// Builds all 512 colours by generating the composite signal for each palette entry and
+    /// decoding it to RGB, see <https://www.nesdev.org/wiki/NTSC_video>
+    pub fn generate(&self) -> Palette {
+        let mut system_palette = [Color::BLACK; 0x200];
+        for (index, color) in system_palette.iter_mut().enumerate() {
+            *color = self.decode(index);
+        }
+        Palette { system_palette }
+    }
+
+    fn decode(&self, index: usize) -> Color {
+        let (mut y, mut i, mut q) = (0., 0., 0.);
+        for phase in 0..12 {
+            let signal = signal_level(index, phase);
+            let angle = phase_angle(phase) + self.hue.to_radians();
+            y += signal;
+            i += signal * angle.cos();
+            q += signal * angle.sin();
+        }
+        let y = (y / 12.) * self.contrast + self.brightness;
+        let i = (i / 12.) * self.saturation * self.contrast;
+        let q = (q / 12.) * self.saturation * self.contrast;
+
+        let (r, g, b) = yiq_to_rgb(y, i, q);
+        Color {
+            r: self.gamma_correct(r),
+            g: self.gamma_correct(g),
+            b: self.gamma_correct(b),
+            a: 0xff,
+        }
+    }
+
+    fn gamma_correct(&self, val: f32) -> u8 {
+        let val = val.clamp(0., 1.).powf(self.gamma / 1.8);
+        (val * 255.).round() as u8
+    }
+}
@@ -1,5 +1,7 @@
 use bitflags::bitflags;
 
+use crate::core::savestate::savestate_bits;
+
 bitflags! {
     pub struct Control : u8 {
         const NAMETABLE_1 =             0b0000_0001;
@@ -24,3 +26,5 @@ impl Control {
         Control::empty()
     }
 }
+
+savestate_bits!(Control);
@@ -1,5 +1,7 @@
 use bitflags::bitflags;
 
+use crate::core::savestate::savestate_bits;
+
 bitflags! {
     pub struct Mask : u8 {
         const GREYSCALE = 1 << 0;
@@ -23,4 +25,20 @@ impl Mask {
     pub fn new() -> Mask {
         Mask::default()
     }
+
+    // Palette entries are 6 bits, greyscale forces them to the grey column
+    pub fn apply_greyscale(&self, color: u8) -> u8 {
+        if self.contains(Mask::GREYSCALE) {
+            color & 0x30
+        } else {
+            color & 0x3f
+        }
+    }
+
+    // Emphasis bits as an index into the 8 banks of a 512 entry palette
+    pub fn emphasis(&self) -> usize {
+        (self.bits() >> 5) as usize
+    }
 }
+
+savestate_bits!(Mask);
@@ -1,5 +1,7 @@
 use bitflags::bitflags;
 
+use crate::core::savestate::savestate_bits;
+
 bitflags! {
     pub struct Status  : u8 {
         const VBLANK = 1 << 7;
@@ -24,3 +26,5 @@ impl Status {
         Status::empty()
     }
 }
+
+savestate_bits!(Status);
@@ -0,0 +1,170 @@
+use std::io::{self, ErrorKind};
+
+/// Something whose state can be written out and read back in place. Only what the hardware would
+/// hold goes in, settings like the palette, mixer or filters stay as they are when a state is
+/// loaded
+pub trait Savestate {
+    fn save(&self, out: &mut Vec<u8>);
+
+    fn load(&mut self, input: &mut &[u8]) -> io::Result<()>;
+}
+
+/// Implements `Savestate` for a struct by saving the listed fields in order. Anything left out
+/// keeps its current value on load
+macro_rules! savestate {
+    ($ty:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::core::savestate::Savestate for $ty {
+            fn save(&self, out: &mut Vec<u8>) {
+                $( $crate::core::savestate::Savestate::save(&self.$field, out); )*
+            }
+
+            fn load(&mut self, input: &mut &[u8]) -> std::io::Result<()> {
+                $( $crate::core::savestate::Savestate::load(&mut self.$field, input)?; )*
+                Ok(())
+            }
+        }
+    };
+}
+
+/// Implements `Savestate` for `bitflags!` types through their bits
+macro_rules! savestate_bits {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl $crate::core::savestate::Savestate for $ty {
+                fn save(&self, out: &mut Vec<u8>) {
+                    $crate::core::savestate::Savestate::save(&self.bits(), out);
+                }
+
+                fn load(&mut self, input: &mut &[u8]) -> std::io::Result<()> {
+                    let mut bits = self.bits();
+                    $crate::core::savestate::Savestate::load(&mut bits, input)?;
+                    *self = <$ty>::from_bits_truncate(bits);
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+pub(crate) use {savestate, savestate_bits};
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "save state is truncated",
+        ));
+    }
+    let (head, tail) = input.split_at(len);
+    *input = tail;
+    Ok(head)
+}
+
+macro_rules! savestate_int {
+    ($($ty:ty),*) => {
+        $(
+            impl Savestate for $ty {
+                fn save(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn load(&mut self, input: &mut &[u8]) -> io::Result<()> {
+                    let bytes = take(input, std::mem::size_of::<$ty>())?;
+                    *self = <$ty>::from_le_bytes(bytes.try_into().unwrap());
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+savestate_int!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+// Saved as 64 bits so states don't depend on the platform
+impl Savestate for usize {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u64).save(out);
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> io::Result<()> {
+        let mut val = 0u64;
+        val.load(input)?;
+        *self = usize::try_from(val).map_err(|_| invalid_data("value out of range"))?;
+        Ok(())
+    }
+}
+
+impl Savestate for bool {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.push(u8::from(*self));
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> io::Result<()> {
+        *self = take(input, 1)?[0] != 0;
+        Ok(())
+    }
+}
+
+impl<T: Savestate, const N: usize> Savestate for [T; N] {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.iter().for_each(|val| val.save(out));
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> io::Result<()> {
+        self.iter_mut().try_for_each(|val| val.load(input))
+    }
+}
+
+impl<T: Savestate + ?Sized> Savestate for Box<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        (**self).save(out);
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> io::Result<()> {
+        (**self).load(input)
+    }
+}
+
+impl<T: Savestate + Default> Savestate for Option<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.is_some().save(out);
+        if let Some(val) = self {
+            val.save(out);
+        }
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> io::Result<()> {
+        let mut is_some = false;
+        is_some.load(input)?;
+        *self = if is_some {
+            let mut val = T::default();
+            val.load(input)?;
+            Some(val)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+// Cartridge RAM. The size comes from the ROM, so a state with a different size is for another game
+impl Savestate for Vec<u8> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        out.extend_from_slice(self);
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> io::Result<()> {
+        let mut len = 0usize;
+        len.load(input)?;
+        if len != self.len() {
+            return Err(invalid_data("save state is for a different cartridge"));
+        }
+        self.copy_from_slice(take(input, len)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use std::io;
+
+use crate::core::bus::{Bus, PowerOnRam};
+use crate::core::cpu::CPU;
+use crate::core::expansion::ExpansionDeviceFactory;
+use crate::core::frame::Frame;
+use crate::core::joypad::Buttons;
+use crate::core::mappers::MapperFactory;
+use crate::core::savestate::{invalid_data, Savestate};
+use crate::ines_parser::NESFile;
+
+const STATE_MAGIC: &[u8; 4] = b"NESS";
+const STATE_VERSION: u8 = 3;
+
+/// The console on its own, for embedding. Runs a frame at a time on the caller's thread and
+/// never touches config.toml, the audio device or the window. Settings the facade doesn't cover,
+/// like the palette or mixer, are set on the hardware through `cpu_mut`
+pub struct Emulator {
+    cpu: CPU,
+    rom: NESFile,
+    // Samples from frames that haven't been drained yet
+    audio: Vec<i16>,
+}
+
+impl Emulator {
+    /// Powers on with `rom` inserted
+    #[must_use]
+    pub fn new(rom: NESFile) -> Self {
+        let expansion = ExpansionDeviceFactory::from_header(&rom);
+        let mut cpu = CPU::new(Bus::with_expansion(&rom, expansion));
+        cpu.reset();
+        Self {
+            cpu,
+            rom,
+            audio: Vec::new(),
+        }
+    }
+
+    /// Swaps the cartridge and powers on again. Settings on the hardware are kept
+    pub fn load_rom(&mut self, rom: NESFile) {
+        let bus = &mut self.cpu.bus;
+        bus.mapper = MapperFactory::from_file(&rom);
+        bus.expansion = ExpansionDeviceFactory::from_header(&rom);
+        self.rom = rom;
+        self.power_cycle(PowerOnRam::default());
+    }
+
+    #[must_use]
+    pub const fn rom_hash(&self) -> u64 {
+        self.rom.hash
+    }
+
+    /// Sets the buttons held on controller `port`, 0 or 1
+    pub fn set_input(&mut self, port: usize, buttons: Buttons) {
+        self.cpu.bus.joypads[port].buttons = buttons;
+    }
+
+    #[must_use]
+    pub fn input(&self, port: usize) -> Buttons {
+        self.cpu.bus.joypads[port].buttons
+    }
+
+    /// Runs until the PPU finishes the next frame
+    pub fn step_frame(&mut self) {
+        self.cpu.run_frame(u64::MAX);
+
+        let apu = &mut self.cpu.bus.apu;
+        apu.end_frame(&mut self.audio);
+        // Keep a little over a second when nobody's draining, dropping the oldest samples
+        let limit = 64 * 1024 * apu.output_channels();
+        if self.audio.len() > limit {
+            self.audio.drain(..self.audio.len() - limit);
+        }
+    }
+
+    /// The last finished frame
+    #[must_use]
+    pub fn frame(&self) -> &Frame {
+        &self.cpu.bus.ppu.curr_frame
+    }
+
+    /// Moves the samples generated since the last call onto the end of `out`. Interleaved
+    /// left/right when the mixer is in stereo
+    pub fn drain_audio(&mut self, out: &mut Vec<i16>) {
+        out.append(&mut self.audio);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.cpu.bus.apu.set_sample_rate(sample_rate);
+    }
+
+    #[must_use]
+    pub fn audio_channels(&self) -> usize {
+        self.cpu.bus.apu.output_channels()
+    }
+
+    /// Where the CPU crashed on a JAM opcode, if it has. Only a reset or power cycle gets it going
+    /// again
+    #[must_use]
+    pub const fn jammed(&self) -> Option<u16> {
+        self.cpu.jammed()
+    }
+
+    /// Presses the reset button
+    pub fn reset(&mut self) {
+        self.cpu.soft_reset();
+    }
+
+    /// Switches the console off and on again. Battery saves survive
+    pub fn power_cycle(&mut self, ram: PowerOnRam) {
+        self.cpu.bus.power_cycle(&self.rom, ram);
+        self.cpu.reset();
+        self.audio.clear();
+    }
+
+    /// Battery backed cartridge RAM, empty when the cartridge has none
+    #[must_use]
+    pub fn battery_save(&self) -> Vec<u8> {
+        self.cpu.bus.mapper.dump_save()
+    }
+
+    pub fn load_battery_save(&mut self, save: &[u8]) -> io::Result<()> {
+        self.cpu.bus.mapper.load_save(save)
+    }
+
+    /// Number of disk sides that can be inserted, zero unless this is a Disk System game
+    #[must_use]
+    pub fn disk_sides(&self) -> usize {
+        self.cpu.bus.mapper.disk_sides()
+    }
+
+    /// The disk side in the drive, if there is one
+    #[must_use]
+    pub fn disk_side(&self) -> Option<usize> {
+        self.cpu.bus.mapper.disk_side()
+    }
+
+    /// Ejects the disk, then inserts `side` a moment later if it's given
+    pub fn insert_disk(&mut self, side: Option<usize>) {
+        self.cpu.bus.mapper.insert_disk(side);
+    }
+
+    /// Snapshot of the whole console, for `load_state`
+    #[must_use]
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = STATE_MAGIC.to_vec();
+        STATE_VERSION.save(&mut out);
+        self.rom.hash.save(&mut out);
+        self.cpu.save(&mut out);
+        out
+    }
+
+    /// Restores a snapshot from `save_state`. Fails without changing anything if it was taken with
+    /// another ROM or is damaged
+    pub fn load_state(&mut self, state: &[u8]) -> io::Result<()> {
+        let mut input = state
+            .strip_prefix(STATE_MAGIC)
+            .ok_or_else(|| invalid_data("not a save state"))?;
+        let (mut version, mut hash) = (0u8, 0u64);
+        version.load(&mut input)?;
+        hash.load(&mut input)?;
+        if version != STATE_VERSION {
+            return Err(invalid_data("save state is from another version"));
+        }
+        if hash != self.rom.hash {
+            return Err(invalid_data("save state is for another ROM"));
+        }
+
+        let mut backup = Vec::new();
+        self.cpu.save(&mut backup);
+        let result = self.cpu.load(&mut input).and_then(|()| {
+            if input.is_empty() {
+                Ok(())
+            } else {
+                Err(invalid_data("save state has trailing data"))
+            }
+        });
+        if result.is_err() {
+            self.cpu.load(&mut backup.as_slice())?;
+        }
+        self.audio.clear();
+        result
+    }
+
+    #[must_use]
+    pub const fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+}
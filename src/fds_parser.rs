@@ -0,0 +1,67 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::Path,
+};
+
+use crate::core::savestate::invalid_data;
+
+const FWNES_MAGIC: &[u8; 4] = b"FDS\x1a";
+const FWNES_HEADER_SIZE: usize = 16;
+// Every side starts with the disk info block: its type, then "*NINTENDO-HVC*"
+const DISK_INFO_MAGIC: &[u8; 15] = b"\x01*NINTENDO-HVC*";
+
+/// Bytes in one side of a .fds image. The gaps and CRCs on the real disk aren't stored
+pub const SIDE_SIZE: usize = 65500;
+
+/// A Famicom Disk System image: the sides of one or more disks, in order. Either a bare .fds dump
+/// or one with the 16 byte fwNES header in front
+#[derive(Clone, Debug)]
+pub struct FdsImage {
+    pub sides: Vec<Vec<u8>>,
+    pub hash: u64,
+}
+
+impl FdsImage {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        // The header's side count is often wrong, so the size is trusted instead
+        let data = bytes
+            .strip_prefix(FWNES_MAGIC)
+            .map_or(bytes, |_| &bytes[FWNES_HEADER_SIZE.min(bytes.len())..]);
+        if !data.starts_with(DISK_INFO_MAGIC) {
+            return Err(invalid_data("not an FDS disk image"));
+        }
+
+        // Some dumps cut the unused end of the last side off
+        let sides = data
+            .chunks(SIDE_SIZE)
+            .filter(|side| side.starts_with(DISK_INFO_MAGIC))
+            .map(|side| {
+                let mut side = side.to_vec();
+                side.resize(SIDE_SIZE, 0);
+                side
+            })
+            .collect();
+
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        Ok(FdsImage {
+            sides,
+            hash: hasher.finish(),
+        })
+    }
+
+    /// What the label calls zero based `side`: A and B of disk 1, then disk 2 and so on
+    #[must_use]
+    pub fn side_name(side: usize) -> String {
+        format!(
+            "Disk {} side {}",
+            side / 2 + 1,
+            if side.is_multiple_of(2) { 'A' } else { 'B' }
+        )
+    }
+}
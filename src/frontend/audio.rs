@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    StreamConfig, StreamError,
+};
+use crossbeam::queue::ArrayQueue;
+
+use crate::config::Config;
+
+const DEFAULT_LATENCY_MS: i64 = 50;
+// Anything lower leaves the rate control nothing to work with. Also keeps zero and negative
+// values in the config from sizing an empty buffer
+const MIN_LATENCY_MS: i64 = 5;
+
+// Maximum deviation from the real sample rate the rate control is allowed to apply. 0.5% is small
+// enough that the pitch change can't be heard
+const MAX_RATE_DELTA: f64 = 0.005;
+
+/// Samples produced by the emulator are pushed into a ring buffer of left/right frames that the
+/// cpal callback drains. The amount of buffered audio is kept around a target latency by nudging
+/// the rate the APU resamples at, see
+/// <https://docs.libretro.com/development/cores/dynamic-rate-control/>
+pub struct AudioOutput {
+    stream: cpal::Stream,
+    buffer: Arc<ArrayQueue<[i16; 2]>>,
+    sample_rate: f64,
+    target_fill: usize,
+}
+
+impl AudioOutput {
+    pub fn new() -> Self {
+        let host = cpal::default_host();
+        let device = host.default_output_device().unwrap();
+
+        let default_config: StreamConfig = device.default_output_config().unwrap().into();
+        let sample_rate = default_config.sample_rate.0 as f64;
+        let channels = default_config.channels as usize;
+
+        let latency_ms =
+            Config::get_int("audio_latency_ms", DEFAULT_LATENCY_MS).max(MIN_LATENCY_MS);
+        let target_fill = (sample_rate * latency_ms as f64 / 1000.) as usize;
+        let buffer = Arc::new(ArrayQueue::new(target_fill * 4));
+
+        let callback_buffer = buffer.clone();
+        let mut last_frame = [0; 2];
+        let stream = device
+            .build_output_stream(
+                &default_config,
+                move |buf: &mut [i16], _: &cpal::OutputCallbackInfo| {
+                    Self::stream_callback(buf, &callback_buffer, channels, &mut last_frame);
+                },
+                |e: StreamError| {
+                    dbg!(e);
+                },
+                None,
+            )
+            .unwrap();
+        stream.play().unwrap();
+
+        AudioOutput {
+            stream,
+            buffer,
+            sample_rate,
+            target_fill,
+        }
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Rate the APU should resample to so the buffer drifts back towards the target latency
+    pub fn adjusted_sample_rate(&self) -> f64 {
+        let fill_error =
+            (self.target_fill as f64 - self.buffer.len() as f64) / self.target_fill as f64;
+        self.sample_rate * (1. + MAX_RATE_DELTA * fill_error.clamp(-1., 1.))
+    }
+
+    /// `samples` is mono, or interleaved left/right when `channels` is 2
+    pub fn push_samples(&self, samples: &[i16], channels: usize) {
+        for frame in samples.chunks_exact(channels) {
+            let frame = if channels == 1 {
+                [frame[0]; 2]
+            } else {
+                [frame[0], frame[1]]
+            };
+            // Only happens if the host stalls for a long time. Drop the oldest audio rather than
+            // letting latency build up
+            self.buffer.force_push(frame);
+        }
+    }
+
+    pub fn pause(&self) {
+        self.stream.pause().ok();
+    }
+
+    pub fn play(&self) {
+        self.stream.play().ok();
+    }
+
+    // On underrun, hold the last frame instead of leaving whatever was in the device buffer,
+    // which avoids both pops and repeated garbage. Mono devices get the average of both sides and
+    // any channels past the first two are left silent
+    fn stream_callback(
+        buf: &mut [i16],
+        buffer: &ArrayQueue<[i16; 2]>,
+        channels: usize,
+        last_frame: &mut [i16; 2],
+    ) {
+        for frame in buf.chunks_mut(channels) {
+            if let Some(next) = buffer.pop() {
+                *last_frame = next;
+            }
+            let [left, right] = *last_frame;
+            match frame {
+                [mono] => *mono = ((i32::from(left) + i32::from(right)) / 2) as i16,
+                [l, r, rest @ ..] => {
+                    *l = left;
+                    *r = right;
+                    rest.fill(0);
+                }
+                [] => {}
+            }
+        }
+    }
+}
+
+impl Default for AudioOutput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
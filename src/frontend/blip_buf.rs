@@ -3,14 +3,34 @@
     blip_buf 1.1.0. http://www.slack.net/~ant/ by Shay Green.
 */
 
+use crate::core::savestate::{invalid_data, Savestate};
+
+// Per output buffer state. Timing is shared between the buffers
+struct BlipChannel {
+    integrator: i32,
+    buf: Box<[i32]>,
+    last_sample: i32,
+}
+
+impl BlipChannel {
+    fn new(size: usize) -> Self {
+        Self {
+            integrator: 0,
+            buf: vec![0; size].into_boxed_slice(),
+            last_sample: 0,
+        }
+    }
+}
+
+/// Band-limited resampler. In stereo, a second buffer holds the right channel and samples come out
+/// interleaved left/right
 pub struct BlipBuf<const S: usize> {
     factor: u64,
     offset: u64,
     available: u64,
-    integrator: i32,
 
-    buf: [i32; S],
-    last_sample: i32,
+    channels: [BlipChannel; 2],
+    stereo: bool,
     time: u64,
 }
 
@@ -38,13 +58,33 @@ impl<const S: usize> BlipBuf<S> {
             factor: f64::ceil(factor) as u64,
             offset: (Self::TIME_UNIT / Self::BLIP_MAX_RATIO) / 2,
             available: 0,
-            integrator: 0,
-            buf: [0; S],
-            last_sample: 0,
+            channels: [BlipChannel::new(S), BlipChannel::new(S)],
+            stereo: false,
             time: 0,
         }
     }
 
+    pub fn stereo(&self) -> bool {
+        self.stereo
+    }
+
+    pub fn set_stereo(&mut self, stereo: bool) {
+        if stereo && !self.stereo {
+            // Start the right channel where the left one is so switching over doesn't pop
+            let [left, right] = &mut self.channels;
+            right.buf.copy_from_slice(&left.buf);
+            right.integrator = left.integrator;
+            right.last_sample = left.last_sample;
+        }
+        self.stereo = stereo;
+    }
+
+    // How much of the buffers has deltas in it
+    fn pending_len(&self) -> usize {
+        let end = (self.time * self.factor + self.offset) >> Self::TIME_BITS;
+        ((self.available + end + Self::BUF_EXTRA) as usize).min(S)
+    }
+
     pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
         let factor = Self::TIME_UNIT as f64 * sample_rate / clock_rate;
         self.factor = f64::ceil(factor) as u64;
@@ -87,17 +127,29 @@ impl<const S: usize> BlipBuf<S> {
     ];
 
     pub fn add_sample(&mut self, s: i32) {
-        let delta = s - self.last_sample;
-        self.last_sample = s;
+        self.add_channel_sample(0, s);
+        self.time += 1;
+    }
 
-        if delta != 0 {
-            self.add_delta(delta);
+    /// Only the left sample is kept when not in stereo
+    pub fn add_stereo_sample(&mut self, left: i32, right: i32) {
+        self.add_channel_sample(0, left);
+        if self.stereo {
+            self.add_channel_sample(1, right);
         }
-
         self.time += 1;
     }
 
-    fn add_delta(&mut self, mut delta: i32) {
+    fn add_channel_sample(&mut self, channel: usize, s: i32) {
+        let delta = s - self.channels[channel].last_sample;
+        self.channels[channel].last_sample = s;
+
+        if delta != 0 {
+            self.add_delta(channel, delta);
+        }
+    }
+
+    fn add_delta(&mut self, channel: usize, mut delta: i32) {
         // TODO: fix hack
         let fixed: u32 =
             (((self.time).wrapping_mul(self.factor) + self.offset) >> Self::PRE_SHIFT) as u32;
@@ -118,12 +170,13 @@ impl<const S: usize> BlipBuf<S> {
         // TODO: fix hack
         buf_index %= S - 15;
 
+        let buf = &mut self.channels[channel].buf;
         for i in 0..8 {
-            self.buf[buf_index + i] += s_in[i] as i32 * delta + in_half_width[i] as i32 * delta2;
+            buf[buf_index + i] += s_in[i] as i32 * delta + in_half_width[i] as i32 * delta2;
         }
 
         for (i, j) in (8..16).zip((0..8).rev()) {
-            self.buf[buf_index + i] += rev[j] as i32 * delta + rev_half_width[j] as i32 * delta2;
+            buf[buf_index + i] += rev[j] as i32 * delta + rev_half_width[j] as i32 * delta2;
         }
     }
 
@@ -141,30 +194,72 @@ impl<const S: usize> BlipBuf<S> {
         let count = self.available as usize;
 
         if count > 0 {
-            let mut sum = self.integrator;
+            let used = if self.stereo { 2 } else { 1 };
+            let start = out.len();
+            out.resize(start + count * used, 0);
 
-            for i in 0..count {
-                let s = sum >> Self::DELTA_BITS;
-                sum += self.buf[i];
+            for (c, channel) in self.channels[..used].iter_mut().enumerate() {
+                let mut sum = channel.integrator;
 
-                let s_clamped = if (s as i16 as i32) != s {
-                    (s >> 16) ^ Self::MAX_SAMPLE
-                } else {
-                    s
-                };
+                for i in 0..count {
+                    let s = sum >> Self::DELTA_BITS;
+                    sum += channel.buf[i];
 
-                out.push(s_clamped as i16);
+                    let s_clamped = if (s as i16 as i32) != s {
+                        (s >> 16) ^ Self::MAX_SAMPLE
+                    } else {
+                        s
+                    };
 
-                sum -= s << (Self::DELTA_BITS - Self::BASS_SHIFT);
-            }
+                    out[start + i * used + c] = s_clamped as i16;
 
-            self.integrator = sum;
+                    sum -= s << (Self::DELTA_BITS - Self::BASS_SHIFT);
+                }
 
-            let remain = (self.available + Self::BUF_EXTRA - count as u64) as usize;
-            self.buf.copy_within(count..(count + remain), 0);
-            self.buf[remain..(remain + count)].fill(0);
+                channel.integrator = sum;
+
+                let remain = (self.available + Self::BUF_EXTRA - count as u64) as usize;
+                channel.buf.copy_within(count..(count + remain), 0);
+                channel.buf[remain..(remain + count)].fill(0);
+            }
 
             self.available = 0;
         }
     }
 }
+
+// The rates and stereo setting come from the host and are left alone
+impl<const S: usize> Savestate for BlipBuf<S> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.offset.save(out);
+        self.available.save(out);
+        self.time.save(out);
+        let len = self.pending_len();
+        len.save(out);
+        for channel in &self.channels {
+            channel.integrator.save(out);
+            channel.last_sample.save(out);
+            channel.buf[..len].iter().for_each(|delta| delta.save(out));
+        }
+    }
+
+    fn load(&mut self, input: &mut &[u8]) -> std::io::Result<()> {
+        self.offset.load(input)?;
+        self.available.load(input)?;
+        self.time.load(input)?;
+        let mut len = 0usize;
+        len.load(input)?;
+        if len > S {
+            return Err(invalid_data("audio buffer is too long"));
+        }
+        for channel in &mut self.channels {
+            channel.integrator.load(input)?;
+            channel.last_sample.load(input)?;
+            channel.buf[..len]
+                .iter_mut()
+                .try_for_each(|delta| delta.load(input))?;
+            channel.buf[len..].fill(0);
+        }
+        Ok(())
+    }
+}
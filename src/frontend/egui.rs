@@ -1,18 +1,36 @@
 use crate::config::Config;
+use crate::core::apu::mixer::{MixerChannel, MixerSettings};
+use crate::core::bus::PowerOnRam;
 use crate::core::console::Console;
-use crate::core::frame::Frame;
+use crate::core::expansion::family_basic_keyboard::KeyboardKey;
+use crate::core::expansion::ExpansionDeviceType;
 use crate::core::joypad::Buttons;
+use crate::core::nsf::NsfPlayer;
+use crate::core::ppu::layers::RenderLayers;
+use crate::core::ppu::palettes::{ntsc::NtscPaletteParams, Palette, PaletteSource};
+use crate::fds_parser::FdsImage;
+use crate::frontend::nsf;
+use crate::frontend::speed::{EmulationSpeed, SPEED_STEPS};
 use crate::ines_parser::NESFile;
+use crate::nsf_parser::NsfFile;
+use crate::video::ntsc::{NtscFilter, NtscPreset, NTSC_HEIGHT, NTSC_WIDTH};
+use crate::video::overscan::Overscan;
+use crate::video::scale::Scaler;
+use crate::video::Image;
 use crossbeam::channel::{self, Sender};
-use eframe::egui::{self, menu, CentralPanel, ColorImage, Key, TopBottomPanel, Ui};
+use eframe::egui::{
+    self, menu, Button, CentralPanel, ColorImage, Event, Grid, Key, ScrollArea, Slider,
+    TextureOptions, TopBottomPanel, Ui, Window,
+};
 use eframe::epaint::ImageData;
 use eframe::App;
 use lazy_static::lazy_static;
 use rfd::FileDialog;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 lazy_static! {
     static ref KEY_MAP: HashMap<Key, Buttons> = [
@@ -30,30 +48,157 @@ lazy_static! {
         acc.insert(*key, *button);
         acc
     });
+    static ref KEYBOARD_MAP: HashMap<Key, KeyboardKey> = [
+        (Key::A, KeyboardKey::A),
+        (Key::B, KeyboardKey::B),
+        (Key::C, KeyboardKey::C),
+        (Key::D, KeyboardKey::D),
+        (Key::E, KeyboardKey::E),
+        (Key::F, KeyboardKey::F),
+        (Key::G, KeyboardKey::G),
+        (Key::H, KeyboardKey::H),
+        (Key::I, KeyboardKey::I),
+        (Key::J, KeyboardKey::J),
+        (Key::K, KeyboardKey::K),
+        (Key::L, KeyboardKey::L),
+        (Key::M, KeyboardKey::M),
+        (Key::N, KeyboardKey::N),
+        (Key::O, KeyboardKey::O),
+        (Key::P, KeyboardKey::P),
+        (Key::Q, KeyboardKey::Q),
+        (Key::R, KeyboardKey::R),
+        (Key::S, KeyboardKey::S),
+        (Key::T, KeyboardKey::T),
+        (Key::U, KeyboardKey::U),
+        (Key::V, KeyboardKey::V),
+        (Key::W, KeyboardKey::W),
+        (Key::X, KeyboardKey::X),
+        (Key::Y, KeyboardKey::Y),
+        (Key::Z, KeyboardKey::Z),
+        (Key::Num0, KeyboardKey::Num0),
+        (Key::Num1, KeyboardKey::Num1),
+        (Key::Num2, KeyboardKey::Num2),
+        (Key::Num3, KeyboardKey::Num3),
+        (Key::Num4, KeyboardKey::Num4),
+        (Key::Num5, KeyboardKey::Num5),
+        (Key::Num6, KeyboardKey::Num6),
+        (Key::Num7, KeyboardKey::Num7),
+        (Key::Num8, KeyboardKey::Num8),
+        (Key::Num9, KeyboardKey::Num9),
+        (Key::F1, KeyboardKey::F1),
+        (Key::F2, KeyboardKey::F2),
+        (Key::F3, KeyboardKey::F3),
+        (Key::F4, KeyboardKey::F4),
+        (Key::F5, KeyboardKey::F5),
+        (Key::F6, KeyboardKey::F6),
+        (Key::F7, KeyboardKey::F7),
+        (Key::F8, KeyboardKey::F8),
+        (Key::Enter, KeyboardKey::Return),
+        (Key::Space, KeyboardKey::Space),
+        (Key::Escape, KeyboardKey::Escape),
+        (Key::Backspace, KeyboardKey::Del),
+        (Key::Insert, KeyboardKey::Ins),
+        (Key::Home, KeyboardKey::ClrHome),
+        (Key::End, KeyboardKey::Stop),
+        (Key::PageUp, KeyboardKey::Kana),
+        (Key::Minus, KeyboardKey::Minus),
+        (Key::PlusEquals, KeyboardKey::Caret),
+        (Key::ArrowUp, KeyboardKey::Up),
+        (Key::ArrowDown, KeyboardKey::Down),
+        (Key::ArrowLeft, KeyboardKey::Left),
+        (Key::ArrowRight, KeyboardKey::Right),
+    ]
+    .into_iter()
+    .collect();
+    // egui has no key codes for most punctuation, so these are picked up from text events and held
+    // for a single frame
+    static ref KEYBOARD_TEXT_MAP: HashMap<char, KeyboardKey> = [
+        ('[', KeyboardKey::LeftBracket),
+        (']', KeyboardKey::RightBracket),
+        (';', KeyboardKey::Semicolon),
+        (':', KeyboardKey::Colon),
+        ('@', KeyboardKey::At),
+        (',', KeyboardKey::Comma),
+        ('.', KeyboardKey::Period),
+        ('/', KeyboardKey::Slash),
+        ('_', KeyboardKey::Underscore),
+        ('\\', KeyboardKey::Yen),
+    ]
+    .into_iter()
+    .collect();
 }
-impl From<Frame> for ImageData {
-    fn from(value: Frame) -> Self {
-        ColorImage::from_rgb([256, 240], &value.image).into()
+impl From<Image> for ImageData {
+    fn from(value: Image) -> Self {
+        ColorImage::from_rgb([value.width, value.height], &value.pixels).into()
     }
 }
 
 pub enum ConsoleMsg {
     JoypadDown(Buttons),
     JoypadUp(Buttons),
-    RunFrame,
+    KeyboardDown(KeyboardKey),
+    KeyboardUp(KeyboardKey),
+    SetSpeed(EmulationSpeed),
+    FrameAdvance,
+    SetMixer(MixerSettings),
+    SetPalette(Box<Palette>),
+    RemoveSpriteLimit(bool),
+    SetRenderLayers(RenderLayers),
+    Reset,
+    PowerCycle(PowerOnRam),
+    PlaySong(u8),
+    InsertDisk(Option<usize>),
 }
 
+const PAUSE_KEY: Key = Key::F9;
+const FRAME_ADVANCE_KEY: Key = Key::F10;
+const SLOWER_KEY: Key = Key::F11;
+const FASTER_KEY: Key = Key::F12;
+const NORMAL_SPEED_KEY: Key = Key::PageDown;
+const TURBO_KEY: Key = Key::Tab;
+
 #[derive(Default)]
 pub struct EGuiApp {
-    console: Option<Arc<Mutex<Console<'static>>>>,
+    console: Option<Arc<Mutex<Console>>>,
+    // Loaded instead of `console` for NSF rips, which take the same messages
+    nsf: Option<Arc<Mutex<NsfPlayer>>>,
     channel: Option<Sender<ConsoleMsg>>,
+    expansion_device: Option<ExpansionDeviceType>,
+    // When set, host keys only drive the Family BASIC keyboard and not the joypad
+    keyboard_capture: bool,
+    // Family BASIC keys last sent as down, so only changes go over the channel
+    keyboard_keys: HashSet<KeyboardKey>,
+    // Keys typed as text, held down until the PPU has finished a frame past the stored count
+    typed_keys: HashMap<KeyboardKey, usize>,
+    // Speed picked through the menu/hotkeys. Holding the turbo key turns on turbo on top of this
+    speed: EmulationSpeed,
+    sent_speed: Option<EmulationSpeed>,
+    mixer: MixerSettings,
+    show_mixer: bool,
+    // Mixer changes not yet written to config.toml
+    mixer_dirty: bool,
+    palette: PaletteSource,
+    // Loaded copy of `palette` for the swatches
+    palette_preview: Option<Box<Palette>>,
+    // The last source that failed to load and why, so it isn't retried on every repaint
+    palette_error: Option<(PaletteSource, String)>,
+    show_palette: bool,
+    ntsc_filter: Option<NtscFilter>,
+    scaler: Scaler,
+    integer_scale: bool,
+    // Stretch pixels to the 8:7 shape they have on a TV
+    pixel_aspect: bool,
+    rom_hash: Option<u64>,
+    overscan: Overscan,
+    show_overscan: bool,
+    remove_sprite_limit: bool,
+    layers: RenderLayers,
+    power_on_ram: PowerOnRam,
 }
 
 impl App for EGuiApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if let Some(channel) = &self.channel {
-            channel.send(ConsoleMsg::RunFrame).unwrap();
-        }
+        self.handle_speed_hotkeys(ctx);
 
         // Draw
         ctx.request_repaint_after(Duration::new(0, 16_666_667 / 2));
@@ -62,32 +207,95 @@ impl App for EGuiApp {
                 menu::bar(ui, |ui| {
                     if ui.button("Load ROM").clicked() {
                         if let Some(path) = FileDialog::new().pick_file() {
-                            let file = NESFile::new(path);
-                            let hash = file.hash;
-                            self.load(file);
-                            if let Some(save_dir_str) = Config::get_string("save_directory") {
-                                let mut save_path = PathBuf::from(save_dir_str);
-                                save_path.push(format!("{}.sav", hash));
-                                if save_path.exists() {
-                                    self.load_save(save_path).unwrap();
-                                }
-                            }
+                            self.open_file(path);
                         }
                     }
                     if ui.button("Load save").clicked() {
                         if let Some(path) = FileDialog::new().pick_file() {
-                            self.load_save(path).unwrap();
+                            if let Err(e) = self.load_save(path.clone()) {
+                                println!("Failed to load {}: {e}", path.display());
+                            }
                         }
                     }
                     if ui.button("Save game").clicked() {
                         self.save_game().unwrap();
                     }
+                    if ui.button("Save state").clicked() {
+                        if let Err(e) = self.save_state() {
+                            println!("Failed to save state: {e}");
+                        }
+                    }
+                    if ui.button("Load state").clicked() {
+                        if let Err(e) = self.load_state() {
+                            println!("Failed to load state: {e}");
+                        }
+                    }
+                    if self.has_keyboard() {
+                        ui.checkbox(&mut self.keyboard_capture, "Keyboard capture");
+                    }
+                    ui.menu_button("Emulation", |ui| {
+                        self.reset_menu(ui);
+                        ui.separator();
+                        self.speed_menu(ui);
+                    });
+                    if let Some((sides, side)) = self.disk_status() {
+                        ui.menu_button("Disk", |ui| self.disk_menu(ui, sides, side));
+                    }
+                    ui.menu_button("Video", |ui| {
+                        if ui.button("Palette").clicked() {
+                            self.show_palette = true;
+                            ui.close_menu();
+                        }
+                        ui.menu_button("NTSC filter", |ui| self.ntsc_filter_menu(ui));
+                        ui.separator();
+                        self.display_menu(ui);
+                        if ui
+                            .checkbox(&mut self.remove_sprite_limit, "Remove sprite limit")
+                            .changed()
+                        {
+                            Self::save_setting("remove_sprite_limit", self.remove_sprite_limit);
+                            if let Some(channel) = &self.channel {
+                                channel
+                                    .send(ConsoleMsg::RemoveSpriteLimit(self.remove_sprite_limit))
+                                    .unwrap();
+                            }
+                        }
+                        if ui.button("Overscan").clicked() {
+                            self.show_overscan = true;
+                            ui.close_menu();
+                        }
+                        ui.separator();
+                        if ui.button("Screenshot").clicked() {
+                            if let Err(e) = self.save_screenshot() {
+                                println!("Failed to save screenshot: {e}");
+                            }
+                            ui.close_menu();
+                        }
+                    });
+                    ui.menu_button("Audio", |ui| {
+                        if ui.button("Mixer").clicked() {
+                            self.show_mixer = true;
+                            ui.close_menu();
+                        }
+                    });
+                    ui.menu_button("Debug", |ui| self.debug_menu(ui));
                 });
             });
+            self.mixer_window(ctx);
+            self.palette_window(ctx);
+            self.overscan_window(ctx);
+            self.crash_window(ctx);
 
-            CentralPanel::default().show(ctx, |ui| self.show_texture(ui));
+            CentralPanel::default().show(ctx, |ui| {
+                if self.nsf.is_some() {
+                    self.nsf_track_list(ui);
+                } else {
+                    self.show_texture(ui);
+                }
+            });
             self.handle_keyevent(ctx);
         });
+        self.sync_speed(ctx);
     }
 }
 
@@ -96,14 +304,132 @@ impl EGuiApp {
         Self {
             channel: None,
             console: None,
+            nsf: None,
+            expansion_device: None,
+            keyboard_capture: false,
+            keyboard_keys: HashSet::new(),
+            typed_keys: HashMap::new(),
+            speed: EmulationSpeed::default(),
+            sent_speed: None,
+            mixer: MixerSettings::from_config(),
+            show_mixer: false,
+            mixer_dirty: false,
+            palette: PaletteSource::from_config(),
+            palette_preview: None,
+            palette_error: None,
+            show_palette: false,
+            ntsc_filter: Config::get_string("ntsc_filter")
+                .and_then(|val| NtscPreset::from_config_str(&val))
+                .map(NtscFilter::new),
+            scaler: Config::get_string("video_scaler")
+                .and_then(|val| Scaler::from_config_str(&val))
+                .unwrap_or_default(),
+            integer_scale: Config::get_bool("integer_scale", false),
+            pixel_aspect: Config::get_bool("pixel_aspect", false),
+            rom_hash: None,
+            overscan: Overscan::from_config(None),
+            show_overscan: false,
+            remove_sprite_limit: Config::get_bool("remove_sprite_limit", false),
+            layers: RenderLayers::default(),
+            power_on_ram: PowerOnRam::from_config(),
         }
     }
 
+    // NSF rips go to the player, anything else is taken for an iNES ROM
+    fn open_file(&mut self, path: PathBuf) {
+        let is_nsf = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("nsf") || ext.eq_ignore_ascii_case("nsfe"));
+        if is_nsf {
+            match NsfFile::new(&path) {
+                Ok(file) => self.load_nsf(file),
+                Err(e) => println!("Failed to load {}: {e}", path.display()),
+            }
+            return;
+        }
+
+        let is_fds = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("fds"));
+        let file = if is_fds {
+            match Self::load_fds(&path) {
+                Ok(file) => file,
+                Err(e) => {
+                    println!("Failed to load {}: {e}", path.display());
+                    return;
+                }
+            }
+        } else {
+            NESFile::new(path)
+        };
+        let hash = file.hash;
+        self.load(file);
+        if let Some(save_dir_str) = Config::get_string("save_directory") {
+            let mut save_path = PathBuf::from(save_dir_str);
+            save_path.push(format!("{}.sav", hash));
+            if save_path.exists() {
+                if let Err(e) = self.load_save(save_path.clone()) {
+                    println!("Failed to load {}: {e}", save_path.display());
+                }
+            }
+        }
+    }
+
+    // Disk System games need the BIOS, which has to come from the user's own console
+    fn load_fds(path: &Path) -> io::Result<NESFile> {
+        let Some(bios_path) = Config::get_string("fds_bios_path") else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "set fds_bios_path in config.toml to your disksys.rom to play Disk System games",
+            ));
+        };
+        let bios = std::fs::read(&bios_path)
+            .map_err(|e| io::Error::new(e.kind(), format!("reading {bios_path}: {e}")))?;
+        NESFile::from_fds(FdsImage::new(path)?, bios)
+    }
+
+    fn load_nsf(&mut self, file: NsfFile) {
+        let (send, recv) = channel::bounded::<ConsoleMsg>(1024);
+        let mut player = nsf::player_from_config(file);
+        player.apu_mut().set_mixer(self.mixer);
+        let player = Arc::new(Mutex::new(player));
+        self.console = None;
+        self.rom_hash = None;
+        self.expansion_device = None;
+        self.keyboard_capture = false;
+        self.keyboard_keys.clear();
+        self.typed_keys.clear();
+        self.channel = Some(send);
+        self.sent_speed = None;
+        self.nsf = Some(player.clone());
+
+        std::thread::spawn(move || nsf::run_thread(player, recv));
+    }
+
     fn load(&mut self, rom: NESFile) {
         let (send, recv) = channel::bounded::<ConsoleMsg>(1024);
-        let console = Arc::new(Mutex::new(Console::new(rom)));
+        self.rom_hash = Some(rom.hash);
+        self.overscan = Overscan::from_config(self.rom_hash);
+        let mut console = Console::new(rom);
+        let bus = &mut console.emulator.cpu_mut().bus;
+        bus.apu.set_mixer(self.mixer);
+        bus.ppu.set_remove_sprite_limit(self.remove_sprite_limit);
+        bus.ppu.set_render_layers(self.layers);
+        bus.init_ram(self.power_on_ram);
+        if let Ok(palette) = self.palette.load() {
+            bus.ppu.set_palette(palette);
+        }
+        self.expansion_device = bus.expansion.as_ref().map(|expansion| expansion.get_type());
+        self.keyboard_capture = self.has_keyboard();
+        self.keyboard_keys.clear();
+        self.typed_keys.clear();
+        let console = Arc::new(Mutex::new(console));
         self.channel = Some(send);
+        self.sent_speed = None;
         self.console = Some(console.clone());
+        self.nsf = None;
 
         std::thread::spawn(move || {
             Console::run_thread(console.clone(), recv);
@@ -120,30 +446,654 @@ impl EGuiApp {
 
     fn load_save(&self, file: PathBuf) -> std::io::Result<()> {
         if let Some(console) = &self.console {
-            let console = console.lock().unwrap();
+            let mut console = console.lock().unwrap();
             console.load_save(file)?;
         }
         Ok(())
     }
 
-    fn show_texture(&self, ui: &mut Ui) {
+    fn save_state(&self) -> std::io::Result<()> {
+        if let Some(console) = &self.console {
+            console.lock().unwrap().save_state()?;
+        }
+        Ok(())
+    }
+
+    fn load_state(&self) -> std::io::Result<()> {
+        if let Some(console) = &self.console {
+            console.lock().unwrap().load_state()?;
+        }
+        Ok(())
+    }
+
+    fn show_texture(&mut self, ui: &mut Ui) {
         if let Some(console) = &self.console {
             let console = console.lock().unwrap();
-            let texture =
-                ui.ctx()
-                    .load_texture("NES", console.cpu.bus.ppu.curr_frame, Default::default());
-            let image = egui::Image::new((texture.id(), texture.size_vec2()))
-                .maintain_aspect_ratio(true)
-                .fit_to_fraction(egui::Vec2::new(1., 1.));
+            let ppu = &console.emulator.cpu().bus.ppu;
+            let image = match &mut self.ntsc_filter {
+                Some(filter) => Image {
+                    width: NTSC_WIDTH,
+                    height: NTSC_HEIGHT,
+                    pixels: filter
+                        .apply(console.emulator.frame(), ppu.palette(), ppu.frame_count)
+                        .to_vec(),
+                },
+                None => Image::from_frame(console.emulator.frame()),
+            };
+            drop(console);
+            let image = self.scaler.apply(&self.overscan.crop(&image));
+            let options = if self.integer_scale {
+                TextureOptions::NEAREST
+            } else {
+                TextureOptions::LINEAR
+            };
+            let texture = ui.ctx().load_texture("NES", image, options);
+
+            // Shape on screen is the same whatever resolution the filters leave the picture at
+            let par = if self.pixel_aspect { 8. / 7. } else { 1. };
+            let (width, height) = self.overscan.visible_size();
+            let size = egui::Vec2::new(width as f32 * par, height as f32);
+            let image = egui::Image::new((texture.id(), size)).maintain_aspect_ratio(true);
+            let image = if self.integer_scale {
+                let available = ui.available_size() / size;
+                let scale = available.x.min(available.y).floor().max(1.);
+                image.fit_to_exact_size(size * scale)
+            } else {
+                image.fit_to_fraction(egui::Vec2::new(1., 1.))
+            };
             ui.add_sized(ui.available_size(), image);
         }
     }
 
+    // Shown in place of the picture while an NSF rip is loaded. Picking a song starts it over
+    fn nsf_track_list(&self, ui: &mut Ui) {
+        let Some(player) = &self.nsf else {
+            return;
+        };
+        let player = player.lock().unwrap();
+        let file = player.file();
+        let mut selected = None;
+
+        ui.heading(&file.name);
+        ui.label(&file.artist);
+        ui.label(&file.copyright);
+        ui.label(format!(
+            "{}  {} / {}",
+            file.track_title(player.song()),
+            format_time(player.elapsed()),
+            format_time(player.track_length(player.song()))
+        ));
+        ui.separator();
+        ScrollArea::vertical().show(ui, |ui| {
+            Grid::new("nsf_tracks").striped(true).show(ui, |ui| {
+                for &song in &file.playlist {
+                    ui.label(format!("{}", song + 1));
+                    if ui
+                        .selectable_label(song == player.song(), file.track_title(song))
+                        .clicked()
+                    {
+                        selected = Some(song);
+                    }
+                    ui.label(format_time(player.track_length(song)));
+                    ui.end_row();
+                }
+            });
+        });
+        drop(player);
+
+        if let (Some(song), Some(channel)) = (selected, &self.channel) {
+            channel.send(ConsoleMsg::PlaySong(song)).unwrap();
+        }
+    }
+
+    // The unfiltered picture with the overscan cropped, named after the ROM like saves are
+    fn save_screenshot(&self) -> image::ImageResult<()> {
+        let (Some(console), Some(hash)) = (&self.console, self.rom_hash) else {
+            return Ok(());
+        };
+        let image = Image::from_frame(console.lock().unwrap().emulator.frame());
+        let mut path = PathBuf::from(Config::get_string_with_default(
+            "screenshot_directory",
+            "./screenshots/",
+        ));
+        std::fs::create_dir_all(&path)?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        path.push(format!("{hash}-{}.png", time.as_millis()));
+        self.overscan.crop(&image).save(path)
+    }
+
+    fn overscan_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_overscan;
+        let mut overscan = self.overscan;
+        Window::new("Overscan")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                Grid::new("overscan_edges").show(ui, |ui| {
+                    for (name, edge) in [
+                        ("Top", &mut overscan.top),
+                        ("Bottom", &mut overscan.bottom),
+                        ("Left", &mut overscan.left),
+                        ("Right", &mut overscan.right),
+                    ] {
+                        ui.label(name);
+                        ui.add(Slider::new(edge, 0..=Overscan::MAX).suffix("px"));
+                        ui.end_row();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("None").clicked() {
+                        overscan = Overscan::default();
+                    }
+                    if ui.button("NTSC").clicked() {
+                        overscan = Overscan::NTSC;
+                    }
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("Save as default").clicked() {
+                        if let Err(e) = overscan.save_to_config(None) {
+                            println!("Failed to save overscan: {e}");
+                        }
+                    }
+                    if let Some(hash) = self.rom_hash {
+                        if ui.button("Save for this game").clicked() {
+                            if let Err(e) = overscan.save_to_config(Some(hash)) {
+                                println!("Failed to save overscan: {e}");
+                            }
+                        }
+                        if ui.button("Use default").clicked() {
+                            if let Err(e) = Overscan::remove_from_config(hash) {
+                                println!("Failed to save overscan: {e}");
+                            }
+                            overscan = Overscan::from_config(None);
+                        }
+                    }
+                });
+            });
+        self.show_overscan = open;
+        self.overscan = overscan;
+    }
+
+    // Shown while the CPU is stuck on a JAM opcode, until the console is reset
+    fn crash_window(&self, ctx: &egui::Context) {
+        let jammed = self
+            .console
+            .as_ref()
+            .and_then(|console| console.lock().unwrap().emulator.jammed());
+        if let Some(addr) = jammed {
+            Window::new("CPU crashed")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "The game ran a JAM opcode at ${addr:04X}, which stops the CPU"
+                    ));
+                    ui.horizontal(|ui| {
+                        if let Some(channel) = &self.channel {
+                            if ui.button("Reset").clicked() {
+                                channel.send(ConsoleMsg::Reset).unwrap();
+                            }
+                            if ui.button("Power cycle").clicked() {
+                                channel
+                                    .send(ConsoleMsg::PowerCycle(self.power_on_ram))
+                                    .unwrap();
+                            }
+                        }
+                    });
+                });
+        }
+    }
+
+    fn debug_menu(&mut self, ui: &mut Ui) {
+        let mut layers = self.layers;
+        ui.checkbox(&mut layers.background, "Show background");
+        ui.checkbox(&mut layers.sprites, "Show sprites");
+        ui.add_enabled(
+            layers.sprites,
+            egui::Checkbox::new(
+                &mut layers.priority_sprites_only,
+                "Only sprites behind background",
+            ),
+        );
+        if layers != self.layers {
+            self.layers = layers;
+            if let Some(channel) = &self.channel {
+                channel.send(ConsoleMsg::SetRenderLayers(layers)).unwrap();
+            }
+        }
+    }
+
+    fn display_menu(&mut self, ui: &mut Ui) {
+        ui.menu_button("Scaler", |ui| {
+            for scaler in Scaler::ALL {
+                if ui.radio(self.scaler == scaler, scaler.name()).clicked() {
+                    self.scaler = scaler;
+                    Self::save_setting("video_scaler", scaler.config_str());
+                    ui.close_menu();
+                }
+            }
+        });
+        if ui
+            .checkbox(&mut self.integer_scale, "Integer scale")
+            .changed()
+        {
+            Self::save_setting("integer_scale", self.integer_scale);
+        }
+        if ui
+            .checkbox(&mut self.pixel_aspect, "8:7 pixel aspect")
+            .changed()
+        {
+            Self::save_setting("pixel_aspect", self.pixel_aspect);
+        }
+    }
+
+    // Writes a top level config.toml key
+    fn save_setting(key: &str, value: impl Into<toml_edit::Value>) {
+        if let Err(e) = Config::update(|doc| doc[key] = toml_edit::value(value)) {
+            println!("Failed to save {key}: {e}");
+        }
+    }
+
+    fn ntsc_filter_menu(&mut self, ui: &mut Ui) {
+        let current = self.ntsc_filter.as_ref().map(NtscFilter::preset);
+        let mut selected = current;
+        if ui.radio(current.is_none(), "Off").clicked() {
+            selected = None;
+        }
+        for preset in NtscPreset::ALL {
+            if ui.radio(current == Some(preset), preset.name()).clicked() {
+                selected = Some(preset);
+            }
+        }
+        if selected == current {
+            return;
+        }
+        ui.close_menu();
+        match (&mut self.ntsc_filter, selected) {
+            (Some(filter), Some(preset)) => filter.set_preset(preset),
+            (filter, preset) => *filter = preset.map(NtscFilter::new),
+        }
+        Self::save_setting(
+            "ntsc_filter",
+            selected.map_or("off", NtscPreset::config_str),
+        );
+    }
+
+    fn mixer_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_mixer;
+        let mut mixer = self.mixer;
+        Window::new("Mixer")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.checkbox(&mut mixer.stereo, "Stereo");
+                Grid::new("mixer_channels").striped(true).show(ui, |ui| {
+                    ui.label("");
+                    ui.label("Volume");
+                    ui.label("Mute");
+                    ui.label("Pan");
+                    ui.end_row();
+                    let stereo = mixer.stereo;
+                    for channel in MixerChannel::ALL {
+                        let mix = mixer.channel_mut(channel);
+                        ui.label(channel.name());
+                        let mut volume = (mix.volume * 100.).round() as i32;
+                        ui.add(Slider::new(&mut volume, 0..=100).suffix("%"));
+                        mix.volume = volume as f32 / 100.;
+                        ui.checkbox(&mut mix.muted, "");
+                        let mut pan = (mix.pan * 100.).round() as i32;
+                        ui.add_enabled(stereo, Slider::new(&mut pan, -100..=100));
+                        mix.pan = pan as f32 / 100.;
+                        ui.end_row();
+                    }
+                });
+                if ui.button("Reset").clicked() {
+                    mixer = MixerSettings::default();
+                }
+            });
+        self.show_mixer = open;
+
+        if mixer != self.mixer {
+            self.mixer = mixer;
+            self.mixer_dirty = true;
+            if let Some(channel) = &self.channel {
+                channel.send(ConsoleMsg::SetMixer(mixer)).unwrap();
+            }
+        }
+        // Wait for sliders to be let go before touching the disk
+        if self.mixer_dirty && !ctx.input(|i| i.pointer.any_down()) {
+            if let Err(e) = self.mixer.save_to_config() {
+                println!("Failed to save mixer settings: {e}");
+            }
+            self.mixer_dirty = false;
+        }
+    }
+
+    fn palette_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_palette;
+        let mut source = self.palette.clone();
+        Window::new("Palette")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let is_ntsc = matches!(source, PaletteSource::Ntsc(_));
+                let is_file = matches!(source, PaletteSource::File(_));
+                if ui
+                    .radio(source == PaletteSource::Default, "Default")
+                    .clicked()
+                {
+                    source = PaletteSource::Default;
+                }
+                if ui.radio(is_ntsc, "NTSC (generated)").clicked() && !is_ntsc {
+                    source = PaletteSource::Ntsc(NtscPaletteParams::default());
+                }
+                if ui
+                    .radio(source == PaletteSource::Rp2c03, "2C03 / 2C05 (RGB)")
+                    .clicked()
+                {
+                    source = PaletteSource::Rp2c03;
+                }
+                for version in 1..=4 {
+                    let name = format!("2C04-000{version} (Vs. System)");
+                    if ui
+                        .radio(source == PaletteSource::Rp2c04(version), name)
+                        .clicked()
+                    {
+                        source = PaletteSource::Rp2c04(version);
+                    }
+                }
+                ui.horizontal(|ui| {
+                    let clicked = ui.radio(is_file, "File").clicked();
+                    if ui.button("Load .pal...").clicked() || (clicked && !is_file) {
+                        if let Some(path) =
+                            FileDialog::new().add_filter("pal", &["pal"]).pick_file()
+                        {
+                            source = PaletteSource::File(path);
+                        }
+                    }
+                });
+                if let PaletteSource::File(path) = &source {
+                    ui.label(path.display().to_string());
+                }
+                if let Some((_, e)) = &self.palette_error {
+                    ui.label(format!("Failed to load palette: {e}"));
+                }
+
+                if let PaletteSource::Ntsc(params) = &mut source {
+                    ui.separator();
+                    Grid::new("ntsc_params").show(ui, |ui| {
+                        ui.label("Hue");
+                        ui.add(Slider::new(&mut params.hue, -45.0..=45.0).suffix("°"));
+                        ui.end_row();
+                        ui.label("Saturation");
+                        ui.add(Slider::new(&mut params.saturation, 0.0..=2.0));
+                        ui.end_row();
+                        ui.label("Contrast");
+                        ui.add(Slider::new(&mut params.contrast, 0.5..=1.5));
+                        ui.end_row();
+                        ui.label("Brightness");
+                        ui.add(Slider::new(&mut params.brightness, -0.5..=0.5));
+                        ui.end_row();
+                        ui.label("Gamma");
+                        ui.add(Slider::new(&mut params.gamma, 1.0..=3.0));
+                        ui.end_row();
+                    });
+                    if ui.button("Reset").clicked() {
+                        *params = NtscPaletteParams::default();
+                    }
+                }
+
+                if let Some(palette) = &self.palette_preview {
+                    ui.separator();
+                    Self::palette_swatches(ui, palette);
+                }
+            });
+        self.show_palette = open;
+
+        let failed = self
+            .palette_error
+            .as_ref()
+            .is_some_and(|(failed, _)| *failed == source);
+        if !failed && (source != self.palette || (open && self.palette_preview.is_none())) {
+            match source.load() {
+                Ok(palette) => {
+                    let palette = Box::new(palette);
+                    if let Some(channel) = &self.channel {
+                        channel
+                            .send(ConsoleMsg::SetPalette(palette.clone()))
+                            .unwrap();
+                    }
+                    self.palette_preview = Some(palette);
+                    self.palette = source;
+                    self.palette_error = None;
+                }
+                Err(e) => {
+                    println!("Failed to load palette: {e}");
+                    self.palette_error = Some((source, e.to_string()));
+                }
+            }
+        }
+    }
+
+    fn palette_swatches(ui: &mut Ui, palette: &Palette) {
+        const SIZE: f32 = 16.;
+        let (rect, _) =
+            ui.allocate_exact_size(egui::Vec2::new(SIZE * 16., SIZE * 4.), egui::Sense::hover());
+        for (i, color) in palette.system_palette[..0x40].iter().enumerate() {
+            let min = rect.min + egui::Vec2::new((i % 16) as f32 * SIZE, (i / 16) as f32 * SIZE);
+            ui.painter().rect_filled(
+                egui::Rect::from_min_size(min, egui::Vec2::splat(SIZE)),
+                0.,
+                egui::Color32::from_rgb(color.r, color.g, color.b),
+            );
+        }
+    }
+
+    // How many disk sides there are and which is in the drive, for Disk System games
+    fn disk_status(&self) -> Option<(usize, Option<usize>)> {
+        let console = self.console.as_ref()?.lock().unwrap();
+        let sides = console.emulator.disk_sides();
+        (sides > 0).then(|| (sides, console.emulator.disk_side()))
+    }
+
+    fn disk_menu(&self, ui: &mut Ui, sides: usize, side: Option<usize>) {
+        let Some(channel) = &self.channel else {
+            return;
+        };
+        if ui.radio(side.is_none(), "Eject").clicked() {
+            channel.send(ConsoleMsg::InsertDisk(None)).unwrap();
+            ui.close_menu();
+        }
+        for i in 0..sides {
+            if ui.radio(side == Some(i), FdsImage::side_name(i)).clicked() {
+                channel.send(ConsoleMsg::InsertDisk(Some(i))).unwrap();
+                ui.close_menu();
+            }
+        }
+    }
+
+    fn reset_menu(&mut self, ui: &mut Ui) {
+        if ui.button("Reset").clicked() {
+            if let Some(channel) = &self.channel {
+                channel.send(ConsoleMsg::Reset).unwrap();
+            }
+            ui.close_menu();
+        }
+        if ui.button("Power cycle").clicked() {
+            if let Some(channel) = &self.channel {
+                channel
+                    .send(ConsoleMsg::PowerCycle(self.power_on_ram))
+                    .unwrap();
+            }
+            ui.close_menu();
+        }
+        ui.menu_button("Power on RAM", |ui| {
+            for ram in PowerOnRam::ALL {
+                if ui.radio(self.power_on_ram == ram, ram.name()).clicked() {
+                    self.power_on_ram = ram;
+                    Self::save_setting("power_on_ram", ram.config_str());
+                    ui.close_menu();
+                }
+            }
+        });
+    }
+
+    fn speed_menu(&mut self, ui: &mut Ui) {
+        let pause_label = if self.speed.paused { "Resume" } else { "Pause" };
+        if ui
+            .add(Button::new(pause_label).shortcut_text(PAUSE_KEY.name()))
+            .clicked()
+        {
+            self.speed.paused = !self.speed.paused;
+            ui.close_menu();
+        }
+        if ui
+            .add_enabled(
+                self.speed.paused,
+                Button::new("Frame advance").shortcut_text(FRAME_ADVANCE_KEY.name()),
+            )
+            .clicked()
+        {
+            self.frame_advance();
+        }
+        ui.separator();
+        if ui
+            .add(Button::new("Slower").shortcut_text(SLOWER_KEY.name()))
+            .clicked()
+        {
+            self.speed.slower();
+        }
+        if ui
+            .add(Button::new("Faster").shortcut_text(FASTER_KEY.name()))
+            .clicked()
+        {
+            self.speed.faster();
+        }
+        if ui
+            .add(Button::new("Normal speed").shortcut_text(NORMAL_SPEED_KEY.name()))
+            .clicked()
+        {
+            self.speed.reset();
+        }
+        ui.menu_button("Speed", |ui| {
+            for step in SPEED_STEPS {
+                let label = format!("{}%", step * 100.);
+                if ui.radio(self.speed.multiplier() == step, label).clicked() {
+                    self.speed.set_multiplier(step);
+                    ui.close_menu();
+                }
+            }
+        });
+        ui.checkbox(
+            &mut self.speed.turbo,
+            format!("Turbo (hold {})", TURBO_KEY.name()),
+        );
+    }
+
+    fn handle_speed_hotkeys(&mut self, ctx: &egui::Context) {
+        let (pause, advance, slower, faster, normal) = ctx.input(|i| {
+            (
+                i.key_pressed(PAUSE_KEY),
+                i.key_pressed(FRAME_ADVANCE_KEY),
+                i.key_pressed(SLOWER_KEY),
+                i.key_pressed(FASTER_KEY),
+                i.key_pressed(NORMAL_SPEED_KEY),
+            )
+        });
+        if pause {
+            self.speed.paused = !self.speed.paused;
+        }
+        if advance && self.speed.paused {
+            self.frame_advance();
+        }
+        if slower {
+            self.speed.slower();
+        }
+        if faster {
+            self.speed.faster();
+        }
+        if normal {
+            self.speed.reset();
+        }
+    }
+
+    fn frame_advance(&self) {
+        if let Some(channel) = &self.channel {
+            channel.send(ConsoleMsg::FrameAdvance).unwrap();
+        }
+    }
+
+    fn sync_speed(&mut self, ctx: &egui::Context) {
+        let mut speed = self.speed;
+        speed.turbo |= ctx.input(|i| i.key_down(TURBO_KEY));
+        if let Some(channel) = &self.channel {
+            if self.sent_speed != Some(speed) {
+                channel.send(ConsoleMsg::SetSpeed(speed)).unwrap();
+                self.sent_speed = Some(speed);
+            }
+        }
+    }
+
+    fn has_keyboard(&self) -> bool {
+        self.expansion_device == Some(ExpansionDeviceType::FamilyBasicKeyboard)
+    }
+
     fn handle_keyevent(&mut self, ctx: &eframe::egui::Context) {
         if let Some(channel) = &self.channel {
             let keys_down = ctx.input(|i| i.keys_down.clone());
+            if self.has_keyboard() {
+                let (modifiers, text) = ctx.input(|i| {
+                    let text = i
+                        .events
+                        .iter()
+                        .filter_map(|event| match event {
+                            Event::Text(text) => Some(text.as_str()),
+                            _ => None,
+                        })
+                        .collect::<String>();
+                    (i.modifiers, text)
+                });
+                // Text events only last one repaint, which can fall between two of the game's polls
+                let frame_count = self.console.as_ref().map_or(0, |console| {
+                    console.lock().unwrap().emulator.cpu().bus.ppu.frame_count
+                });
+                for (c, kb_key) in KEYBOARD_TEXT_MAP.iter() {
+                    if text.contains(*c) {
+                        self.typed_keys.insert(*kb_key, frame_count);
+                    }
+                }
+                self.typed_keys
+                    .retain(|_, pressed_at| *pressed_at >= frame_count);
+
+                let mut pressed: HashSet<KeyboardKey> = KEYBOARD_MAP
+                    .iter()
+                    .filter(|(key, _)| keys_down.contains(key))
+                    .map(|(_, kb_key)| *kb_key)
+                    .chain(self.typed_keys.keys().copied())
+                    .collect();
+                for (kb_key, down) in [
+                    (KeyboardKey::LeftShift, modifiers.shift),
+                    (KeyboardKey::Ctrl, modifiers.ctrl),
+                    (KeyboardKey::Grph, modifiers.alt),
+                ] {
+                    if down {
+                        pressed.insert(kb_key);
+                    }
+                }
+                for key in pressed.difference(&self.keyboard_keys) {
+                    channel.send(ConsoleMsg::KeyboardDown(*key)).unwrap();
+                }
+                for key in self.keyboard_keys.difference(&pressed) {
+                    channel.send(ConsoleMsg::KeyboardUp(*key)).unwrap();
+                }
+                self.keyboard_keys = pressed;
+            }
+            let capture = self.has_keyboard() && self.keyboard_capture;
             KEY_MAP.iter().for_each(|(key, button)| {
-                if keys_down.contains(key) {
+                if !capture && keys_down.contains(key) {
                     channel.try_send(ConsoleMsg::JoypadDown(*button)).unwrap();
                 } else {
                     channel.send(ConsoleMsg::JoypadUp(*button)).unwrap();
@@ -152,3 +1102,9 @@ impl EGuiApp {
         }
     }
 }
+
+// Minutes and seconds
+fn format_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
@@ -1,2 +1,6 @@
+pub mod audio;
 pub mod blip_buf;
 pub mod egui;
+pub mod nsf;
+pub mod speed;
+pub mod wav;
@@ -0,0 +1,116 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{Receiver, TryRecvError};
+
+use crate::{
+    config::Config,
+    core::{
+        apu::{filter::FilterPreset, mixer::MixerSettings},
+        nsf::{NsfPlayer, DEFAULT_FADE_LENGTH, DEFAULT_TRACK_LENGTH},
+    },
+    frontend::{audio::AudioOutput, egui::ConsoleMsg, speed::EmulationSpeed},
+    nsf_parser::NsfFile,
+};
+
+/// The player with the audio settings and default song length from config.toml
+pub fn player_from_config(file: NsfFile) -> NsfPlayer {
+    let mut player = NsfPlayer::new(file);
+    let length = Config::get_int("nsf_track_length", DEFAULT_TRACK_LENGTH.as_secs() as i64);
+    let fade = Config::get_int("nsf_fade_length", DEFAULT_FADE_LENGTH.as_secs() as i64);
+    player.set_default_length(
+        Duration::from_secs(length.max(0) as u64),
+        Duration::from_secs(fade.max(0) as u64),
+    );
+
+    let apu = player.apu_mut();
+    if let Some(preset) =
+        Config::get_string("audio_filter").and_then(|val| FilterPreset::from_config_str(&val))
+    {
+        apu.set_filter_preset(preset);
+    }
+    apu.set_mixer(MixerSettings::from_config());
+    player
+}
+
+/// `Console::run_thread` for NSF rips. Takes the same messages, ignoring the ones about the
+/// picture and controllers, and moves on through the playlist when a song ends. Returns once the
+/// app drops its end of the channel
+pub fn run_thread(player: Arc<Mutex<NsfPlayer>>, recv: Receiver<ConsoleMsg>) {
+    let audio = AudioOutput::new();
+    let mut samples = Vec::with_capacity(1024);
+    player.lock().unwrap().set_sample_rate(audio.sample_rate());
+
+    let mut speed = EmulationSpeed::default();
+    let mut next_frame = Instant::now();
+
+    loop {
+        loop {
+            let msg = match recv.try_recv() {
+                Ok(msg) => msg,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            };
+            let mut player = player.lock().unwrap();
+            match msg {
+                ConsoleMsg::SetSpeed(new_speed) => {
+                    if speed.paused && !new_speed.paused {
+                        next_frame = Instant::now();
+                    }
+                    if new_speed.paused {
+                        audio.pause();
+                    } else {
+                        audio.play();
+                    }
+                    speed = new_speed;
+                }
+                ConsoleMsg::SetMixer(mixer) => player.apu_mut().set_mixer(mixer),
+                ConsoleMsg::PlaySong(song) => player.play_song(song),
+                ConsoleMsg::Reset => {
+                    let song = player.song();
+                    player.play_song(song);
+                }
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        if speed.paused || (!speed.turbo && now < next_frame) {
+            let wait = if speed.paused {
+                Duration::from_millis(1)
+            } else {
+                next_frame - now
+            };
+            if wait > Duration::from_millis(2) {
+                std::thread::sleep(wait - Duration::from_millis(1));
+            } else {
+                std::thread::yield_now();
+            }
+            continue;
+        }
+
+        let mut player = player.lock().unwrap();
+        player.step_frame();
+        if player.finished() {
+            player.next_song();
+        }
+
+        player.drain_audio(&mut samples);
+        if !speed.should_mute() {
+            audio.push_samples(&samples, player.audio_channels());
+            let apu = player.apu_mut();
+            let clock_rate = apu.clock_rate();
+            apu.output_buffer
+                .set_rates(clock_rate, audio.adjusted_sample_rate());
+        }
+        samples.clear();
+
+        let frame_duration = speed.frame_duration_at(player.frame_rate());
+        next_frame += frame_duration;
+        if next_frame < now {
+            next_frame = now + frame_duration;
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+// NTSC frame rate: 1789772.7272 Hz CPU clock / 29780.5 CPU cycles per frame
+pub const NTSC_FRAME_RATE: f64 = 60.0988;
+
+pub const SPEED_STEPS: [f64; 9] = [0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 3.0, 4.0, 8.0];
+const NORMAL_SPEED_IDX: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EmulationSpeed {
+    pub paused: bool,
+    // Uncapped, runs frames as fast as the host allows
+    pub turbo: bool,
+    speed_idx: usize,
+}
+
+impl Default for EmulationSpeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EmulationSpeed {
+    pub fn new() -> Self {
+        EmulationSpeed {
+            paused: false,
+            turbo: false,
+            speed_idx: NORMAL_SPEED_IDX,
+        }
+    }
+
+    pub fn multiplier(&self) -> f64 {
+        SPEED_STEPS[self.speed_idx]
+    }
+
+    pub fn set_multiplier(&mut self, multiplier: f64) {
+        self.speed_idx = SPEED_STEPS
+            .iter()
+            .position(|step| *step == multiplier)
+            .unwrap_or(NORMAL_SPEED_IDX);
+    }
+
+    pub fn faster(&mut self) {
+        self.speed_idx = (self.speed_idx + 1).min(SPEED_STEPS.len() - 1);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed_idx = self.speed_idx.saturating_sub(1);
+    }
+
+    pub fn reset(&mut self) {
+        self.speed_idx = NORMAL_SPEED_IDX;
+    }
+
+    pub fn is_normal(&self) -> bool {
+        !self.turbo && self.speed_idx == NORMAL_SPEED_IDX
+    }
+
+    // Audio is only played back at 1x. Anything else would play at the wrong pitch
+    pub fn should_mute(&self) -> bool {
+        !self.is_normal()
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        self.frame_duration_at(NTSC_FRAME_RATE)
+    }
+
+    // For PAL NSF rips, which play at 50 Hz
+    pub fn frame_duration_at(&self, frame_rate: f64) -> Duration {
+        Duration::from_secs_f64(1. / (frame_rate * self.multiplier()))
+    }
+}
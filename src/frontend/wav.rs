@@ -0,0 +1,36 @@
+use std::io::{self, Write};
+
+/// Writes `samples` out as a 16-bit PCM WAV file. `samples` is mono, or interleaved left/right
+/// when `channels` is 2
+pub fn write_wav(
+    out: &mut impl Write,
+    samples: &[i16],
+    channels: u16,
+    sample_rate: u32,
+) -> io::Result<()> {
+    let data_len = u32::try_from(samples.len() * 2)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too much audio for a WAV"))?;
+    let block_align = channels * 2;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    // PCM
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&channels.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * u32::from(block_align)).to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    let bytes = samples
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect::<Vec<_>>();
+    out.write_all(&bytes)
+}
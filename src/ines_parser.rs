@@ -2,9 +2,15 @@
 
 use std::{
     hash::{DefaultHasher, Hash, Hasher},
+    io,
     path::PathBuf,
 };
 
+use crate::{
+    core::{mappers::fds::fits_on_disk, savestate::invalid_data},
+    fds_parser::FdsImage,
+};
+
 const NES_MAGIC: [u8; 4] = [0x4e, 0x45, 0x53, 0x1a];
 
 pub enum NameTableMirrorType {
@@ -186,7 +192,7 @@ impl DefaultExpansionDevice {
 }
 
 #[derive(Clone, Copy, Debug)]
-enum ConsoleType {
+pub enum ConsoleType {
     VsSystemType(VsSystemType),
     Extended(ExtendedConsoleType),
     _Unused(u8),
@@ -198,7 +204,7 @@ pub struct Header {
     prg_rom_size_lsb: u8,
     chr_rom_size_lsb: u8,
     pub flags1: Flags1,
-    _flags2: Flags2,
+    flags2: Flags2,
     _mapper_msb: MapperMSB,
     rom_size_msb: ROMSizeMSB,
     prg_ram_eeprom_size: PRGRAMEEPROMSize,
@@ -206,10 +212,14 @@ pub struct Header {
     _timing: Timing,
     _console_type: ConsoleType,
     _misc_roms: MiscROMs,
-    _default_expansion_device: DefaultExpansionDevice,
+    default_expansion_device: DefaultExpansionDevice,
 }
 
 impl Header {
+    pub fn is_nes2(&self) -> bool {
+        self.flags2.get(Flags2Enum::MAGIC) == 2
+    }
+
     pub fn new(bytes: [u8; 16]) -> Self {
         assert_eq!(bytes[0..4], NES_MAGIC);
         // assert!(bytes[7] & 0x0c == 0x08);
@@ -218,7 +228,7 @@ impl Header {
             prg_rom_size_lsb: bytes[4],
             chr_rom_size_lsb: bytes[5],
             flags1: Flags1(bytes[6]),
-            _flags2: Flags2(bytes[7]),
+            flags2: Flags2(bytes[7]),
             _mapper_msb: MapperMSB(bytes[8]),
             rom_size_msb: ROMSizeMSB(bytes[9]),
             prg_ram_eeprom_size: PRGRAMEEPROMSize(bytes[10]),
@@ -230,7 +240,7 @@ impl Header {
                 _ => ConsoleType::_Unused(bytes[13]),
             },
             _misc_roms: MiscROMs(bytes[14]),
-            _default_expansion_device: DefaultExpansionDevice(bytes[15]),
+            default_expansion_device: DefaultExpansionDevice(bytes[15]),
         }
     }
 }
@@ -273,12 +283,19 @@ pub struct NESFile {
     // Misc ROM Area
     pub misc_rom_area: Option<Vec<u8>>,
 
+    // Disk System images have no cartridge, the BIOS goes in the PRG ROM area
+    pub disk: Option<FdsImage>,
+
     pub hash: u64,
 }
 
 impl NESFile {
     pub fn new(file_path: PathBuf) -> Self {
-        let bytes = std::fs::read(file_path).unwrap();
+        Self::from_bytes(&std::fs::read(file_path).unwrap())
+    }
+
+    /// Parses an iNES or NES 2.0 image that's already in memory
+    pub fn from_bytes(bytes: &[u8]) -> Self {
         let file_size = bytes.len();
 
         let header = Header::new(bytes[..16].try_into().unwrap());
@@ -315,11 +332,43 @@ impl NESFile {
             prg_rom_area,
             chr_rom_area,
             misc_rom_area,
+            disk: None,
             hash: hasher.finish(),
         }
     }
 
+    /// Inserts `disk` into a Disk System running `bios`, the 8KB disksys.rom. The header is made up
+    /// to say there's a battery, since the disk keeps whatever the game writes to it
+    pub fn from_fds(disk: FdsImage, bios: Vec<u8>) -> io::Result<Self> {
+        if bios.len() != 0x2000 {
+            return Err(invalid_data("the Disk System BIOS should be 8KB"));
+        }
+        if !disk.sides.iter().all(|side| fits_on_disk(side)) {
+            return Err(invalid_data(
+                "an FDS disk side is too long to fit on the disk",
+            ));
+        }
+        let mut header = [0; 16];
+        header[..4].copy_from_slice(&NES_MAGIC);
+        header[6] = 0x02;
+
+        Ok(NESFile {
+            header: Header::new(header),
+            trainer: None,
+            prg_rom_area: bios,
+            chr_rom_area: None,
+            misc_rom_area: None,
+            hash: disk.hash,
+            disk: Some(disk),
+        })
+    }
+
+    /// iNES 1.0 headers can't be trusted with the size, so those get 8KB the way most emulators do.
+    /// Plenty of test ROMs report their results there without asking for it
     pub fn get_prg_ram_size(&self) -> usize {
+        if !self.header.is_nes2() {
+            return 0x2000;
+        }
         let shift_count = self
             .header
             .prg_ram_eeprom_size
@@ -330,6 +379,14 @@ impl NESFile {
         64 << shift_count
     }
 
+    pub fn get_default_expansion_device(&self) -> u8 {
+        if self.header.is_nes2() {
+            self.header.default_expansion_device.get()
+        } else {
+            0
+        }
+    }
+
     pub fn get_eeprom_size(&self) -> usize {
         let shift_count = self
             .header
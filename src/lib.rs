@@ -12,5 +12,9 @@
 #[allow(clippy::cast_precision_loss)]
 pub mod config;
 pub mod core;
+pub mod emulator;
+pub mod fds_parser;
 pub mod frontend;
 pub mod ines_parser;
+pub mod nsf_parser;
+pub mod video;
@@ -0,0 +1,330 @@
+use std::{io, path::Path, time::Duration};
+
+use bitflags::bitflags;
+
+use crate::core::savestate::invalid_data;
+
+const NSF_MAGIC: &[u8; 5] = b"NESM\x1a";
+const NSFE_MAGIC: &[u8; 4] = b"NSFE";
+const NSF_HEADER_SIZE: usize = 0x80;
+
+/// PLAY rate in microseconds that NTSC rips use, 60.1 Hz. Also used when a header leaves it at 0
+pub const DEFAULT_NTSC_SPEED: u16 = 16639;
+/// 50 Hz for PAL rips
+pub const DEFAULT_PAL_SPEED: u16 = 19997;
+
+bitflags! {
+    /// Sound chips a rip uses besides the 2A03
+    #[derive(Default)]
+    pub struct ExpansionAudio: u8 {
+        const VRC6 = 0x01;
+        const VRC7 = 0x02;
+        const FDS = 0x04;
+        const MMC5 = 0x08;
+        const N163 = 0x10;
+        const SUNSOFT_5B = 0x20;
+    }
+}
+
+impl ExpansionAudio {
+    pub fn names(self) -> Vec<&'static str> {
+        [
+            (Self::VRC6, "VRC6"),
+            (Self::VRC7, "VRC7"),
+            (Self::FDS, "FDS"),
+            (Self::MMC5, "MMC5"),
+            (Self::N163, "Namco 163"),
+            (Self::SUNSOFT_5B, "Sunsoft 5B"),
+        ]
+        .into_iter()
+        .filter(|(chip, _)| self.contains(*chip))
+        .map(|(_, name)| name)
+        .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NsfRegion {
+    Ntsc,
+    Pal,
+    // Plays on either, INIT gets told which through X
+    Dual,
+}
+
+impl NsfRegion {
+    fn from_flags(flags: u8) -> Self {
+        match flags & 0x03 {
+            0 => NsfRegion::Ntsc,
+            1 => NsfRegion::Pal,
+            _ => NsfRegion::Dual,
+        }
+    }
+}
+
+/// What NSFe metadata says about a song. Plain NSF files have none of it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NsfTrack {
+    pub title: Option<String>,
+    pub length: Option<Duration>,
+    pub fade: Option<Duration>,
+}
+
+/// An NSF or NSFe rip: the game's sound driver and music data, with the addresses of the routines
+/// that start a song and play a tick of it
+#[derive(Clone, Debug)]
+pub struct NsfFile {
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub total_songs: u8,
+    // Zero based, unlike the NSF header
+    pub starting_song: u8,
+    pub name: String,
+    pub artist: String,
+    pub copyright: String,
+    pub ripper: Option<String>,
+    // PLAY rates in microseconds
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    // Initial 4KB banks for $8000-$FFFF, None when the rip isn't bankswitched
+    pub banks: Option<[u8; 8]>,
+    pub region: NsfRegion,
+    pub expansion: ExpansionAudio,
+    pub data: Vec<u8>,
+    // One per song
+    pub tracks: Vec<NsfTrack>,
+    // Order to play songs in. Every song in order unless the NSFe has a playlist
+    pub playlist: Vec<u8>,
+}
+
+impl NsfFile {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// Parses either format, told apart by the magic number
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.starts_with(NSF_MAGIC) {
+            Self::parse_nsf(bytes)
+        } else if let Some(chunks) = bytes.strip_prefix(NSFE_MAGIC) {
+            Self::parse_nsfe(chunks)
+        } else {
+            Err(invalid_data("not an NSF or NSFe file"))
+        }
+    }
+
+    fn empty() -> Self {
+        NsfFile {
+            load_addr: 0,
+            init_addr: 0,
+            play_addr: 0,
+            total_songs: 1,
+            starting_song: 0,
+            name: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: None,
+            ntsc_speed: DEFAULT_NTSC_SPEED,
+            pal_speed: DEFAULT_PAL_SPEED,
+            banks: None,
+            region: NsfRegion::Ntsc,
+            expansion: ExpansionAudio::empty(),
+            data: vec![],
+            tracks: vec![],
+            playlist: vec![],
+        }
+    }
+
+    fn parse_nsf(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < NSF_HEADER_SIZE {
+            return Err(invalid_data("NSF header is truncated"));
+        }
+        let word = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        let banks: [u8; 8] = bytes[0x70..0x78].try_into().unwrap();
+
+        let mut file = NsfFile {
+            load_addr: word(0x08),
+            init_addr: word(0x0A),
+            play_addr: word(0x0C),
+            total_songs: bytes[0x06],
+            starting_song: bytes[0x07].saturating_sub(1),
+            name: header_string(&bytes[0x0E..0x2E]),
+            artist: header_string(&bytes[0x2E..0x4E]),
+            copyright: header_string(&bytes[0x4E..0x6E]),
+            ntsc_speed: word(0x6E),
+            pal_speed: word(0x78),
+            banks: banks.iter().any(|&bank| bank != 0).then_some(banks),
+            region: NsfRegion::from_flags(bytes[0x7A]),
+            expansion: ExpansionAudio::from_bits_truncate(bytes[0x7B]),
+            data: bytes[NSF_HEADER_SIZE..].to_vec(),
+            ..Self::empty()
+        };
+
+        // NSF2 can put NSFe metadata after the program, whose length is then in the header
+        let data_len = u32::from_le_bytes([bytes[0x7D], bytes[0x7E], bytes[0x7F], 0]) as usize;
+        if bytes[0x05] >= 2 && data_len > 0 && data_len < file.data.len() {
+            let metadata = file.data.split_off(data_len);
+            file.parse_chunks(&metadata)?;
+        }
+        file.finish()
+    }
+
+    fn parse_nsfe(chunks: &[u8]) -> io::Result<Self> {
+        let mut file = Self::empty();
+        file.parse_chunks(chunks)?;
+        if file.init_addr == 0 {
+            return Err(invalid_data("NSFe file has no INFO chunk"));
+        }
+        file.finish()
+    }
+
+    // Each chunk is a 32 bit length, a four letter id and the data. Chunks starting with a capital
+    // are needed to play the file, so one we don't know means we can't
+    fn parse_chunks(&mut self, mut input: &[u8]) -> io::Result<()> {
+        let mut titles = vec![];
+        let mut lengths = vec![];
+        let mut fades = vec![];
+
+        while input.len() >= 8 {
+            let len = u32::from_le_bytes(input[..4].try_into().unwrap()) as usize;
+            let id: [u8; 4] = input[4..8].try_into().unwrap();
+            let data = input
+                .get(8..8 + len)
+                .ok_or_else(|| invalid_data("NSFe chunk runs past the end of the file"))?;
+            input = &input[8 + len..];
+
+            match &id {
+                b"INFO" => {
+                    if data.len() < 8 {
+                        return Err(invalid_data("NSFe INFO chunk is truncated"));
+                    }
+                    self.load_addr = u16::from_le_bytes([data[0], data[1]]);
+                    self.init_addr = u16::from_le_bytes([data[2], data[3]]);
+                    self.play_addr = u16::from_le_bytes([data[4], data[5]]);
+                    self.region = NsfRegion::from_flags(data[6]);
+                    self.expansion = ExpansionAudio::from_bits_truncate(data[7]);
+                    self.total_songs = data.get(8).copied().unwrap_or(1);
+                    self.starting_song = data.get(9).copied().unwrap_or(0);
+                }
+                b"DATA" => self.data = data.to_vec(),
+                b"BANK" => {
+                    let mut banks = [0; 8];
+                    banks
+                        .iter_mut()
+                        .zip(data)
+                        .for_each(|(bank, &val)| *bank = val);
+                    self.banks = Some(banks);
+                }
+                b"RATE" => {
+                    let rates = data
+                        .chunks_exact(2)
+                        .map(|rate| u16::from_le_bytes([rate[0], rate[1]]))
+                        .collect::<Vec<_>>();
+                    if let Some(&ntsc) = rates.first() {
+                        self.ntsc_speed = ntsc;
+                    }
+                    if let Some(&pal) = rates.get(1) {
+                        self.pal_speed = pal;
+                    }
+                }
+                b"plst" => self.playlist = data.to_vec(),
+                b"time" => lengths = milliseconds(data),
+                b"fade" => fades = milliseconds(data),
+                b"tlbl" => titles = null_terminated(data),
+                b"auth" => {
+                    let mut fields = null_terminated(data).into_iter();
+                    let mut next = |field: &mut String| {
+                        if let Some(val) = fields.next() {
+                            *field = val;
+                        }
+                    };
+                    next(&mut self.name);
+                    next(&mut self.artist);
+                    next(&mut self.copyright);
+                    self.ripper = fields.next();
+                }
+                b"NEND" => break,
+                _ if id[0].is_ascii_uppercase() => {
+                    return Err(invalid_data(&format!(
+                        "NSFe file needs the unsupported {} chunk",
+                        String::from_utf8_lossy(&id)
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        self.tracks = (0..self.total_songs as usize)
+            .map(|song| NsfTrack {
+                title: titles.get(song).filter(|title| !title.is_empty()).cloned(),
+                length: lengths.get(song).copied().flatten(),
+                fade: fades.get(song).copied().flatten(),
+            })
+            .collect();
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<Self> {
+        if self.total_songs == 0 {
+            return Err(invalid_data("NSF file has no songs"));
+        }
+        if self.data.is_empty() {
+            return Err(invalid_data("NSF file has no program data"));
+        }
+        // Disk System rips have RAM from $6000 to load into
+        let lowest_load = if self.expansion.contains(ExpansionAudio::FDS) {
+            0x6000
+        } else {
+            0x8000
+        };
+        if self.load_addr < lowest_load && self.banks.is_none() {
+            return Err(invalid_data("NSF load address is too low"));
+        }
+        self.tracks
+            .resize(self.total_songs as usize, NsfTrack::default());
+        self.playlist.retain(|&song| song < self.total_songs);
+        if self.playlist.is_empty() {
+            self.playlist = (0..self.total_songs).collect();
+        }
+        self.starting_song = self.starting_song.min(self.total_songs - 1);
+        if self.ntsc_speed == 0 {
+            self.ntsc_speed = DEFAULT_NTSC_SPEED;
+        }
+        if self.pal_speed == 0 {
+            self.pal_speed = DEFAULT_PAL_SPEED;
+        }
+        Ok(self)
+    }
+
+    /// The song's NSFe title, or its number when there isn't one
+    pub fn track_title(&self, song: u8) -> String {
+        self.tracks
+            .get(song as usize)
+            .and_then(|track| track.title.clone())
+            .unwrap_or_else(|| format!("Track {}", song + 1))
+    }
+}
+
+// 32 byte fields padded with zeros
+fn header_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn null_terminated(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .split(|&b| b == 0)
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+        .collect()
+}
+
+// Negative times mean the player should use its default
+fn milliseconds(bytes: &[u8]) -> Vec<Option<Duration>> {
+    bytes
+        .chunks_exact(4)
+        .map(|ms| {
+            let ms = i32::from_le_bytes(ms.try_into().unwrap());
+            u64::try_from(ms).ok().map(Duration::from_millis)
+        })
+        .collect()
+}
@@ -0,0 +1,67 @@
+pub mod ntsc;
+pub mod overscan;
+pub mod scale;
+
+use std::path::Path;
+
+use image::{save_buffer, ColorType, ImageResult};
+
+use crate::core::frame::Frame;
+
+pub type Rgb = [u8; 3];
+
+/// Owned RGB8 picture of any size, what the video filters take and produce
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    #[must_use]
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * 3],
+        }
+    }
+
+    #[must_use]
+    pub fn from_frame(frame: &Frame) -> Self {
+        Self {
+            width: 256,
+            height: 240,
+            pixels: frame.image.to_vec(),
+        }
+    }
+
+    /// Pixel at (x, y), with coordinates off the edge clamped to the nearest edge pixel
+    #[must_use]
+    pub fn get(&self, x: isize, y: isize) -> Rgb {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        let index = (y * self.width + x) * 3;
+        [
+            self.pixels[index],
+            self.pixels[index + 1],
+            self.pixels[index + 2],
+        ]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Rgb) {
+        let index = (y * self.width + x) * 3;
+        self.pixels[index..index + 3].copy_from_slice(&color);
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> ImageResult<()> {
+        save_buffer(
+            path,
+            &self.pixels,
+            self.width as u32,
+            self.height as u32,
+            ColorType::Rgb8,
+        )
+    }
+}
@@ -0,0 +1,255 @@
+use lazy_static::lazy_static;
+
+use crate::core::frame::Frame;
+use crate::core::ppu::palettes::ntsc::{phase_angle, signal_level, yiq_to_rgb};
+use crate::core::ppu::palettes::Palette;
+
+/// Width of the filtered image. Same as blargg's nes_ntsc for a 256 pixel input
+pub const NTSC_WIDTH: usize = 602;
+pub const NTSC_HEIGHT: usize = 240;
+
+// The PPU outputs 8 samples of the 12 phase colour wave for every pixel
+const SAMPLES_PER_PIXEL: usize = 8;
+const LINE_SAMPLES: usize = 256 * SAMPLES_PER_PIXEL;
+// Blank signal either side of the picture so the filter windows never run off the line
+const PAD: usize = 12;
+
+lazy_static! {
+    // Signal level of every palette index at every phase, and the subcarrier used to pull I and Q
+    // back out
+    static ref LEVELS: Box<[[f32; 12]; 0x200]> = {
+        let mut levels = Box::new([[0.; 12]; 0x200]);
+        for (index, phases) in levels.iter_mut().enumerate() {
+            for (phase, level) in phases.iter_mut().enumerate() {
+                *level = signal_level(index, phase);
+            }
+        }
+        levels
+    };
+    static ref CARRIER: [(f32, f32); 12] = {
+        let mut carrier = [(0., 0.); 12];
+        for (phase, entry) in carrier.iter_mut().enumerate() {
+            let angle = phase_angle(phase);
+            *entry = (angle.cos(), angle.sin());
+        }
+        carrier
+    };
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NtscPreset {
+    // Luma and chroma share one signal, so fine detail bleeds colour and the colour carrier shows
+    // up as crawling dots
+    #[default]
+    Composite,
+    // Separate luma and chroma, no crosstalk but chroma is still blurry
+    SVideo,
+    // Palette colours resampled to the same width, no signal artifacts
+    Rgb,
+    // Composite signal on a black and white set
+    Monochrome,
+}
+
+// Box filter widths in samples. A 12 sample luma window would remove the carrier completely
+struct Bandwidth {
+    luma: usize,
+    chroma: usize,
+    // Decode luma from a signal with the carrier already taken out
+    separate: bool,
+    color: bool,
+}
+
+impl NtscPreset {
+    pub const ALL: [NtscPreset; 4] = [
+        NtscPreset::Composite,
+        NtscPreset::SVideo,
+        NtscPreset::Rgb,
+        NtscPreset::Monochrome,
+    ];
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            NtscPreset::Composite => "Composite",
+            NtscPreset::SVideo => "S-Video",
+            NtscPreset::Rgb => "RGB",
+            NtscPreset::Monochrome => "Monochrome",
+        }
+    }
+
+    #[must_use]
+    pub const fn config_str(self) -> &'static str {
+        match self {
+            NtscPreset::Composite => "composite",
+            NtscPreset::SVideo => "svideo",
+            NtscPreset::Rgb => "rgb",
+            NtscPreset::Monochrome => "monochrome",
+        }
+    }
+
+    /// `None` for "off" or anything unrecognised
+    #[must_use]
+    pub fn from_config_str(val: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|preset| preset.config_str() == val.to_ascii_lowercase())
+    }
+
+    const fn bandwidth(self) -> Bandwidth {
+        match self {
+            // RGB skips the signal entirely
+            NtscPreset::Composite | NtscPreset::Rgb => Bandwidth {
+                luma: 10,
+                chroma: 12,
+                separate: false,
+                color: true,
+            },
+            NtscPreset::SVideo => Bandwidth {
+                luma: 4,
+                chroma: 12,
+                separate: true,
+                color: true,
+            },
+            NtscPreset::Monochrome => Bandwidth {
+                luma: 4,
+                chroma: 12,
+                separate: false,
+                color: false,
+            },
+        }
+    }
+}
+
+/// Re-creates the PPU's composite video signal from the palette indices of a frame and decodes
+/// it the way a TV would, in the style of blargg's nes_ntsc. See
+/// <https://www.nesdev.org/wiki/NTSC_video>
+pub struct NtscFilter {
+    preset: NtscPreset,
+    // Running sums of the line's luma, I and Q products so any window is two lookups
+    luma: Vec<f32>,
+    i: Vec<f32>,
+    q: Vec<f32>,
+    image: Vec<u8>,
+}
+
+impl NtscFilter {
+    #[must_use]
+    pub fn new(preset: NtscPreset) -> Self {
+        let len = LINE_SAMPLES + 2 * PAD + 1;
+        Self {
+            preset,
+            luma: vec![0.; len],
+            i: vec![0.; len],
+            q: vec![0.; len],
+            image: vec![0; NTSC_WIDTH * NTSC_HEIGHT * 3],
+        }
+    }
+
+    #[must_use]
+    pub const fn preset(&self) -> NtscPreset {
+        self.preset
+    }
+
+    pub fn set_preset(&mut self, preset: NtscPreset) {
+        self.preset = preset;
+    }
+
+    /// Filters `frame` into a `NTSC_WIDTH` x `NTSC_HEIGHT` RGB image. `frame_count` picks which of
+    /// the three colour wave phases the frame starts on, giving the dot crawl. The palette is only
+    /// used by the RGB preset
+    pub fn apply(&mut self, frame: &Frame, palette: &Palette, frame_count: usize) -> &[u8] {
+        for y in 0..NTSC_HEIGHT {
+            let indices = &frame.indices[y * 256..(y + 1) * 256];
+            if self.preset == NtscPreset::Rgb {
+                Self::resample(indices, palette, self.line_mut(y));
+                continue;
+            }
+            // A scanline is 341 * 8 samples, 4 past a whole number of colour cycles, so each
+            // line starts 4 phases on from the last. The frame does the same
+            let start = ((y + frame_count % 3) * 4) % 12;
+            self.modulate(indices, start);
+            self.demodulate(y);
+        }
+        &self.image
+    }
+
+    fn modulate(&mut self, indices: &[u16], start: usize) {
+        let separate = self.preset.bandwidth().separate;
+        let (mut y, mut i, mut q) = (0., 0., 0.);
+        for s in 0..LINE_SAMPLES + 2 * PAD {
+            // PAD is a whole colour cycle so the phase lines up with the unpadded sample
+            let phase = (start + s) % 12;
+            let (level, luma) = match s.checked_sub(PAD) {
+                Some(x) if x < LINE_SAMPLES => {
+                    let levels = &LEVELS[indices[x / SAMPLES_PER_PIXEL] as usize & 0x1ff];
+                    let luma = if separate {
+                        levels.iter().sum::<f32>() / 12.
+                    } else {
+                        levels[phase]
+                    };
+                    (levels[phase], luma)
+                }
+                _ => (0., 0.),
+            };
+            let (cos, sin) = CARRIER[phase];
+            // The chroma demodulator on an S-Video set never sees the luma
+            let chroma = if separate { level - luma } else { level };
+            y += luma;
+            i += chroma * cos;
+            q += chroma * sin;
+            self.luma[s + 1] = y;
+            self.i[s + 1] = i;
+            self.q[s + 1] = q;
+        }
+    }
+
+    fn line_mut(&mut self, row: usize) -> &mut [u8] {
+        &mut self.image[row * NTSC_WIDTH * 3..(row + 1) * NTSC_WIDTH * 3]
+    }
+
+    // Decodes the modulated line into image row `row`
+    fn demodulate(&mut self, row: usize) {
+        let bandwidth = self.preset.bandwidth();
+        let line = &mut self.image[row * NTSC_WIDTH * 3..(row + 1) * NTSC_WIDTH * 3];
+        let window = |sums: &[f32], center: f32, width: usize| {
+            let start = (center - width as f32 / 2.).round() as usize;
+            (sums[start + width] - sums[start]) / width as f32
+        };
+        for (x, pixel) in line.chunks_exact_mut(3).enumerate() {
+            let center = PAD as f32 + (x as f32 + 0.5) * LINE_SAMPLES as f32 / NTSC_WIDTH as f32;
+            let y = window(&self.luma, center, bandwidth.luma);
+            let (i, q) = if bandwidth.color {
+                (
+                    window(&self.i, center, bandwidth.chroma),
+                    window(&self.q, center, bandwidth.chroma),
+                )
+            } else {
+                (0., 0.)
+            };
+            let (r, g, b) = yiq_to_rgb(y, i, q);
+            pixel[0] = (r.clamp(0., 1.) * 255.).round() as u8;
+            pixel[1] = (g.clamp(0., 1.) * 255.).round() as u8;
+            pixel[2] = (b.clamp(0., 1.) * 255.).round() as u8;
+        }
+    }
+
+    // Area-weighted stretch to the filtered width, so pixels stay sharp but equally wide
+    fn resample(indices: &[u16], palette: &Palette, line: &mut [u8]) {
+        let scale = 256. / NTSC_WIDTH as f32;
+        for (x, pixel) in line.chunks_exact_mut(3).enumerate() {
+            let left = x as f32 * scale;
+            let first = left as usize;
+            let last = ((left + scale) as usize).min(255);
+            // Share of the output pixel covered by the first input pixel
+            let weight = if first == last {
+                1.
+            } else {
+                (last as f32 - left) / scale
+            };
+            let a = palette.get_indexed(indices[first]);
+            let b = palette.get_indexed(indices[last]);
+            let mix = |a: u8, b: u8| (a as f32 * weight + b as f32 * (1. - weight)).round() as u8;
+            pixel.copy_from_slice(&[mix(a.r, b.r), mix(a.g, b.g), mix(a.b, b.b)]);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use toml_edit::{InlineTable, Item};
+
+use super::Image;
+use crate::config::Config;
+
+/// Pixels hidden at each edge of the 256x240 picture. Kept in NES pixels whatever size the
+/// picture has been filtered to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    /// Most that can be cropped from a single edge
+    pub const MAX: usize = 64;
+    /// About what an NTSC TV hides
+    pub const NTSC: Overscan = Overscan {
+        top: 8,
+        bottom: 8,
+        left: 0,
+        right: 0,
+    };
+
+    /// The `overscan` key of config.toml, or the ROM's entry in the `[game_overscan]` table when
+    /// it has one. Read from disk each time since the overscan window saves while running
+    #[must_use]
+    pub fn from_config(rom_hash: Option<u64>) -> Self {
+        let Ok(doc) = Config::read() else {
+            return Self::default();
+        };
+        let global = doc
+            .get("overscan")
+            .and_then(Self::from_item)
+            .unwrap_or_default();
+        rom_hash
+            .and_then(|hash| doc.get("game_overscan")?.get(hash.to_string()))
+            .and_then(Self::from_item)
+            .unwrap_or(global)
+    }
+
+    fn from_item(item: &Item) -> Option<Self> {
+        let table = item.as_table_like()?;
+        let edge = |key: &str| {
+            let val = table.get(key).and_then(Item::as_integer).unwrap_or(0);
+            val.clamp(0, Self::MAX as i64) as usize
+        };
+        Some(Self {
+            top: edge("top"),
+            bottom: edge("bottom"),
+            left: edge("left"),
+            right: edge("right"),
+        })
+    }
+
+    /// Saves as the default, or just for the ROM when `rom_hash` is set
+    pub fn save_to_config(&self, rom_hash: Option<u64>) -> std::io::Result<()> {
+        let mut table = InlineTable::new();
+        table.insert("top", (self.top as i64).into());
+        table.insert("bottom", (self.bottom as i64).into());
+        table.insert("left", (self.left as i64).into());
+        table.insert("right", (self.right as i64).into());
+        Config::update(|doc| match rom_hash {
+            Some(hash) => {
+                doc["game_overscan"].or_insert(toml_edit::table())[&hash.to_string()] =
+                    toml_edit::value(table);
+            }
+            None => doc["overscan"] = toml_edit::value(table),
+        })
+    }
+
+    /// Drops the ROM's own setting so it goes back to the default
+    pub fn remove_from_config(rom_hash: u64) -> std::io::Result<()> {
+        Config::update(|doc| {
+            if let Some(games) = doc
+                .get_mut("game_overscan")
+                .and_then(Item::as_table_like_mut)
+            {
+                games.remove(&rom_hash.to_string());
+            }
+        })
+    }
+
+    /// Size of what's left of the 256x240 picture
+    #[must_use]
+    pub const fn visible_size(&self) -> (usize, usize) {
+        (
+            256usize.saturating_sub(self.left + self.right),
+            240usize.saturating_sub(self.top + self.bottom),
+        )
+    }
+
+    /// Crops `image`, which can be any multiple or stretch of 256x240
+    #[must_use]
+    pub fn crop(&self, image: &Image) -> Image {
+        let scale_x = |val: usize| (val * image.width + 128) / 256;
+        let scale_y = |val: usize| (val * image.height + 120) / 240;
+        let left = scale_x(self.left).min(image.width);
+        let top = scale_y(self.top).min(image.height);
+        let right = image.width - scale_x(self.right).min(image.width - left);
+        let bottom = image.height - scale_y(self.bottom).min(image.height - top);
+
+        let mut out = Image::new(right - left, bottom - top);
+        for y in top..bottom {
+            let row = y * image.width * 3;
+            let out_row = (y - top) * out.width * 3;
+            out.pixels[out_row..out_row + out.width * 3]
+                .copy_from_slice(&image.pixels[row + left * 3..row + right * 3]);
+        }
+        out
+    }
+}
@@ -0,0 +1,255 @@
+use super::{Image, Rgb};
+
+// Brightness of the dark row between scanlines
+const SCANLINE_BRIGHTNESS: f32 = 0.6;
+// Brightness of the two phosphor colours that aren't lit in each column of the CRT mask
+const MASK_BRIGHTNESS: f32 = 0.7;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scaler {
+    #[default]
+    None,
+    Scale2x,
+    Scale3x,
+    Xbr,
+    // 2x with every other row darkened
+    Scanlines,
+    // 3x with an aperture grille and scanlines
+    CrtMask,
+}
+
+impl Scaler {
+    pub const ALL: [Scaler; 6] = [
+        Scaler::None,
+        Scaler::Scale2x,
+        Scaler::Scale3x,
+        Scaler::Xbr,
+        Scaler::Scanlines,
+        Scaler::CrtMask,
+    ];
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Scaler::None => "None",
+            Scaler::Scale2x => "Scale2x",
+            Scaler::Scale3x => "Scale3x",
+            Scaler::Xbr => "xBR",
+            Scaler::Scanlines => "Scanlines",
+            Scaler::CrtMask => "CRT mask",
+        }
+    }
+
+    #[must_use]
+    pub const fn config_str(self) -> &'static str {
+        match self {
+            Scaler::None => "none",
+            Scaler::Scale2x => "scale2x",
+            Scaler::Scale3x => "scale3x",
+            Scaler::Xbr => "xbr",
+            Scaler::Scanlines => "scanlines",
+            Scaler::CrtMask => "crt",
+        }
+    }
+
+    #[must_use]
+    pub fn from_config_str(val: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|scaler| scaler.config_str() == val.to_ascii_lowercase())
+    }
+
+    #[must_use]
+    pub const fn factor(self) -> usize {
+        match self {
+            Scaler::None => 1,
+            Scaler::Scale2x | Scaler::Xbr | Scaler::Scanlines => 2,
+            Scaler::Scale3x | Scaler::CrtMask => 3,
+        }
+    }
+
+    /// Returns `image` scaled up by `factor()`
+    #[must_use]
+    pub fn apply(self, image: &Image) -> Image {
+        if self == Scaler::None {
+            return image.clone();
+        }
+        let factor = self.factor();
+        let mut out = Image::new(image.width * factor, image.height * factor);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let pixel = image.get(x as isize, y as isize);
+                // Each kernel returns the row-major `factor` x `factor` block for this pixel
+                let mut put = |block: &[Rgb]| {
+                    for (i, color) in block.iter().enumerate() {
+                        out.set(x * factor + i % factor, y * factor + i / factor, *color);
+                    }
+                };
+                match self {
+                    Scaler::None => put(&[pixel]),
+                    Scaler::Scale2x => put(&scale2x(&Neighbours::new(image, x, y))),
+                    Scaler::Scale3x => put(&scale3x(&Neighbours::new(image, x, y))),
+                    Scaler::Xbr => put(&xbr(image, x, y)),
+                    Scaler::Scanlines => put(&scanlines(pixel)),
+                    Scaler::CrtMask => put(&crt_mask(pixel)),
+                }
+            }
+        }
+        out
+    }
+}
+
+// The 3x3 block around E
+//   A B C
+//   D E F
+//   G H I
+struct Neighbours {
+    a: Rgb,
+    b: Rgb,
+    c: Rgb,
+    d: Rgb,
+    e: Rgb,
+    f: Rgb,
+    g: Rgb,
+    h: Rgb,
+    i: Rgb,
+}
+
+impl Neighbours {
+    fn new(image: &Image, x: usize, y: usize) -> Self {
+        let (x, y) = (x as isize, y as isize);
+        Self {
+            a: image.get(x - 1, y - 1),
+            b: image.get(x, y - 1),
+            c: image.get(x + 1, y - 1),
+            d: image.get(x - 1, y),
+            e: image.get(x, y),
+            f: image.get(x + 1, y),
+            g: image.get(x - 1, y + 1),
+            h: image.get(x, y + 1),
+            i: image.get(x + 1, y + 1),
+        }
+    }
+}
+
+fn blend(a: Rgb, b: Rgb, weight: f32) -> Rgb {
+    let mix = |a: u8, b: u8| (a as f32 * (1. - weight) + b as f32 * weight).round() as u8;
+    [mix(a[0], b[0]), mix(a[1], b[1]), mix(a[2], b[2])]
+}
+
+fn dim(color: Rgb, brightness: f32) -> Rgb {
+    color.map(|c| (c as f32 * brightness).round() as u8)
+}
+
+fn yuv(color: Rgb) -> (i32, i32, i32) {
+    let [r, g, b] = color.map(i32::from);
+    (
+        (r + g + b) / 3,
+        (r - b) / 4 + 128,
+        (2 * g - r - b) / 8 + 128,
+    )
+}
+
+// Weighted colour distance used by xBR
+fn distance(a: Rgb, b: Rgb) -> i32 {
+    let (ya, ua, va) = yuv(a);
+    let (yb, ub, vb) = yuv(b);
+    (ya - yb).abs() * 48 + (ua - ub).abs() * 7 + (va - vb).abs() * 6
+}
+
+// https://www.scale2x.it/algorithm
+fn scale2x(n: &Neighbours) -> [Rgb; 4] {
+    if n.b == n.h || n.d == n.f {
+        return [n.e; 4];
+    }
+    [
+        if n.d == n.b { n.d } else { n.e },
+        if n.b == n.f { n.f } else { n.e },
+        if n.d == n.h { n.d } else { n.e },
+        if n.h == n.f { n.f } else { n.e },
+    ]
+}
+
+fn scale3x(n: &Neighbours) -> [Rgb; 9] {
+    if n.b == n.h || n.d == n.f {
+        return [n.e; 9];
+    }
+    let pick = |cond: bool, color: Rgb| if cond { color } else { n.e };
+    [
+        pick(n.d == n.b, n.d),
+        pick(
+            (n.d == n.b && n.e != n.c) || (n.b == n.f && n.e != n.a),
+            n.b,
+        ),
+        pick(n.b == n.f, n.f),
+        pick(
+            (n.d == n.b && n.e != n.g) || (n.d == n.h && n.e != n.a),
+            n.d,
+        ),
+        n.e,
+        pick(
+            (n.b == n.f && n.e != n.i) || (n.h == n.f && n.e != n.c),
+            n.f,
+        ),
+        pick(n.d == n.h, n.d),
+        pick(
+            (n.d == n.h && n.e != n.i) || (n.h == n.f && n.e != n.g),
+            n.h,
+        ),
+        pick(n.h == n.f, n.f),
+    ]
+}
+
+// 2xBR, https://forums.libretro.com/t/xbr-algorithm-tutorial/123
+fn xbr(image: &Image, x: usize, y: usize) -> [Rgb; 4] {
+    let (x, y) = (x as isize, y as isize);
+    let e = image.get(x, y);
+    let mut block = [e; 4];
+    // Each output pixel looks at the edge across its own corner, mirroring the neighbourhood
+    for (n, (dx, dy)) in [(-1, -1), (1, -1), (-1, 1), (1, 1)].into_iter().enumerate() {
+        let at = |ox: isize, oy: isize| image.get(x + ox * dx, y + oy * dy);
+        let (b, c, d, f) = (at(0, -1), at(1, -1), at(-1, 0), at(1, 0));
+        let (g, h, i) = (at(-1, 1), at(0, 1), at(1, 1));
+        let (f4, h5, i4, i5) = (at(2, 0), at(0, 2), at(2, 1), at(1, 2));
+        // Change in colour across the F-H diagonal and across the E-I one. When the first is
+        // smaller the colours run along F-H, so there's an edge cutting off this corner
+        let along_fh = distance(e, c)
+            + distance(e, g)
+            + distance(i, f4)
+            + distance(i, h5)
+            + 4 * distance(h, f);
+        let along_ei = distance(h, d)
+            + distance(h, i5)
+            + distance(f, i4)
+            + distance(f, b)
+            + 4 * distance(e, i);
+        if along_fh < along_ei {
+            let closer = if distance(e, f) <= distance(e, h) {
+                f
+            } else {
+                h
+            };
+            block[n] = blend(e, closer, 0.5);
+        }
+    }
+    block
+}
+
+fn scanlines(color: Rgb) -> [Rgb; 4] {
+    let dark = dim(color, SCANLINE_BRIGHTNESS);
+    [color, color, dark, dark]
+}
+
+// Each column lights one of red, green or blue at full strength
+fn crt_mask(color: Rgb) -> [Rgb; 9] {
+    let mut block = [color; 9];
+    for (i, lit) in block.iter_mut().enumerate() {
+        let (row, column) = (i / 3, i % 3);
+        *lit = dim(color, MASK_BRIGHTNESS);
+        lit[column] = color[column];
+        if row == 2 {
+            *lit = dim(*lit, SCANLINE_BRIGHTNESS);
+        }
+    }
+    block
+}
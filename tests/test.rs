@@ -18,18 +18,194 @@ macro_rules! integration_tests {
     }
 }
 
+// For ROMs that report through blargg's status protocol at $6000, see `run_blargg`
+macro_rules! blargg_tests {
+    ($($name:ident: $file:expr;)*) => {
+        $(
+            #[test]
+            fn $name() {
+                run_blargg($file);
+            }
+        )*
+    }
+}
+
+// The apu_mixer ROMs beep, play a tone on the channel under test alongside its inverse on the DMC
+// DAC, then beep again. They don't report a result, so check that the frames between the beeps
+// stay quiet relative to the beeps
+macro_rules! apu_mixer_tests {
+    ($($name:ident: $value:expr;)*) => {
+        $(
+            #[test]
+            fn $name() {
+                let (file, frames, max_ratio) = $value;
+                let rom = NESFile::new(Path::new(file).to_path_buf());
+                let bus = Bus::new(&rom);
+                let mut cpu = CPU::new(bus);
+                cpu.reset();
+                let mut levels = Vec::with_capacity(frames);
+                let mut samples = vec![];
+                for _ in 0..frames {
+                    cpu.run_until_frame();
+                    samples.clear();
+                    cpu.bus.apu.end_frame(&mut samples);
+                    levels.push(rms(&samples));
+                }
+                let (beep, residual) = mixer_residual(&levels);
+                assert!(
+                    residual < beep * max_ratio,
+                    "Residual level was {residual:.1} against beeps at {beep:.1}"
+                );
+            }
+        )*
+    }
+}
+
 mod tests {
-    use nes::core::bus::Bus;
-    use nes::core::cpu::CPU;
+    use nes::core::apu::mixer::{MixerChannel, MixerSettings};
+    use nes::core::bus::{Bus, PowerOnRam};
+    use nes::core::cpu::{AddressingMode, BusAccess, CpuBus, RamBus, Status, CPU, OPS};
+    use nes::core::mappers::MapperFactory;
+    use nes::core::nsf::NsfPlayer;
+    use nes::core::ppu::layers::RenderLayers;
+    use nes::core::ppu::palettes::{ntsc::NtscPaletteParams, rgb, Palette, PaletteSource};
+    use nes::emulator::Emulator;
+    use nes::fds_parser::{FdsImage, SIDE_SIZE};
+    use nes::frontend::speed::{EmulationSpeed, NTSC_FRAME_RATE, SPEED_STEPS};
+    use nes::frontend::wav::write_wav;
     use nes::ines_parser::NESFile;
+    use nes::nsf_parser::{ExpansionAudio, NsfFile};
+    use nes::video::ntsc::{NtscFilter, NtscPreset, NTSC_HEIGHT, NTSC_WIDTH};
+    use nes::video::{overscan::Overscan, scale::Scaler, Image};
+    use serde_json::Value;
+    use std::io::Write;
     use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    const BEEP_LEVEL: f64 = 200.;
+    // Written to $6001-$6003 once the status and text are valid
+    const BLARGG_SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+    // A minute, well past the slowest ROM
+    const BLARGG_MAX_FRAMES: usize = 3600;
+
+    /// Runs a blargg ROM until it reports a result in $6000, then fails with the text it printed
+    /// unless that's 0. The status is $80 while it runs and $81 when it wants the reset button
+    /// pressed, at least 100ms later
+    fn run_blargg(file: &str) {
+        let rom = NESFile::new(Path::new(file).to_path_buf());
+        let mut cpu = CPU::new(Bus::new(&rom));
+        cpu.reset();
+        let mut reset_at = None;
+        for frame in 0..BLARGG_MAX_FRAMES {
+            cpu.run_until_frame();
+            if (0x6001..=0x6003)
+                .map(|addr| cpu.bus.read_trace(addr))
+                .ne(BLARGG_SIGNATURE)
+            {
+                continue;
+            }
+            match cpu.bus.read_trace(0x6000) {
+                0x80 => reset_at = None,
+                0x81 => match reset_at {
+                    None => reset_at = Some(frame + 10),
+                    Some(at) if frame >= at => {
+                        cpu.soft_reset();
+                        reset_at = None;
+                    }
+                    Some(_) => {}
+                },
+                0 => return,
+                code => panic!("{file} failed with code {code}:\n{}", blargg_text(&cpu)),
+            }
+        }
+        panic!(
+            "{file} didn't finish in {BLARGG_MAX_FRAMES} frames:\n{}",
+            blargg_text(&cpu)
+        );
+    }
+
+    /// The zero terminated text at $6004
+    fn blargg_text(cpu: &CPU) -> String {
+        let text: Vec<u8> = (0x6004..0x8000)
+            .map(|addr| cpu.bus.read_trace(addr))
+            .take_while(|&c| c != 0)
+            .collect();
+        String::from_utf8_lossy(&text).into_owned()
+    }
+
+    fn rms(samples: &[i16]) -> f64 {
+        let len = samples.len().max(1) as f64;
+        let mean = samples.iter().map(|&s| f64::from(s)).sum::<f64>() / len;
+        let sum = samples
+            .iter()
+            .map(|&s| (f64::from(s) - mean).powi(2))
+            .sum::<f64>();
+        (sum / len).sqrt()
+    }
+
+    /// Returns the beep level and the loudest frame between the first two beeps
+    fn mixer_residual(levels: &[f64]) -> (f64, f64) {
+        let first_start = levels
+            .iter()
+            .position(|&l| l > BEEP_LEVEL)
+            .expect("no beep");
+        let first_end = first_start
+            + levels[first_start..]
+                .iter()
+                .position(|&l| l < BEEP_LEVEL)
+                .expect("beep never ended");
+        let second_start = first_end
+            + levels[first_end..]
+                .iter()
+                .position(|&l| l > BEEP_LEVEL)
+                .expect("no second beep");
+
+        let beep = levels[first_start..first_end]
+            .iter()
+            .copied()
+            .fold(0., f64::max);
+        // Skip the filter ringing on either side of the beeps
+        let residual = levels[first_end + 2..second_start - 2]
+            .iter()
+            .copied()
+            .fold(0., f64::max);
+        (beep, residual)
+    }
+
+    blargg_tests! {
+        // CPU TESTS -------------------------------------------------------------------------------
+        instr_test_v5: "tests/instr_test-v5/all_instrs.nes";
+        instr_test_v5_official: "tests/instr_test-v5/official_only.nes";
+        instr_misc: "tests/instr_misc/instr_misc.nes";
+        instr_timing: "tests/instr_timing/instr_timing.nes";
+        cpu_dummy_writes_oam: "tests/cpu_dummy_writes/cpu_dummy_writes_oam.nes";
+        cpu_dummy_writes_ppumem: "tests/cpu_dummy_writes/cpu_dummy_writes_ppumem.nes";
+        cpu_exec_space_ppuio: "tests/cpu_exec_space/test_cpu_exec_space_ppuio.nes";
+        cpu_exec_space_apu: "tests/cpu_exec_space/test_cpu_exec_space_apu.nes";
+        cpu_interrupts: "tests/cpu_interrupts_v2/cpu_interrupts.nes";
+        nes_instr_test_implied: "tests/nes_instr_test/rom_singles/01-implied.nes";
+        nes_instr_test_immediate: "tests/nes_instr_test/rom_singles/02-immediate.nes";
+        nes_instr_test_zero_page: "tests/nes_instr_test/rom_singles/03-zero_page.nes";
+        nes_instr_test_zp_xy: "tests/nes_instr_test/rom_singles/04-zp_xy.nes";
+        nes_instr_test_absolute: "tests/nes_instr_test/rom_singles/05-absolute.nes";
+        nes_instr_test_abs_xy: "tests/nes_instr_test/rom_singles/06-abs_xy.nes";
+        nes_instr_test_ind_x: "tests/nes_instr_test/rom_singles/07-ind_x.nes";
+        nes_instr_test_ind_y: "tests/nes_instr_test/rom_singles/08-ind_y.nes";
+        nes_instr_test_branches: "tests/nes_instr_test/rom_singles/09-branches.nes";
+        nes_instr_test_stack: "tests/nes_instr_test/rom_singles/10-stack.nes";
+        nes_instr_test_special: "tests/nes_instr_test/rom_singles/11-special.nes";
+
+        // PPU TESTS -------------------------------------------------------------------------------
+        ppu_vbl_nmi: "tests/ppu_vbl_nmi/ppu_vbl_nmi.nes";
+        ppu_read_buffer: "tests/ppu_read_buffer/test_ppu_read_buffer.nes";
+        ppu_open_bus: "tests/ppu_open_bus/ppu_open_bus.nes";
+        oam_read: "tests/oam_read/oam_read.nes";
+        oam_stress: "tests/oam_stress/oam_stress.nes";
+    }
 
     integration_tests! {
         // CPU TESTS -------------------------------------------------------------------------------
-        instr_test_v5: ("tests/instr_test-v5/all_instrs.nes", 2398, 13190525789780138270);
-        cpu_dummy_writes_oam: ("tests/cpu_dummy_writes/cpu_dummy_writes_oam.nes", 329, 18226267073703253929);
-        cpu_dummy_writes_ppumem: ("tests/cpu_dummy_writes/cpu_dummy_writes_ppumem.nes", 234, 17557076518018075713);
-        cpu_exec_space_ppuio: ("tests/cpu_exec_space/test_cpu_exec_space_ppuio.nes", 43, 7085559936242306659);
         cpu_timing_tests: ("tests/cpu_timing_test6/cpu_timing_test.nes", 612, 11550658946518422994);
 
         // PPU TESTS -------------------------------------------------------------------------------
@@ -37,9 +213,6 @@ mod tests {
         sprite_ram: ("tests/blargg_ppu_tests_2005.09.15b/sprite_ram.nes", 18, 3301376315147960416);
         vbl_clear_time: ("tests/blargg_ppu_tests_2005.09.15b/vbl_clear_time.nes", 24, 3301376315147960416);
         vram_access: ("tests/blargg_ppu_tests_2005.09.15b/vram_access.nes", 19, 3301376315147960416);
-        ppu_vbl_nmi: ("tests/ppu_vbl_nmi/ppu_vbl_nmi.nes", 1624, 3000831971158866996);
-        ppu_read_buffer: ("tests/ppu_read_buffer/test_ppu_read_buffer.nes", 1269, 10957719060148031592);
-        oam_stress: ("tests/oam_stress/oam_stress.nes", 1703, 60536158850127617);
 
         sprite_hit_basics: ("tests/sprite_hit_tests_2005.10.05/01.basics.nes", 32, 4669044134520954011);
         sprite_hit_alignment: ("tests/sprite_hit_tests_2005.10.05/02.alignment.nes", 31, 4554223117083026616);
@@ -82,12 +255,1077 @@ mod tests {
         len_reload_timing: ("tests/blargg_apu_2005.07.30/11.len_reload_timing.nes", 17, 3301376315147960416);
     }
 
-    // CPU Tests -----------------------------------------------------------------------------------
-    // let rom = File::new("tests/cpu_exec_space/test_cpu_exec_space_apu.nes"); // Fails - expected
-    // let rom = File::new("tests/cpu_interrupts_v2/cpu_interrupts.nes"); // Fails - expected
-    // let rom = File::new("tests/instr_misc/instr_misc.nes"); // Fails - expected
-    // let rom = File::new("tests/instr_timing/instr_timing.nes"); // Fails - expected
-    // let rom = File::new("tests/nestest/nestest.nes"); // Passes
+    apu_mixer_tests! {
+        apu_mixer_square: ("tests/apu_mixer/square.nes", 960, 0.1);
+        apu_mixer_triangle: ("tests/apu_mixer/triangle.nes", 600, 0.1);
+        // Noise fades in and out and can't be cancelled exactly by the DMC
+        apu_mixer_noise: ("tests/apu_mixer/noise.nes", 1140, 0.25);
+        apu_mixer_dmc: ("tests/apu_mixer/dmc.nes", 700, 0.1);
+    }
+
+    #[test]
+    fn palette_emphasis() {
+        let palette = Palette::default();
+        let white = palette.get(0x30, 0);
+        // Red emphasis dims green and blue
+        let red = palette.get(0x30, 0b001);
+        assert_eq!(red.r, white.r);
+        assert!(red.g < white.g && red.b < white.b);
+        // All three dim everything
+        let all = palette.get(0x30, 0b111);
+        assert!(all.r < white.r && all.g < white.g && all.b < white.b);
+    }
+
+    #[test]
+    fn palette_formats() {
+        let base = [0x80; 0xc0];
+        assert_eq!(Palette::from_bytes(&base).unwrap().get(0, 0).r, 0x80);
+        let full = [0x40; 0x600];
+        assert_eq!(Palette::from_bytes(&full).unwrap().get(0, 0b111).g, 0x40);
+        assert!(Palette::from_bytes(&[0; 100]).is_err());
+
+        let ntsc = NtscPaletteParams::default().generate();
+        assert_eq!(ntsc.get(0x0f, 0).r, 0);
+        let white = ntsc.get(0x20, 0);
+        assert!(white.r > 0xf0 && white.g > 0xf0 && white.b > 0xf0);
+    }
+
+    #[test]
+    fn rgb_palettes() {
+        let rp2c03 = rgb::rp2c03();
+        // RP2C04-0001's first entry is the 2C03's $35, and its $3F is the 2C03's $3A
+        let rp2c04 = rgb::rp2c04(1);
+        assert_eq!(rp2c04.get(0x00, 0), rp2c03.get(0x35, 0));
+        assert_eq!(rp2c04.get(0x3f, 0), rp2c03.get(0x3a, 0));
+        assert_eq!(rgb::rp2c04(4).get(0x00, 0), rp2c03.get(0x18, 0));
+
+        // Every 2C04 shows each of the 2C03's colours, only in another order
+        let colors = |palette: &Palette| {
+            let mut colors = palette.system_palette[..0x40]
+                .iter()
+                .map(|c| (c.r, c.g, c.b))
+                .collect::<Vec<_>>();
+            colors.sort_unstable();
+            colors.dedup();
+            colors
+        };
+        for version in 1..=4 {
+            assert_eq!(
+                colors(&rgb::rp2c04(version)),
+                colors(&rp2c03),
+                "2C04-000{version}"
+            );
+        }
+        assert_ne!(rgb::rp2c04(2).system_palette, rgb::rp2c04(3).system_palette);
+        assert_eq!(
+            PaletteSource::Rp2c04(2).load().unwrap().get(0x01, 0),
+            rp2c03.get(0x27, 0)
+        );
+    }
+
+    #[test]
+    fn frame_palette_indices() {
+        let rom = NESFile::new(Path::new("tests/window5/colorwin_ntsc.nes").to_path_buf());
+        let mut cpu = CPU::new(Bus::new(&rom));
+        cpu.reset();
+        for _ in 0..60 {
+            cpu.run_until_frame();
+        }
+        let mut frame = cpu.bus.ppu.curr_frame;
+        let (hash, index_hash) = (frame.get_hash(), frame.get_index_hash());
+
+        let mut colors = Palette::default().system_palette;
+        colors.rotate_left(1);
+        frame.repalettize(&Palette {
+            system_palette: colors,
+        });
+        assert_ne!(frame.get_hash(), hash);
+        assert_eq!(frame.get_index_hash(), index_hash);
+
+        frame.repalettize(&Palette::default());
+        assert_eq!(frame.get_hash(), hash);
+    }
+
+    #[test]
+    fn ntsc_filter_presets() {
+        let rom = NESFile::new(Path::new("tests/window5/colorwin_ntsc.nes").to_path_buf());
+        let mut cpu = CPU::new(Bus::new(&rom));
+        cpu.reset();
+        for _ in 0..60 {
+            cpu.run_until_frame();
+        }
+        let frame = &cpu.bus.ppu.curr_frame;
+        let palette = Palette::default();
+
+        let mut filter = NtscFilter::new(NtscPreset::Composite);
+        let first = filter.apply(frame, &palette, 0).to_vec();
+        assert_eq!(first.len(), NTSC_WIDTH * NTSC_HEIGHT * 3);
+        // Dot crawl repeats every three frames
+        assert_ne!(filter.apply(frame, &palette, 1), first);
+        assert_eq!(filter.apply(frame, &palette, 3), first);
+
+        filter.set_preset(NtscPreset::SVideo);
+        let svideo = filter.apply(frame, &palette, 0).to_vec();
+        assert_ne!(svideo, first);
+
+        filter.set_preset(NtscPreset::Monochrome);
+        let mono = filter.apply(frame, &palette, 0);
+        assert!(mono.chunks_exact(3).all(|p| p[0] == p[1] && p[1] == p[2]));
+
+        // RGB is the palette colours stretched out, the left edge is the frame's first pixel
+        filter.set_preset(NtscPreset::Rgb);
+        let rgb = filter.apply(frame, &palette, 0);
+        let color = palette.get_indexed(frame.indices[0]);
+        assert_eq!(rgb[..3], [color.r, color.g, color.b]);
+    }
+
+    #[test]
+    fn video_scalers() {
+        // A single white pixel on a diagonal black/white edge
+        let mut image = Image::new(4, 4);
+        for (x, y) in [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (0, 2)] {
+            image.set(x, y, [0xff; 3]);
+        }
+        for scaler in Scaler::ALL {
+            let scaled = scaler.apply(&image);
+            assert_eq!(scaled.width, 4 * scaler.factor());
+            assert_eq!(scaled.height, 4 * scaler.factor());
+        }
+        assert!(Scaler::None.apply(&image) == image);
+
+        // Scale2x fills in the staircase on the black side of the edge
+        let scaled = Scaler::Scale2x.apply(&image);
+        assert_eq!(scaled.get(4, 2), [0xff; 3]);
+        assert_eq!(scaled.get(5, 3), [0; 3]);
+        // xBR blends the corners the edge cuts across, on both sides of it, and leaves the pixels
+        // away from it alone
+        let scaled = Scaler::Xbr.apply(&image);
+        assert_eq!(scaled.get(3, 3), [0x80; 3]);
+        assert_eq!(scaled.get(4, 2), [0x80; 3]);
+        assert_eq!(scaled.get(2, 2), [0xff; 3]);
+        assert_eq!(scaled.get(4, 4), [0; 3]);
+
+        let scaled = Scaler::Scanlines.apply(&image);
+        assert_eq!(scaled.get(0, 0), [0xff; 3]);
+        assert!(scaled.get(0, 1)[0] < 0xff);
+    }
+
+    #[test]
+    fn overscan_crop() {
+        let mut image = Image::new(256, 240);
+        image.set(4, 8, [1, 2, 3]);
+        let overscan = Overscan {
+            top: 8,
+            bottom: 16,
+            left: 4,
+            right: 2,
+        };
+        let cropped = overscan.crop(&image);
+        assert_eq!((cropped.width, cropped.height), overscan.visible_size());
+        assert_eq!((cropped.width, cropped.height), (250, 216));
+        assert_eq!(cropped.get(0, 0), [1, 2, 3]);
+
+        // Edges are in NES pixels, so a stretched picture loses the same share
+        let wide = overscan.crop(&Image::new(NTSC_WIDTH, 480));
+        assert_eq!((wide.width, wide.height), (NTSC_WIDTH - 14, 432));
+
+        assert_eq!(Overscan::default().crop(&image), image);
+    }
+
+    #[test]
+    fn sprite_limit_removal() {
+        let rom = NESFile::new(Path::new("tests/spritecans-2011/spritecans.nes").to_path_buf());
+        let mut limited = CPU::new(Bus::new(&rom));
+        let mut unlimited = CPU::new(Bus::new(&rom));
+        unlimited.bus.ppu.set_remove_sprite_limit(true);
+        limited.reset();
+        unlimited.reset();
+
+        // The cans pile up past 8 a line after about 1000 frames
+        let mut differed = false;
+        for _ in 0..1060 {
+            limited.run_until_frame();
+            unlimited.run_until_frame();
+            differed |= limited.get_frame_hash() != unlimited.get_frame_hash();
+            // Only the picture changes, the CPU runs exactly the same
+            assert_eq!(limited.cycle_count, unlimited.cycle_count);
+            assert_eq!(limited.pc, unlimited.pc);
+        }
+        assert!(
+            (0..0x800).all(|addr| limited.bus.read_trace(addr) == unlimited.bus.read_trace(addr))
+        );
+        assert!(differed);
+    }
+
+    #[test]
+    fn render_layers() {
+        let rom = NESFile::new(
+            Path::new("tests/sprite_hit_tests_2005.10.05/10.timing_order.nes").to_path_buf(),
+        );
+        let mut shown = CPU::new(Bus::new(&rom));
+        let mut hidden = CPU::new(Bus::new(&rom));
+        hidden.bus.ppu.set_render_layers(RenderLayers {
+            background: false,
+            sprites: false,
+            priority_sprites_only: false,
+        });
+        shown.reset();
+        hidden.reset();
+        for _ in 0..66 {
+            shown.run_until_frame();
+            hidden.run_until_frame();
+        }
+        // The ROM times sprite 0 hits, so any change to them would show up in the CPU's state
+        assert_eq!(shown.cycle_count, hidden.cycle_count);
+        assert!((0..0x800).all(|addr| shown.bus.read_trace(addr) == hidden.bus.read_trace(addr)));
+
+        // Nothing but the backdrop is left
+        let indices = hidden.bus.ppu.curr_frame.indices;
+        assert!(indices.iter().all(|&index| index == indices[0]));
+        assert_ne!(shown.get_frame_hash(), hidden.get_frame_hash());
+    }
+
+    #[test]
+    fn reset_and_power_cycle() {
+        let rom = NESFile::new(Path::new("tests/spritecans-2011/spritecans.nes").to_path_buf());
+        let mut cpu = CPU::new(Bus::new(&rom));
+        cpu.reset();
+        for _ in 0..30 {
+            cpu.run_until_frame();
+        }
+
+        let ram: Vec<u8> = (0..0x800).map(|addr| cpu.bus.read_trace(addr)).collect();
+        let sp = cpu.sp;
+        cpu.soft_reset();
+        assert_eq!(cpu.pc, cpu.bus.read_16_trace(0xFFFC));
+        assert_eq!(cpu.sp, sp.wrapping_sub(3));
+        assert!(cpu.status.contains(Status::INTERRUPT_DISABLE));
+        assert!((0..0x800).all(|addr| cpu.bus.read_trace(addr) == ram[addr as usize]));
+
+        // PPU register writes are dropped until the end of the next vblank
+        assert!(cpu.bus.ppu.warming_up());
+        cpu.run_until_frame();
+        cpu.run_until_frame();
+        assert!(!cpu.bus.ppu.warming_up());
+
+        for ram in PowerOnRam::ALL {
+            cpu.bus.power_cycle(&rom, ram);
+            let expected = match ram {
+                PowerOnRam::Zeros => Some([0x00, 0x00]),
+                PowerOnRam::Ones => Some([0xff, 0xff]),
+                PowerOnRam::Pattern => Some([0x00, 0xff]),
+                PowerOnRam::Random => None,
+            };
+            if let Some([low, high]) = expected {
+                assert!((0..0x800).all(|addr| {
+                    cpu.bus.read_trace(addr) == if addr & 4 == 0 { low } else { high }
+                }));
+            }
+        }
+
+        // The PPU warms up after a power cycle too, so turning on NMIs does nothing until the end of
+        // the first vblank
+        cpu.bus.power_cycle(&rom, PowerOnRam::Zeros);
+        assert!(cpu.bus.ppu.warming_up());
+        cpu.bus.write(0x2000, 0x80);
+        let bus = &mut cpu.bus;
+        bus.ppu.run_to(242 * 341 * 4, &mut *bus.mapper);
+        assert!(!bus.ppu.nmi_generated);
+        bus.ppu.run_to(263 * 341 * 4, &mut *bus.mapper);
+        assert!(!bus.ppu.warming_up());
+        cpu.bus.write(0x2000, 0x80);
+        let bus = &mut cpu.bus;
+        bus.ppu.run_to((262 + 242) * 341 * 4, &mut *bus.mapper);
+        assert!(bus.ppu.nmi_generated);
+
+        // Still boots and runs the same as from a fresh start
+        let mut fresh = CPU::new(Bus::new(&rom));
+        fresh.reset();
+        cpu.bus.power_cycle(&rom, PowerOnRam::Zeros);
+        cpu.reset();
+        for _ in 0..30 {
+            cpu.run_until_frame();
+            fresh.run_until_frame();
+        }
+        assert_eq!(cpu.get_frame_hash(), fresh.get_frame_hash());
+        assert_eq!(cpu.cycle_count, fresh.cycle_count);
+    }
+
+    #[test]
+    fn emulator_save_state() {
+        // Square wave for the APU, the instruction tests run on MMC1
+        for file in [
+            "tests/apu_mixer/square.nes",
+            "tests/instr_test-v5/all_instrs.nes",
+        ] {
+            let mut emulator = Emulator::new(NESFile::new(Path::new(file).to_path_buf()));
+            let mut audio = vec![];
+            for _ in 0..200 {
+                emulator.step_frame();
+            }
+            emulator.drain_audio(&mut audio);
+            assert!(!audio.is_empty());
+
+            let state = emulator.save_state();
+            let run = |emulator: &mut Emulator| {
+                let mut hashes = vec![];
+                let mut audio = vec![];
+                for _ in 0..100 {
+                    emulator.step_frame();
+                    hashes.push(emulator.frame().get_hash());
+                }
+                emulator.drain_audio(&mut audio);
+                (hashes, audio, emulator.cpu().cycle_count)
+            };
+            let first = run(&mut emulator);
+            emulator.load_state(&state).unwrap();
+            assert_eq!(run(&mut emulator), first);
+        }
+
+        // A state for another ROM, or a damaged one, is refused and changes nothing
+        let mut emulator = Emulator::new(NESFile::new(
+            Path::new("tests/apu_mixer/square.nes").to_path_buf(),
+        ));
+        let other = Emulator::new(NESFile::new(
+            Path::new("tests/instr_test-v5/all_instrs.nes").to_path_buf(),
+        ));
+        emulator.step_frame();
+        let state = emulator.save_state();
+        assert!(emulator.load_state(&other.save_state()).is_err());
+        assert!(emulator.load_state(&state[..state.len() - 1]).is_err());
+        assert_eq!(emulator.save_state(), state);
+    }
+
+    #[test]
+    fn mixer_settings() {
+        // The ROM beeps and plays a square wave with its inverse on the DMC, so the tone is only
+        // heard once the DMC is muted
+        let run = |mixer: MixerSettings| {
+            let mut emulator = Emulator::new(NESFile::new(
+                Path::new("tests/apu_mixer/square.nes").to_path_buf(),
+            ));
+            emulator.cpu_mut().bus.apu.set_mixer(mixer);
+            // Turning stereo on starts the right side from the left, skip what's already buffered
+            emulator.step_frame();
+            let mut audio = vec![];
+            emulator.drain_audio(&mut audio);
+            audio.clear();
+            for _ in 0..960 {
+                emulator.step_frame();
+                emulator.drain_audio(&mut audio);
+            }
+            (emulator.audio_channels(), audio)
+        };
+
+        let (channels, cancelled) = run(MixerSettings::default());
+        assert_eq!(channels, 1);
+        let mut mixer = MixerSettings::default();
+        mixer.channel_mut(MixerChannel::DMC).muted = true;
+        let (_, square) = run(mixer);
+        assert!(rms(&square) > 3. * rms(&cancelled));
+
+        // Panned hard left, the right side of each frame is silent
+        mixer.stereo = true;
+        mixer.channels.iter_mut().for_each(|mix| mix.pan = -1.);
+        let (channels, stereo) = run(mixer);
+        assert_eq!(channels, 2);
+        assert_eq!(stereo.len() % 2, 0);
+        let left: Vec<i16> = stereo.iter().step_by(2).copied().collect();
+        assert!((rms(&left) - rms(&square)).abs() < rms(&square) * 0.05);
+        assert!(stereo.iter().skip(1).step_by(2).all(|&s| s == 0));
+
+        // Nothing is left with the first pulse at zero volume and everything else muted
+        mixer.stereo = false;
+        for channel in MixerChannel::ALL {
+            mixer.channel_mut(channel).muted = channel != MixerChannel::Pulse1;
+        }
+        mixer.channel_mut(MixerChannel::Pulse1).volume = 0.;
+        let (_, silent) = run(mixer);
+        assert!(silent.iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn cpu_jam() {
+        // NROM with a JAM opcode at the reset vector
+        let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0x02; 0x4000];
+        prg[0x3ffc..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80]);
+        rom.extend(prg);
+        rom.extend([0; 0x2000]);
+
+        let mut emulator = Emulator::new(NESFile::from_bytes(&rom));
+        emulator.step_frame();
+        assert_eq!(emulator.jammed(), Some(0x8000));
+        // The clock keeps running and frames keep coming
+        let cycles = emulator.cpu().cycle_count;
+        emulator.step_frame();
+        assert!(emulator.cpu().cycle_count - cycles > 29000);
+
+        emulator.reset();
+        assert_eq!(emulator.jammed(), None);
+        assert_eq!(emulator.cpu().pc, 0x8000);
+
+        // A JMP to a JAM in the last byte of the address space, where the PC wraps to 0
+        rom[0x10..0x13].copy_from_slice(&[0x4c, 0xff, 0xff]);
+        rom[0x400f] = 0x02;
+        let mut emulator = Emulator::new(NESFile::from_bytes(&rom));
+        emulator.step_frame();
+        assert_eq!(emulator.jammed(), Some(0xffff));
+    }
+
+    #[test]
+    fn prg_ram_size() {
+        // NROM with a single 16KB bank and no CHR. Byte 7 marks NES 2.0, byte 10 is its RAM size
+        let rom = |nes2: u8, prg_ram: u8| {
+            let mut rom = vec![
+                0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, nes2, 0, 0, prg_ram, 0, 0, 0, 0, 0,
+            ];
+            rom.extend([0; 0x4000]);
+            NESFile::from_bytes(&rom)
+        };
+        let ines = rom(0, 0);
+        let nes2_8k = rom(0x08, 0x07);
+        let nes2_none = rom(0x08, 0);
+
+        // iNES 1.0 has no reliable way to say, so it always gets 8KB
+        assert_eq!(ines.get_prg_ram_size(), 0x2000);
+        assert_eq!(nes2_8k.get_prg_ram_size(), 0x2000);
+        assert_eq!(nes2_none.get_prg_ram_size(), 0);
+
+        let mut mapper = MapperFactory::from_file(&ines);
+        mapper.write(0x6000, 0x5a);
+        mapper.write(0x7fff, 0xa5);
+        assert_eq!(mapper.read(0x6000), 0x5a);
+        assert_eq!(mapper.read(0x7fff), 0xa5);
+    }
+
+    // Keeps the CPU's trace where the test can get at it
+    #[derive(Clone, Default)]
+    struct SharedSink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn nestest() {
+        // Nintendulator's log of nestest's automated run from $C000
+        let golden = std::fs::read_to_string("tests/nestest/test_pat.txt").unwrap();
+        let golden: Vec<&str> = golden.lines().collect();
+        let last_cycle: u64 = golden[golden.len() - 1]
+            .rsplit("CYC:")
+            .next()
+            .and_then(|cyc| cyc.parse().ok())
+            .unwrap();
+
+        let rom = NESFile::new(Path::new("tests/nestest/nestest.nes").to_path_buf());
+        let mut cpu = CPU::new(Bus::new(&rom));
+        // Nintendulator starts the PPU with CPU cycle 0, 5 dots after this emulator does
+        cpu.bus.ppu.set_master_clock(20);
+        cpu.reset();
+        cpu.pc = 0xc000;
+        let sink = SharedSink::default();
+        cpu.set_sink(Box::new(sink.clone()));
+        cpu.enable_logging();
+        // Stops after the instruction the log ends on
+        cpu.run_frame(last_cycle + 1);
+
+        let log = String::from_utf8(sink.0.lock().unwrap().clone()).unwrap();
+        let log: Vec<&str> = log.lines().collect();
+        if let Some(line) = (0..golden.len()).find(|&i| log.get(i) != Some(&golden[i])) {
+            panic!(
+                "Trace diverges from the log at line {}:\n{}\nexpected: {}\nactual:   {}",
+                line + 1,
+                golden[line.saturating_sub(5)..line].join("\n"),
+                golden[line],
+                log.get(line).unwrap_or(&"nothing"),
+            );
+        }
+        assert_eq!(log.len(), golden.len());
+        // nestest's own error codes for the official and unofficial opcodes
+        assert_eq!((cpu.bus.read_trace(0x02), cpu.bus.read_trace(0x03)), (0, 0));
+    }
+
+    // Tom Harte's SingleStepTests for the 2A03, one JSON file of 10,000 cases per opcode, from
+    // https://github.com/SingleStepTests/65x02 under nes6502/v1. They're too big to keep in the
+    // repo, so `single_step_tests` is ignored until they've been copied here
+    const SINGLE_STEP_DIR: &str = "tests/65x02/nes6502/v1";
+    // A few cases in the same format for the instructions with unusual bus activity: the stack
+    // ones, branches and SHX
+    const SINGLE_STEP_SUBSET_DIR: &str = "tests/single_step";
+    // The JAMs, which the tests run through cycles of a halted CPU that never end here
+    const SINGLE_STEP_SKIP: [u8; 12] = [
+        0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2,
+    ];
+
+    fn json_u16(val: &Value) -> u16 {
+        val.as_u64().unwrap() as u16
+    }
+
+    fn json_u8(val: &Value) -> u8 {
+        val.as_u64().unwrap() as u8
+    }
+
+    /// Runs one case, returning what differs from the expected state
+    fn run_single_step(case: &Value) -> Result<(), String> {
+        let (initial, expected) = (&case["initial"], &case["final"]);
+        let mut bus = RamBus::new();
+        bus.enable_logging();
+        for entry in initial["ram"].as_array().unwrap() {
+            bus.ram[json_u16(&entry[0]) as usize] = json_u8(&entry[1]);
+        }
+        let mut cpu = CPU::new(bus);
+        cpu.pc = json_u16(&initial["pc"]);
+        cpu.sp = json_u8(&initial["s"]);
+        cpu.acc = json_u8(&initial["a"]);
+        cpu.x = json_u8(&initial["x"]);
+        cpu.y = json_u8(&initial["y"]);
+        cpu.status = Status::from_bits_truncate(json_u8(&initial["p"]));
+
+        cpu.step();
+
+        // B and bit 5 only exist on the stack, so P's compared without them
+        let flags = !(Status::BREAK.bits() | Status::BREAK2.bits());
+        let registers = |pc, s, a, x, y, p: u8| {
+            format!("{pc:04x} {s:02x} {a:02x} {x:02x} {y:02x} {:02x}", p & flags)
+        };
+        let actual = registers(cpu.pc, cpu.sp, cpu.acc, cpu.x, cpu.y, cpu.status.bits());
+        let wanted = registers(
+            json_u16(&expected["pc"]),
+            json_u8(&expected["s"]),
+            json_u8(&expected["a"]),
+            json_u8(&expected["x"]),
+            json_u8(&expected["y"]),
+            json_u8(&expected["p"]),
+        );
+        if actual != wanted {
+            return Err(format!("PC S A X Y P were {actual}, expected {wanted}"));
+        }
+
+        for entry in expected["ram"].as_array().unwrap() {
+            let (addr, val) = (json_u16(&entry[0]), json_u8(&entry[1]));
+            let actual = cpu.bus.ram[addr as usize];
+            if actual != val {
+                return Err(format!("${addr:04x} was {actual:02x}, expected {val:02x}"));
+            }
+        }
+
+        let cycles: Vec<BusAccess> = case["cycles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|cycle| {
+                let (addr, val) = (json_u16(&cycle[0]), json_u8(&cycle[1]));
+                match cycle[2].as_str().unwrap() {
+                    "read" => BusAccess::Read(addr, val),
+                    _ => BusAccess::Write(addr, val),
+                }
+            })
+            .collect();
+        if cpu.bus.log != cycles {
+            return Err(format!(
+                "bus activity was {:?}, expected {cycles:?}",
+                cpu.bus.log
+            ));
+        }
+        Ok(())
+    }
+
+    /// Runs every file in `dir`, returning the first failing case of each opcode
+    fn run_single_step_dir(dir: &str) -> Vec<String> {
+        let mut files: Vec<_> = std::fs::read_dir(dir)
+            .unwrap_or_else(|_| panic!("the SingleStepTests aren't in {dir}"))
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        files.sort();
+
+        let mut failures = vec![];
+        for path in files {
+            let opcode = path.file_stem().and_then(|stem| stem.to_str());
+            let opcode = u8::from_str_radix(opcode.unwrap(), 16).unwrap();
+            if SINGLE_STEP_SKIP.contains(&opcode) {
+                continue;
+            }
+            let cases: Value =
+                serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            for case in cases.as_array().unwrap() {
+                if let Err(msg) = run_single_step(case) {
+                    failures.push(format!(
+                        "{opcode:02x} \"{}\": {msg}",
+                        case["name"].as_str().unwrap()
+                    ));
+                    break;
+                }
+            }
+        }
+        failures
+    }
+
+    #[test]
+    fn single_step_subset() {
+        let failures = run_single_step_dir(SINGLE_STEP_SUBSET_DIR);
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    #[ignore = "needs the SingleStepTests copied into tests/65x02/nes6502/v1"]
+    fn single_step_tests() {
+        let failures = run_single_step_dir(SINGLE_STEP_DIR);
+        assert!(
+            failures.is_empty(),
+            "{} opcodes failed:\n{}",
+            failures.len(),
+            failures.join("\n")
+        );
+    }
+
+    #[test]
+    fn op_table() {
+        for (i, op) in OPS.iter().enumerate() {
+            assert_eq!(usize::from(op.hex), i, "${:02x} is out of order", op.hex);
+        }
+
+        // Nothing crosses a page here, so every instruction takes its listed cycles, plus one for
+        // a taken branch. The CPU touches the bus on every cycle
+        for op in OPS.iter().filter(|op| op.name != "*JAM") {
+            let mut bus = RamBus::new();
+            bus.load(0x0200, &[op.hex, 0x10, 0x00]);
+            bus.enable_logging();
+            let mut cpu = CPU::new(bus);
+            cpu.pc = 0x0200;
+            cpu.step();
+            let taken = matches!(op.addressing_mode, AddressingMode::Relative) && cpu.pc == 0x0212;
+            assert_eq!(
+                cpu.bus.log.len(),
+                usize::from(op.cycles) + usize::from(taken),
+                "${:02x} {}",
+                op.hex,
+                op.name
+            );
+        }
+    }
+
+    #[test]
+    fn cpu_on_ram_bus() {
+        let mut bus = RamBus::new();
+        // Adds 5 down to 1 into A through a subroutine, stores it in $10 then spins with IRQs on
+        bus.load(
+            0x8000,
+            &[
+                0xa2, 0x05, 0xa9, 0x00, 0x20, 0x00, 0x84, 0xca, 0xd0, 0xfa, 0x85, 0x10, 0x58, 0x4c,
+                0x0d, 0x80,
+            ],
+        );
+        bus.load(0x8400, &[0x86, 0x11, 0x18, 0x65, 0x11, 0x60]);
+        // The IRQ handler counts in $12
+        bus.load(0x9000, &[0xe6, 0x12, 0x40]);
+        bus.load(0xfffc, &[0x00, 0x80, 0x00, 0x90]);
+
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        assert_eq!(cpu.pc, 0x8000);
+        while cpu.pc != 0x800d {
+            cpu.step();
+        }
+        assert_eq!(cpu.bus.ram[0x10], 15);
+
+        cpu.bus.enable_logging();
+        cpu.step();
+        assert_eq!(
+            cpu.bus.log,
+            [
+                BusAccess::Read(0x800d, 0x4c),
+                BusAccess::Read(0x800e, 0x0d),
+                BusAccess::Read(0x800f, 0x80),
+            ]
+        );
+
+        // Taken straight after the JMP it shows up during
+        cpu.bus.irq = true;
+        cpu.step();
+        assert_eq!(cpu.pc, 0x9000);
+        // Pushed with B clear
+        assert_eq!(cpu.bus.ram[0x100 + cpu.sp as usize + 1] & 0x10, 0);
+        cpu.bus.irq = false;
+        (0..2).for_each(|_| cpu.step());
+        assert_eq!((cpu.pc, cpu.bus.ram[0x12]), (0x800d, 1));
+    }
+
+    // Three 4KB banks. INIT stores the song in $00, reads $9000 before and after switching it to
+    // bank 2 into $01 and $02 and starts a tone on pulse 1. PLAY at $8040 counts calls in $03
+    fn nsf_data() -> Vec<u8> {
+        let mut data = vec![0; 0x3000];
+        data[..0x22].copy_from_slice(&[
+            0x85, 0x00, 0xad, 0x00, 0x90, 0x85, 0x01, 0xa9, 0x02, 0x8d, 0xf9, 0x5f, 0xad, 0x00,
+            0x90, 0x85, 0x02, 0xa9, 0xbf, 0x8d, 0x00, 0x40, 0xa9, 0xfd, 0x8d, 0x02, 0x40, 0xa9,
+            0x00, 0x8d, 0x03, 0x40, 0x60, 0x00,
+        ]);
+        data[0x40..0x43].copy_from_slice(&[0xe6, 0x03, 0x60]);
+        data[0x1000] = 0x11;
+        data[0x2000] = 0x22;
+        data
+    }
+
+    fn nsf_file(play_speed: u16) -> Vec<u8> {
+        let mut file = vec![0; 0x80];
+        file[..5].copy_from_slice(b"NESM\x1a");
+        file[0x05] = 1;
+        file[0x06] = 3;
+        file[0x07] = 2;
+        file[0x08..0x0e].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x40, 0x80]);
+        file[0x0e..0x12].copy_from_slice(b"Test");
+        file[0x6e..0x70].copy_from_slice(&play_speed.to_le_bytes());
+        file[0x70..0x78].copy_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        file[0x7b] = ExpansionAudio::VRC6.bits();
+        file.extend(nsf_data());
+        file
+    }
+
+    #[test]
+    fn nsf_player() {
+        let file = NsfFile::from_bytes(&nsf_file(16639)).unwrap();
+        assert_eq!((file.name.as_str(), file.total_songs), ("Test", 3));
+        assert_eq!(file.starting_song, 1);
+        assert_eq!(file.expansion.names(), ["VRC6"]);
+
+        let mut player = NsfPlayer::new(file);
+        let mut samples = vec![];
+        (0..60).for_each(|_| player.step_frame());
+        player.drain_audio(&mut samples);
+        let ram = &player.cpu().bus.ram;
+        assert_eq!(ram[..3], [1, 0x11, 0x22]);
+        // 60.1 Hz for a second
+        assert!((59..=61).contains(&ram[3]), "{} PLAY calls", ram[3]);
+        assert!(rms(&samples) > 100., "pulse 1 is silent");
+
+        player.play_song(2);
+        (0..30).for_each(|_| player.step_frame());
+        let ram = &player.cpu().bus.ram;
+        assert_eq!(ram[..3], [2, 0x11, 0x22]);
+        assert!((29..=31).contains(&ram[3]), "{} PLAY calls", ram[3]);
+
+        let mut player = NsfPlayer::new(NsfFile::from_bytes(&nsf_file(8333)).unwrap());
+        (0..60).for_each(|_| player.step_frame());
+        let calls = player.cpu().bus.ram[3];
+        assert!((119..=121).contains(&calls), "{calls} PLAY calls at 120 Hz");
+
+        // Plays for the length, then fades to nothing
+        player.set_default_length(Duration::from_secs(1), Duration::from_millis(500));
+        player.set_sample_rate(48000.);
+        let samples = player.render_song(0);
+        assert!(
+            (71_900..=72_900).contains(&samples.len()),
+            "{}",
+            samples.len()
+        );
+        assert!(rms(&samples[..24000]) > 100.);
+        assert!(rms(&samples[samples.len() - 500..]) < 10.);
+
+        let mut wav = vec![];
+        write_wav(&mut wav, &samples[..100], 1, 48000).unwrap();
+        assert_eq!(wav.len(), 44 + 200);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav[4..8], (36u32 + 200).to_le_bytes());
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(wav[24..28], 48000u32.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav[44..46], samples[0].to_le_bytes());
+
+        // A bankswitched rip needs something in its banks
+        let mut empty = nsf_file(16639);
+        empty.truncate(0x80);
+        assert!(NsfFile::from_bytes(&empty).is_err());
+    }
+
+    #[test]
+    fn nsf_pal() {
+        // INIT leaves pulse 1 on a period of 253: 440 Hz on an NTSC clock, 409 Hz on PAL
+        let play = |pal: bool| {
+            let mut file = nsf_file(16639);
+            if pal {
+                file[0x78..0x7a].copy_from_slice(&19997u16.to_le_bytes());
+                file[0x7a] = 1;
+            }
+            let mut player = NsfPlayer::new(NsfFile::from_bytes(&file).unwrap());
+            player.set_sample_rate(48000.);
+            // A second of frames
+            (0..player.frame_rate().round() as usize).for_each(|_| player.step_frame());
+            let mut samples = vec![];
+            player.drain_audio(&mut samples);
+            let rises = samples.windows(2).filter(|w| w[0] < 0 && w[1] >= 0).count();
+            (
+                rises,
+                player.cpu().bus.ram[3],
+                player.elapsed(),
+                samples.len(),
+            )
+        };
+
+        let (rises, calls, elapsed, len) = play(false);
+        assert!((438..=442).contains(&rises), "{rises} Hz");
+        assert!((59..=61).contains(&calls), "{calls} PLAY calls");
+        assert!(elapsed.abs_diff(Duration::from_secs(1)) < Duration::from_millis(20));
+        assert!((47_500..=48_500).contains(&len), "{len} samples");
+
+        let (rises, calls, elapsed, len) = play(true);
+        assert!((407..=411).contains(&rises), "{rises} Hz");
+        assert!((49..=51).contains(&calls), "{calls} PLAY calls");
+        assert!(elapsed.abs_diff(Duration::from_secs(1)) < Duration::from_millis(20));
+        assert!((47_500..=48_500).contains(&len), "{len} samples");
+    }
+
+    #[test]
+    fn nsfe_metadata() {
+        fn chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+            out.extend((data.len() as u32).to_le_bytes());
+            out.extend(id);
+            out.extend(data);
+        }
+        let times = [1000i32, -1, 2500]
+            .iter()
+            .flat_map(|ms| ms.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut file = b"NSFE".to_vec();
+        chunk(
+            &mut file,
+            b"INFO",
+            &[0x00, 0x80, 0x00, 0x80, 0x40, 0x80, 0, 0, 3, 0],
+        );
+        chunk(&mut file, b"BANK", &[0, 1]);
+        chunk(&mut file, b"DATA", &nsf_data());
+        chunk(&mut file, b"tlbl", b"One\0\0Three\0");
+        chunk(&mut file, b"time", &times);
+        chunk(&mut file, b"plst", &[2, 0]);
+        chunk(&mut file, b"auth", b"Game\0Artist\0Copyright\0Ripper\0");
+        chunk(&mut file, b"xtra", b"skipped");
+        let mut nend = file.clone();
+        chunk(&mut nend, b"NEND", &[]);
+
+        let nsf = NsfFile::from_bytes(&nend).unwrap();
+        assert_eq!(
+            (
+                nsf.name.as_str(),
+                nsf.artist.as_str(),
+                nsf.ripper.as_deref()
+            ),
+            ("Game", "Artist", Some("Ripper"))
+        );
+        assert_eq!(nsf.banks, Some([0, 1, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(nsf.playlist, [2, 0]);
+        assert_eq!(
+            (0..3).map(|song| nsf.track_title(song)).collect::<Vec<_>>(),
+            ["One", "Track 2", "Three"]
+        );
+        assert_eq!(nsf.tracks[0].length, Some(Duration::from_secs(1)));
+        assert_eq!(nsf.tracks[1].length, None);
+
+        let mut player = NsfPlayer::new(nsf);
+        assert_eq!(player.track_length(2), Duration::from_millis(2500));
+        player.next_song();
+        assert_eq!(player.song(), 2);
+        player.next_song();
+        assert_eq!(player.song(), 0);
+        player.step_frame();
+        assert_eq!(player.cpu().bus.ram[..3], [0, 0x11, 0x22]);
+
+        // Chunks with a capital first letter have to be understood to play the file
+        chunk(&mut file, b"VRC7", &[0]);
+        assert!(NsfFile::from_bytes(&file).is_err());
+        assert!(NsfFile::from_bytes(b"NESM").is_err());
+    }
+
+    #[test]
+    fn nsf_fds_audio() {
+        let mut file = nsf_file(16639);
+        // Loads at $6000, into the Disk System's RAM
+        file[0x08..0x0e].copy_from_slice(&[0x00, 0x60, 0x00, 0x60, 0x30, 0x60]);
+        file[0x70..0x78].fill(0);
+        file[0x7b] = ExpansionAudio::FDS.bits();
+        file.truncate(0x80);
+        let mut data = vec![0; 0x40];
+        data[..0x28].copy_from_slice(&[
+            0xa9, 0x80, 0x8d, 0x89, 0x40, 0xa2, 0x3f, 0x8a, 0x9d, 0x40, 0x40, 0xca, 0x10, 0xf9,
+            0xa9, 0x00, 0x8d, 0x89, 0x40, 0xa9, 0xa0, 0x8d, 0x80, 0x40, 0xa9, 0x00, 0x8d, 0x82,
+            0x40, 0xa9, 0x04, 0x8d, 0x83, 0x40, 0xa9, 0x42, 0x8d, 0x00, 0x70, 0x60,
+        ]);
+        data[0x30] = 0x60;
+        file.extend(data);
+
+        let nsf = NsfFile::from_bytes(&file).unwrap();
+        assert_eq!(nsf.load_addr, 0x6000);
+        let mut player = NsfPlayer::new(nsf);
+        let mut samples = vec![];
+        (0..30).for_each(|_| player.step_frame());
+        player.drain_audio(&mut samples);
+        assert_eq!(player.cpu().bus.peek(0x7000), 0x42);
+        assert_eq!(player.cpu().bus.peek(0x407f), 0x3f);
+        assert!(rms(&samples) > 100., "the wavetable is silent");
+
+        // Only Disk System rips have RAM to load into below $8000
+        file[0x7b] = 0;
+        assert!(NsfFile::from_bytes(&file).is_err());
+    }
+
+    // One side of a disk with a single 4 byte file on it, its bytes all `tag`
+    fn fds_side(tag: u8) -> Vec<u8> {
+        let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+        side.resize(56, tag);
+        side.extend([2, 1]);
+        side.extend([3, 0, 0]);
+        side.extend(b"FILENAME");
+        side.extend([0x00, 0x60, 4, 0, 0]);
+        side.extend([4, tag, tag, tag, tag]);
+        side
+    }
+
+    fn fds_bios() -> Vec<u8> {
+        let mut bios = vec![0; 0x2000];
+        // Turns the frame IRQ off, starts the drive reading with IRQs on and waits
+        bios[..0x13].copy_from_slice(&[
+            0xa9, 0x40, 0x8d, 0x17, 0x40, 0xa9, 0x01, 0x8d, 0x23, 0x40, 0xa9, 0xc5, 0x8d, 0x25,
+            0x40, 0x58, 0x4c, 0x0f, 0xe0,
+        ]);
+        // IRQ: copies the byte read to $0200 + [$00], stopping the motor after 16
+        bios[0x100..0x114].copy_from_slice(&[
+            0xa6, 0x00, 0xad, 0x31, 0x40, 0x9d, 0x00, 0x02, 0xe6, 0x00, 0xe0, 0x0f, 0xd0, 0x05,
+            0xa9, 0x00, 0x8d, 0x25, 0x40, 0x40,
+        ]);
+        bios[0x1ffa..].copy_from_slice(&[0x13, 0xe1, 0x00, 0xe0, 0x00, 0xe1]);
+        bios
+    }
+
+    #[test]
+    fn fds_disk_drive() {
+        let mut image = b"FDS\x1a\x02".to_vec();
+        image.resize(16, 0);
+        let sides = [fds_side(0xaa), fds_side(0xbb)];
+        image.extend(&sides[0]);
+        image.resize(16 + SIDE_SIZE, 0);
+        // The end of the last side is often cut off
+        image.extend(&sides[1]);
+        let disk = FdsImage::from_bytes(&image).unwrap();
+        assert_eq!(disk.sides.len(), 2);
+        assert!(FdsImage::from_bytes(b"FDS\x1a").is_err());
+        assert!(NESFile::from_fds(disk.clone(), vec![0; 0x4000]).is_err());
+        // Hundreds of small files fit in the image, but not once the gaps between them are back
+        let mut crowded = fds_side(0xaa);
+        for _ in 0..300 {
+            crowded.extend_from_within(58..79);
+        }
+        assert!(crowded.len() < SIDE_SIZE);
+        let crowded = FdsImage::from_bytes(&crowded).unwrap();
+        assert!(NESFile::from_fds(crowded, fds_bios()).is_err());
+
+        let mut emulator = Emulator::new(NESFile::from_fds(disk, fds_bios()).unwrap());
+        assert_eq!((emulator.disk_sides(), emulator.disk_side()), (2, Some(0)));
+        (0..30).for_each(|_| emulator.step_frame());
+        let bus = &emulator.cpu().bus;
+        let read = (0x200..0x210)
+            .map(|addr| bus.peek(addr))
+            .collect::<Vec<_>>();
+        assert_eq!(read, sides[0][..16]);
+        // The motor is off, so the head is back at the start
+        assert_eq!(bus.peek(0x4030) & 0x40, 0x40);
+
+        // The disk is written back as a plain image, with changes kept
+        let mut save = emulator.battery_save();
+        let raw = sides
+            .iter()
+            .flat_map(|side| {
+                let mut side = side.clone();
+                side.resize(SIDE_SIZE, 0);
+                side
+            })
+            .collect::<Vec<_>>();
+        assert!(save == raw, "the disk didn't come back out as it went in");
+        save[SIDE_SIZE + 75] = 0xcc;
+        emulator.load_battery_save(&save).unwrap();
+        assert!(emulator.battery_save() == save);
+        assert!(emulator.load_battery_save(&save[..SIDE_SIZE]).is_err());
+        assert!(emulator.battery_save() == save);
+
+        // Swapping sides leaves the drive empty for a while first
+        emulator.insert_disk(Some(1));
+        assert_eq!(emulator.disk_side(), None);
+        assert_eq!(emulator.cpu().bus.peek(0x4032) & 0x05, 0x05);
+        (0..70).for_each(|_| emulator.step_frame());
+        assert_eq!(emulator.disk_side(), Some(1));
+        assert_eq!(emulator.cpu().bus.peek(0x4032) & 0x05, 0);
+        emulator.insert_disk(None);
+        assert_eq!(emulator.disk_side(), None);
+    }
+
+    #[test]
+    fn fds_timer_irq() {
+        let disk = FdsImage::from_bytes(&fds_side(0)).unwrap();
+        let mut mapper = MapperFactory::from_file(&NESFile::from_fds(disk, fds_bios()).unwrap());
+        // Does nothing until the disk registers are enabled
+        mapper.write(0x4020, 99);
+        mapper.write(0x4022, 0x03);
+        (0..200).for_each(|_| mapper.tick());
+        assert!(!mapper.irq());
+
+        mapper.write(0x4023, 0x01);
+        mapper.write(0x4022, 0x03);
+        let mut irqs = 0;
+        for _ in 0..1000 {
+            mapper.tick();
+            if mapper.irq() {
+                irqs += 1;
+                assert_eq!(mapper.read_expansion_area(0x4030).unwrap() & 0x01, 0x01);
+            }
+        }
+        assert_eq!(irqs, 10);
+
+        // Without repeat it fires once
+        mapper.write(0x4022, 0x02);
+        let irqs = (0..1000)
+            .filter(|_| {
+                mapper.tick();
+                mapper.irq() && mapper.read_expansion_area(0x4030).is_some()
+            })
+            .count();
+        assert_eq!(irqs, 1);
+    }
+
+    #[test]
+    fn emulation_speed() {
+        let mut speed = EmulationSpeed::new();
+        assert_eq!(speed.multiplier(), 1.0);
+        assert!(!speed.should_mute());
+        let frame = speed.frame_duration().as_secs_f64();
+        assert!((frame - 1. / NTSC_FRAME_RATE).abs() < 1e-9);
+
+        speed.faster();
+        speed.faster();
+        assert_eq!(speed.multiplier(), 2.0);
+        assert!(speed.should_mute());
+        assert!((speed.frame_duration().as_secs_f64() - frame / 2.).abs() < 1e-9);
+
+        // Clamped at both ends of the steps
+        (0..SPEED_STEPS.len() + 2).for_each(|_| speed.faster());
+        assert_eq!(speed.multiplier(), SPEED_STEPS[SPEED_STEPS.len() - 1]);
+        (0..SPEED_STEPS.len() + 2).for_each(|_| speed.slower());
+        assert_eq!(speed.multiplier(), SPEED_STEPS[0]);
+        assert!(speed.should_mute());
+
+        speed.reset();
+        assert!(!speed.should_mute());
+        speed.turbo = true;
+        assert!(speed.should_mute());
+        speed.turbo = false;
+        // Pausing doesn't change the speed, so doesn't mute by itself
+        speed.paused = true;
+        assert!(!speed.should_mute());
+    }
 
     // PPU Tests -----------------------------------------------------------------------------------
     // let rom = File::new("tests/stress/NEStress.NES"); // ??